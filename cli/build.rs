@@ -0,0 +1,10 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // so building doesn't need protoc installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    // the service definition lives with the server that answers it
+    let proto = "../example-databases/rust/null-server/proto";
+    tonic_build::configure()
+        .build_server(false)
+        .compile(&[format!("{}/null.proto", proto)], &[proto])?;
+    Ok(())
+}
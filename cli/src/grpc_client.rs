@@ -0,0 +1,13 @@
+pub mod proto {
+    tonic::include_proto!("null");
+}
+
+use proto::key_value_client::KeyValueClient;
+use tonic::transport::Channel;
+
+// null-server's default --grpc-port
+pub const PORT: u16 = 50051;
+
+pub async fn connect(host: &str) -> Result<KeyValueClient<Channel>, tonic::transport::Error> {
+    KeyValueClient::connect(format!("http://{}:{}", host, PORT)).await
+}
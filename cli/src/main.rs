@@ -11,7 +11,9 @@ use rand::Rng;
 use rand::prelude::*;
 use rand::{thread_rng};
 use rand::distributions::{Alphanumeric, Uniform, Standard};
+use tokio::io::{AsyncBufReadExt, BufReader};
 
+mod grpc_client;
 mod null_client;
 
 #[derive(Parser)]
@@ -44,6 +46,57 @@ enum Commands {
         host: String,
     },
 
+    /// Take an online backup into a directory on the server
+    Backup {
+        dir: String,
+        #[clap(long, default_value = "localhost")]
+        host: String,
+    },
+
+    /// Stream every live record out as JSON Lines
+    Export {
+        /// Write to this file instead of stdout
+        #[clap(long)]
+        out: Option<String>,
+        #[clap(long, default_value = "localhost")]
+        host: String,
+    },
+
+    /// Load a JSON Lines export into a server, whatever engine it runs
+    Import {
+        file: String,
+        #[clap(long, default_value = "localhost")]
+        host: String,
+    },
+
+    /// Stream every live record whose key starts with prefix, over gRPC
+    Scan {
+        #[clap(default_value = "")]
+        prefix: String,
+        #[clap(long, default_value = "localhost")]
+        host: String,
+    },
+
+    /// Print every change to a key starting with prefix as it happens, over gRPC
+    Watch {
+        #[clap(default_value = "")]
+        prefix: String,
+        #[clap(long, default_value = "localhost")]
+        host: String,
+    },
+
+    /// Write several records at once over gRPC, all or none of them
+    Batch {
+        /// key=value to put
+        #[clap(long)]
+        put: Vec<String>,
+        /// key to delete
+        #[clap(long)]
+        delete: Vec<String>,
+        #[clap(long, default_value = "localhost")]
+        host: String,
+    },
+
     Bench {
         #[clap(long, default_value_t = 100)]
         records: i32,
@@ -103,6 +156,98 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .await?;
         }
 
+        Commands::Backup { dir, host } => {
+            println!("backing up to {}", dir);
+            let client = reqwest::Client::new();
+            let resp = client.post(format!("http://{}:8080/_admin/backup", host))
+                .query(&[("dir", dir)])
+                .send()
+                .await?
+                .text()
+                .await?;
+            println!("{}", resp)
+        }
+
+        Commands::Export { out, host } => {
+            let mut resp = reqwest::get(format!("http://{}:8080/_export", host))
+                .await?
+                .error_for_status()?;
+            let mut to: Box<dyn std::io::Write> = match out {
+                Some(path) => Box::new(std::fs::File::create(path)?),
+                None => Box::new(std::io::stdout()),
+            };
+            while let Some(chunk) = resp.chunk().await? {
+                to.write_all(&chunk)?;
+            }
+            to.flush()?;
+        }
+
+        Commands::Import { file, host } => {
+            println!("importing {}", file);
+            // sent a line at a time, so an export bigger than memory still goes in
+            let lines = BufReader::new(tokio::fs::File::open(&file).await?).lines();
+            let body = futures_util::stream::try_unfold(lines, |mut lines| async move {
+                Ok::<_, std::io::Error>(lines.next_line().await?.map(|line| (line + "\n", lines)))
+            });
+            let client = reqwest::Client::new();
+            let resp = client.post(format!("http://{}:8080/_import", host))
+                .body(reqwest::Body::wrap_stream(body))
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            println!("{}", resp)
+        }
+
+        Commands::Scan { prefix, host } => {
+            let mut client = grpc_client::connect(host).await?;
+            let mut records = client
+                .scan(grpc_client::proto::ScanRequest { prefix: prefix.clone() })
+                .await?
+                .into_inner();
+            while let Some(record) = records.message().await? {
+                println!("{}:{}", record.key, record.value);
+            }
+        }
+
+        Commands::Watch { prefix, host } => {
+            let mut client = grpc_client::connect(host).await?;
+            let mut changes = client
+                .watch(grpc_client::proto::WatchRequest { prefix: prefix.clone() })
+                .await?
+                .into_inner();
+            while let Some(change) = changes.message().await? {
+                if change.deleted {
+                    println!("deleted {}", change.key);
+                } else {
+                    println!("put {}:{}", change.key, change.value);
+                }
+            }
+        }
+
+        Commands::Batch { put, delete, host } => {
+            let mut writes = Vec::new();
+            for record in put {
+                let (key, value) = record.split_once('=').ok_or("--put takes key=value")?;
+                writes.push(grpc_client::proto::Write {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    delete: false,
+                });
+            }
+            for key in delete {
+                writes.push(grpc_client::proto::Write {
+                    key: key.clone(),
+                    delete: true,
+                    ..Default::default()
+                });
+            }
+            println!("writing {} records", writes.len());
+            let mut client = grpc_client::connect(host).await?;
+            client.batch(grpc_client::proto::BatchRequest { writes }).await?;
+        }
+
         Commands::Bench {records,duration,host} => {
             println!("benchmarking database");
             benchmark(*records,*duration,host.to_string());
@@ -0,0 +1,8 @@
+//! The map behind all-memory-kv and the memcached listener that serves it,
+//! shared with its HTTP and RESP front ends in main.rs.
+#[macro_use]
+extern crate lazy_static;
+
+pub mod memcache;
+pub mod metrics;
+pub mod store;
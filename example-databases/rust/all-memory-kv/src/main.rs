@@ -1,23 +1,36 @@
 use actix_web::{get, post,delete, web::{self, Data}, App, Responder,HttpResponse,HttpServer};
-#[macro_use]
-extern crate lazy_static;
 use std::collections::HashMap;
-use std::sync::RwLock; // read heavy -- probably better period.
+use std::sync::Arc;
+use store::Entry;
+use null_common::error::{self, DbError};
+use null_common::{jsonl, logging, resp};
+use all_memory_kv::{memcache, metrics, store};
 
+// what request logs call this server
+const ENGINE: &str = "all-memory-kv";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    logging::init();
     let mut data = Data::new({
         let mut m = HashMap::new();
         // Pre-fill the db with some values
-        m.insert("foo".to_owned(), "foo".to_owned());
-        m.insert("bar".to_owned(), "baz".to_owned());
-        m.insert("bax".to_owned(), "baz".to_owned());
-        RwLock::new(m)
+        m.insert("foo".to_owned(), Entry::plain("foo".to_owned()));
+        m.insert("bar".to_owned(), Entry::plain("baz".to_owned()));
+        m.insert("bax".to_owned(), Entry::plain("baz".to_owned()));
+        store::Db::new(m)
     });
+    resp::start(6379, Arc::new(RespStore(data.clone().into_inner())), "memory")?;
+    memcache::start(11211, data.clone().into_inner())?;
     HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
+            .app_data(web::PayloadConfig::new(error::MAX_BODY_BYTES))
+            .wrap_fn(|req, srv| logging::trace_request(ENGINE, req, srv))
+            // ahead of /{key}, which would take these for keys
+            .service(metrics::get_metrics)
+            .service(export_records)
+            .service(import_records)
             .service(get_value_for_key)
             .service(put_value_for_key)
             .service(delete_value_for_key)
@@ -29,30 +42,107 @@ async fn main() -> std::io::Result<()> {
 
 #[get("/{key}")]
 pub async fn get_value_for_key(
-    data: Data<RwLock<HashMap<String, String>>>,
+    data: Data<store::Db>,
     web::Path(key): web::Path<String>
-) -> impl Responder {
+) -> Result<HttpResponse, DbError> {
     //Get the key!
     let map = data.read().unwrap();
-    HttpResponse::Ok().body(map.get(&key).unwrap())
+    match store::get(&map, &key) {
+        Some(entry) => Ok(HttpResponse::Ok().body(entry.value)),
+        None => Err(DbError::NotFound(key)),
+    }
 }
 
 #[post("/{key}")]
 pub async fn put_value_for_key(
-    data: Data<RwLock<HashMap<String, String>>>,
+    data: Data<store::Db>,
     web::Path(key): web::Path<String>,req_body: String
-) -> impl Responder {
+) -> Result<HttpResponse, DbError> {
+    check_value(&req_body)?;
     let mut map = data.write().unwrap();
-    map.insert(key,req_body);
-    HttpResponse::Ok().body("It is saved... in memory!")
+    metrics::BYTES_WRITTEN.inc_by((key.len() + req_body.len()) as u64);
+    map.insert(key, Entry::plain(req_body));
+    Ok(HttpResponse::Ok().body("It is saved... in memory!"))
+}
+
+// The map as the RESP listener sees it, which lives in null-common and so
+// can't be handed the map itself.
+struct RespStore(Arc<store::Db>);
+
+impl resp::Store for RespStore {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(store::get(&self.0.read().unwrap(), key).map(|entry| entry.value))
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        let mut map = self.0.write().unwrap();
+        for (key, value) in batch {
+            match value {
+                Some(value) => {
+                    metrics::BYTES_WRITTEN.inc_by((key.len() + value.len()) as u64);
+                    map.insert(key.clone(), Entry::plain(value.clone()))
+                }
+                None => map.remove(key),
+            };
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>, DbError> {
+        Ok(store::records(&self.0.read().unwrap()).into_iter().map(|(key, _)| key).collect())
+    }
+
+    fn check(&self, _key: &str, value: &str) -> Result<(), DbError> {
+        check_value(value)
+    }
+}
+
+fn check_value(value: &str) -> Result<(), DbError> {
+    if value.len() > error::MAX_VALUE_BYTES {
+        return Err(DbError::TooLarge { size: value.len(), limit: error::MAX_VALUE_BYTES });
+    }
+    Ok(())
 }
 
 #[delete("/{key}")]
 pub async fn delete_value_for_key(
-    data: Data<RwLock<HashMap<String, String>>>,
+    data: Data<store::Db>,
     web::Path(key): web::Path<String>
 ) -> impl Responder {
     let mut map = data.write().unwrap();
     map.remove(&key);
     HttpResponse::Ok().body("It has been deleted!")
-}
\ No newline at end of file
+}
+
+#[get("/_export")]
+pub async fn export_records(
+    data: Data<store::Db>
+) -> impl Responder {
+    // copy out so writers aren't held up while the export streams
+    let records = store::records(&data.read().unwrap());
+    jsonl::export(records.into_iter())
+}
+
+#[post("/_import")]
+pub async fn import_records(
+    data: Data<store::Db>,
+    body: web::Payload
+) -> impl Responder {
+    let mut lines = jsonl::Lines::new(body);
+    let mut imported = 0;
+    while let Some(line) = lines.next().await {
+        let record = line.map_err(|e| e.to_string()).and_then(|line| jsonl::decode(&line)).and_then(|(key, value)| {
+            check_value(&value).map_err(|e| e.to_string())?;
+            Ok((key, value))
+        });
+        match record {
+            Ok((key, value)) => {
+                metrics::BYTES_WRITTEN.inc_by((key.len() + value.len()) as u64);
+                data.write().unwrap().insert(key, Entry::plain(value));
+                imported += 1;
+            }
+            Err(e) => return jsonl::import_result(imported, Some((lines.line_number, e))),
+        }
+    }
+    jsonl::import_result(imported, None)
+}
@@ -0,0 +1,255 @@
+// A memcached text protocol listener, so apps that speak memcached can use the
+// store as a cache: get, gets, set, add, replace, cas, delete, incr, decr and
+// touch, with flags and exptime. Values are text like everywhere else in the
+// store, so data blocks that aren't UTF-8 are turned away.
+use null_common::error::MAX_VALUE_BYTES;
+use crate::metrics;
+use crate::store::{self, Db, Entry};
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, info_span};
+
+// memcached's own limits on a key and on a command line
+const MAX_KEY_BYTES: usize = 250;
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+// exptimes up to this many seconds are relative, anything bigger is a unix time
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+// how often expired entries are dropped when nobody reads them
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+
+const BAD_FORMAT: &str = "CLIENT_ERROR bad command line format";
+
+// Binds the listener, then answers every connection on its own thread.
+pub fn start(port: u16, db: Arc<Db>) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    info!(port, "memcached listening");
+
+    let sweeper = db.clone();
+    thread::spawn(move || loop {
+        thread::sleep(SWEEP_EVERY);
+        let swept = store::sweep(&mut sweeper.write().unwrap());
+        if swept > 0 {
+            debug!(keys = swept, "dropped expired keys");
+        }
+    });
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!(error = %e, "couldn't accept memcached connection");
+                    continue;
+                }
+            };
+            let db = db.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                let span = info_span!("memcached", peer = %peer);
+                let _enter = span.enter();
+                if let Err(e) = serve(&db, stream) {
+                    debug!(error = %e, "memcached connection closed");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn serve(db: &Db, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream);
+    loop {
+        // answer pipelined commands together, before waiting on the client
+        if reader.buffer().is_empty() {
+            out.flush()?;
+        }
+        let mut line = Vec::new();
+        if reader.by_ref().take(MAX_LINE_BYTES).read_until(b'\n', &mut line)? == 0 {
+            return out.flush();
+        }
+        if !line.ends_with(b"\n") {
+            out.write_all(b"CLIENT_ERROR line too long\r\n")?;
+            return out.flush();
+        }
+        let line = String::from_utf8_lossy(&line);
+        let args = line.split_whitespace().collect::<Vec<&str>>();
+        if args.is_empty() {
+            continue;
+        }
+        debug!(command = args[0], "memcached command");
+
+        let noreply = args.len() > 1 && args[args.len() - 1] == "noreply";
+        let reply = match args[0] {
+            "get" | "gets" if args.len() > 1 => retrieve(db, &args[1..], args[0] == "gets"),
+            "set" | "add" | "replace" | "cas" => storage(db, &args, &mut reader)?,
+            "delete" => delete(db, &args),
+            "incr" | "decr" => incr_decr(db, &args),
+            "touch" => touch(db, &args),
+            "version" => format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")),
+            "quit" => return out.flush(),
+            _ => "ERROR\r\n".to_string(),
+        };
+        metrics::observe_memcache_command(args[0], !failed(&reply));
+        if !noreply {
+            out.write_all(reply.as_bytes())?;
+        }
+    }
+}
+
+// get <key>* and gets <key>*, which adds each value's cas unique.
+fn retrieve(db: &Db, keys: &[&str], with_cas: bool) -> String {
+    let map = db.read().unwrap();
+    let mut reply = String::new();
+    for key in keys {
+        if let Some(entry) = store::get(&map, key) {
+            reply.push_str(&format!("VALUE {} {} {}", key, entry.flags, entry.value.len()));
+            if with_cas {
+                reply.push_str(&format!(" {}", entry.cas));
+            }
+            reply.push_str(&format!("\r\n{}\r\n", entry.value));
+        }
+    }
+    reply + "END\r\n"
+}
+
+// <command> <key> <flags> <exptime> <bytes> [cas unique] [noreply], followed
+// by a data block of bytes bytes.
+fn storage(db: &Db, args: &[&str], reader: &mut impl BufRead) -> io::Result<String> {
+    let command = args[0];
+    let fields = if command == "cas" { 6 } else { 5 };
+    let bytes = match args.get(4).and_then(|b| b.parse::<usize>().ok()) {
+        Some(bytes) => bytes,
+        // no way to know how much data follows, so it will be read as commands
+        None => return Ok(format!("{}\r\n", BAD_FORMAT)),
+    };
+
+    // the data block is read whatever happens, so the next command starts
+    // where it should
+    if bytes > MAX_VALUE_BYTES {
+        io::copy(&mut reader.take(bytes as u64 + 2), &mut io::sink())?;
+        return Ok("SERVER_ERROR object too large for cache\r\n".to_string());
+    }
+    let mut data = vec![0; bytes + 2];
+    reader.read_exact(&mut data)?;
+    if !data.ends_with(b"\r\n") {
+        return Ok("CLIENT_ERROR bad data chunk\r\n".to_string());
+    }
+    data.truncate(bytes);
+
+    let key = args[1];
+    let shaped = args.len() == fields || (args.len() == fields + 1 && args[fields] == "noreply");
+    let parsed = (
+        args.get(2).and_then(|f| f.parse::<u32>().ok()),
+        args.get(3).and_then(|e| e.parse::<i64>().ok()),
+        if command == "cas" { args.get(5).and_then(|c| c.parse::<u64>().ok()) } else { Some(0) },
+    );
+    let (flags, exptime, unique) = match parsed {
+        (Some(flags), Some(exptime), Some(unique)) if shaped && valid_key(key) => (flags, exptime, unique),
+        _ => return Ok(format!("{}\r\n", BAD_FORMAT)),
+    };
+    let value = match String::from_utf8(data) {
+        Ok(value) => value,
+        Err(_) => return Ok("CLIENT_ERROR values must be UTF-8 text\r\n".to_string()),
+    };
+
+    let mut map = db.write().unwrap();
+    let current = store::get(&map, key);
+    let stored = match (command, current) {
+        ("add", Some(_)) | ("replace", None) => return Ok("NOT_STORED\r\n".to_string()),
+        ("cas", None) => return Ok("NOT_FOUND\r\n".to_string()),
+        ("cas", Some(entry)) if entry.cas != unique => return Ok("EXISTS\r\n".to_string()),
+        _ => Entry::new(value, flags, expires_at(exptime)),
+    };
+    metrics::BYTES_WRITTEN.inc_by((key.len() + stored.value.len()) as u64);
+    map.insert(key.to_string(), stored);
+    Ok("STORED\r\n".to_string())
+}
+
+// delete <key> [noreply]. Old clients send a time of 0 after the key.
+fn delete(db: &Db, args: &[&str]) -> String {
+    if args.len() < 2 || args[2..].iter().any(|a| *a != "0" && *a != "noreply") {
+        return format!("{}\r\n", BAD_FORMAT);
+    }
+    let mut map = db.write().unwrap();
+    let live = store::get(&map, args[1]).is_some();
+    map.remove(args[1]);
+    if live {
+        "DELETED\r\n".to_string()
+    } else {
+        "NOT_FOUND\r\n".to_string()
+    }
+}
+
+// incr <key> <delta> [noreply] and decr. incr wraps at 64 bits and decr stops
+// at 0, as memcached does.
+fn incr_decr(db: &Db, args: &[&str]) -> String {
+    if args.len() < 3 {
+        return "ERROR\r\n".to_string();
+    }
+    let delta = match args[2].parse::<u64>() {
+        Ok(delta) => delta,
+        Err(_) => return "CLIENT_ERROR invalid numeric delta argument\r\n".to_string(),
+    };
+    let mut map = db.write().unwrap();
+    let entry = match store::get(&map, args[1]) {
+        Some(entry) => entry,
+        None => return "NOT_FOUND\r\n".to_string(),
+    };
+    let n = match entry.value.parse::<u64>() {
+        Ok(n) if args[0] == "incr" => n.wrapping_add(delta),
+        Ok(n) => n.saturating_sub(delta),
+        Err(_) => {
+            return "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n".to_string()
+        }
+    };
+    map.insert(args[1].to_string(), Entry::new(n.to_string(), entry.flags, entry.expires));
+    format!("{}\r\n", n)
+}
+
+// touch <key> <exptime> [noreply] gives a key a new exptime without a write.
+fn touch(db: &Db, args: &[&str]) -> String {
+    let exptime = match args.get(2).and_then(|e| e.parse::<i64>().ok()) {
+        Some(exptime) => exptime,
+        None => return "ERROR\r\n".to_string(),
+    };
+    let mut map = db.write().unwrap();
+    if store::get(&map, args[1]).is_none() {
+        return "NOT_FOUND\r\n".to_string();
+    }
+    if let Some(entry) = map.get_mut(args[1]) {
+        entry.expires = expires_at(exptime);
+    }
+    "TOUCHED\r\n".to_string()
+}
+
+// 0 never expires, up to 30 days is seconds from now, anything bigger is a
+// unix time, and a negative exptime has already passed.
+fn expires_at(exptime: i64) -> Option<Instant> {
+    let now = Instant::now();
+    let seconds = match exptime {
+        0 => return None,
+        e if e < 0 => return Some(now),
+        e if e <= MAX_RELATIVE_EXPTIME => e as u64,
+        e => {
+            let unix_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+            if e <= unix_now {
+                return Some(now);
+            }
+            (e - unix_now) as u64
+        }
+    };
+    Some(now + Duration::from_secs(seconds))
+}
+
+fn failed(reply: &str) -> bool {
+    reply.starts_with("ERROR") || reply.starts_with("CLIENT_ERROR") || reply.starts_with("SERVER_ERROR")
+}
+
+fn valid_key(key: &str) -> bool {
+    key.len() <= MAX_KEY_BYTES && !key.chars().any(|c| c.is_control())
+}
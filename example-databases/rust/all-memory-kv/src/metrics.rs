@@ -0,0 +1,55 @@
+use actix_web::{get, web::Data, Responder};
+use null_common::metrics::render;
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, IntCounter, IntCounterVec,
+    IntGauge,
+};
+
+lazy_static! {
+    static ref MEMCACHE_COMMANDS: IntCounterVec = register_int_counter_vec!(
+        "null_memcache_commands_total",
+        "memcached commands answered, by command and whether they succeeded",
+        &["command", "result"]
+    )
+    .unwrap();
+    pub static ref BYTES_WRITTEN: IntCounter = register_int_counter!(
+        "null_bytes_written_total",
+        "Bytes of keys and values stored"
+    )
+    .unwrap();
+    static ref INDEX_KEYS: IntGauge = register_int_gauge!(
+        "null_index_keys",
+        "Keys held in the in-memory index"
+    )
+    .unwrap();
+    static ref INDEX_BYTES: IntGauge = register_int_gauge!(
+        "null_index_bytes",
+        "Bytes of keys and values held in the in-memory index"
+    )
+    .unwrap();
+}
+
+pub fn observe_memcache_command(command: &str, ok: bool) {
+    // anything a client sends would otherwise become a label
+    let command = match command {
+        "get" | "gets" | "set" | "add" | "replace" | "cas" | "delete" | "incr" | "decr" | "touch"
+        | "version" => command,
+        _ => "unknown",
+    };
+    MEMCACHE_COMMANDS
+        .with_label_values(&[command, if ok { "ok" } else { "error" }])
+        .inc();
+}
+
+#[get("/metrics")]
+pub async fn get_metrics(
+    data: Data<crate::store::Db>
+) -> impl Responder {
+    {
+        let records = crate::store::records(&data.read().unwrap());
+        INDEX_KEYS.set(records.len() as i64);
+        INDEX_BYTES.set(records.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>() as i64);
+    }
+
+    render()
+}
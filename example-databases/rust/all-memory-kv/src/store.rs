@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock; // read heavy -- probably better period.
+use std::time::Instant;
+
+// Everything every API shares. Keys written over HTTP or RESP get flags of 0
+// and never expire; memcached clients can set both.
+pub type Db = RwLock<HashMap<String, Entry>>;
+
+// memcached's cas unique, bumped on every write so a client can tell whether a
+// value changed since it read it
+static NEXT_CAS: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone)]
+pub struct Entry {
+    pub value: String,
+    pub flags: u32,
+    pub cas: u64,
+    pub expires: Option<Instant>,
+}
+
+impl Entry {
+    pub fn new(value: String, flags: u32, expires: Option<Instant>) -> Self {
+        Entry {
+            value,
+            flags,
+            cas: NEXT_CAS.fetch_add(1, Ordering::Relaxed),
+            expires,
+        }
+    }
+
+    // A plain value, the way HTTP and RESP write them.
+    pub fn plain(value: String) -> Self {
+        Entry::new(value, 0, None)
+    }
+
+    // Expired entries stay in the map until they are swept or overwritten,
+    // so every read has to ask.
+    pub fn live(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+// The value for key, unless it was never written, was deleted or has expired.
+pub fn get(map: &HashMap<String, Entry>, key: &str) -> Option<Entry> {
+    map.get(key).filter(|entry| entry.live(Instant::now())).cloned()
+}
+
+// Every live key and value.
+pub fn records(map: &HashMap<String, Entry>) -> Vec<(String, String)> {
+    let now = Instant::now();
+    map.iter()
+        .filter(|(_, entry)| entry.live(now))
+        .map(|(key, entry)| (key.clone(), entry.value.clone()))
+        .collect()
+}
+
+// Drops every expired entry.
+pub fn sweep(map: &mut HashMap<String, Entry>) -> usize {
+    let now = Instant::now();
+    let before = map.len();
+    map.retain(|_, entry| entry.live(now));
+    before - map.len()
+}
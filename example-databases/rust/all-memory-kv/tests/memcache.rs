@@ -0,0 +1,97 @@
+use all_memory_kv::{memcache, store};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Client {
+    // A listener over an empty store, on a port of its own.
+    fn start() -> Self {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        memcache::start(port, Arc::new(store::Db::new(HashMap::new()))).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        Client { reader: BufReader::new(stream.try_clone().unwrap()), stream }
+    }
+
+    // Sends text and returns the reply, VALUE lines and their data included.
+    fn call(&mut self, text: &str) -> String {
+        self.stream.write_all(text.as_bytes()).unwrap();
+        let mut reply = String::new();
+        loop {
+            let start = reply.len();
+            self.reader.read_line(&mut reply).unwrap();
+            if !reply[start..].starts_with("VALUE") {
+                return reply;
+            }
+            self.reader.read_line(&mut reply).unwrap();
+        }
+    }
+}
+
+#[test]
+fn set_get_and_delete() {
+    let mut client = Client::start();
+    assert_eq!(client.call("get foo\r\n"), "END\r\n");
+    assert_eq!(client.call("set foo 5 0 3\r\nbar\r\n"), "STORED\r\n");
+    assert_eq!(client.call("get foo missing\r\n"), "VALUE foo 5 3\r\nbar\r\nEND\r\n");
+    assert_eq!(client.call("add foo 0 0 1\r\nx\r\n"), "NOT_STORED\r\n");
+    assert_eq!(client.call("replace nope 0 0 1\r\nx\r\n"), "NOT_STORED\r\n");
+    assert_eq!(client.call("delete foo\r\n"), "DELETED\r\n");
+    assert_eq!(client.call("delete foo\r\n"), "NOT_FOUND\r\n");
+
+    // noreply answers nothing, so the next reply is the version's
+    assert!(client.call("set quiet 0 0 1 noreply\r\nq\r\nversion\r\n").starts_with("VERSION "));
+    assert_eq!(client.call("get quiet\r\n"), "VALUE quiet 0 1\r\nq\r\nEND\r\n");
+    assert_eq!(client.call("set bad 0 0 nope\r\n"), "CLIENT_ERROR bad command line format\r\n");
+    assert_eq!(client.call("bogus\r\n"), "ERROR\r\n");
+}
+
+#[test]
+fn cas_only_writes_over_what_was_read() {
+    let mut client = Client::start();
+    assert_eq!(client.call("cas k 0 0 1 1\r\nx\r\n"), "NOT_FOUND\r\n");
+    client.call("set k 0 0 2\r\nv1\r\n");
+    let reply = client.call("gets k\r\n");
+    let unique: u64 = reply.lines().next().unwrap().rsplit(' ').next().unwrap().parse().unwrap();
+
+    assert_eq!(client.call(&format!("cas k 0 0 2 {}\r\nv2\r\n", unique)), "STORED\r\n");
+    // the write bumped the unique, so the same one is now stale
+    assert_eq!(client.call(&format!("cas k 0 0 2 {}\r\nv3\r\n", unique)), "EXISTS\r\n");
+    assert_eq!(client.call("get k\r\n"), "VALUE k 0 2\r\nv2\r\nEND\r\n");
+}
+
+#[test]
+fn incr_and_decr() {
+    let mut client = Client::start();
+    assert_eq!(client.call("incr n 1\r\n"), "NOT_FOUND\r\n");
+    client.call("set n 0 0 2\r\n10\r\n");
+    assert_eq!(client.call("incr n 5\r\n"), "15\r\n");
+    assert_eq!(client.call("decr n 100\r\n"), "0\r\n");
+    client.call(&format!("set max 0 0 20\r\n{}\r\n", u64::MAX));
+    assert_eq!(client.call("incr max 2\r\n"), "1\r\n");
+    client.call("set word 0 0 3\r\nabc\r\n");
+    assert_eq!(client.call("incr word 1\r\n"), "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n");
+}
+
+#[test]
+fn keys_expire() {
+    let mut client = Client::start();
+    client.call("set gone 0 -1 1\r\nx\r\n");
+    assert_eq!(client.call("get gone\r\n"), "END\r\n");
+
+    client.call("set soon 0 1 1\r\nx\r\n");
+    client.call("set kept 0 1 1\r\nx\r\n");
+    assert_eq!(client.call("touch kept 0\r\n"), "TOUCHED\r\n");
+    assert_eq!(client.call("get soon\r\n"), "VALUE soon 0 1\r\nx\r\nEND\r\n");
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(client.call("get soon kept\r\n"), "VALUE kept 0 1\r\nx\r\nEND\r\n");
+    assert_eq!(client.call("touch soon 10\r\n"), "NOT_FOUND\r\n");
+}
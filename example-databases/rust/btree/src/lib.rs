@@ -0,0 +1,226 @@
+//! A B+tree in one file of 4 KiB pages: a header page, then internal nodes
+//! and leaves (see page.rs for how each is laid out). A lookup reads one page
+//! per level from the root down to a leaf. Writes change pages in place,
+//! splitting nodes that fill up and merging ones that empty out (see tree.rs).
+//! Pages are cached in a buffer pool of a fixed number of frames, and changed
+//! pages are written back when their frame is reused or on flush (see
+//! pool.rs).
+//!
+//! ```no_run
+//! let db = disk_btree::open("/tmp/null", disk_btree::Options::default())?;
+//! db.put("foo", "bar")?;
+//! assert_eq!(db.get("foo")?, Some("bar".to_string()));
+//! db.close()?;
+//! # Ok::<(), disk_btree::Error>(())
+//! ```
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use null_common::error::DbError;
+
+mod page;
+mod pager;
+mod pool;
+mod tree;
+
+use page::{MAX_KEY_BYTES, MAX_RECORD_BYTES, PAGE_SIZE};
+pub use pool::PoolStats;
+use tree::Tree;
+
+// The one file the database lives in, inside the directory it is opened at.
+pub const FILE: &str = "null.btree";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // a page on disk we can't make sense of
+    Corrupt(String),
+    // a key or value we can't store as given
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// For callers that only deal in io::Error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Corrupt(msg) => io::Error::new(io::ErrorKind::InvalidData, msg),
+            Error::Invalid(msg) => io::Error::new(io::ErrorKind::InvalidInput, msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => Error::Corrupt(e.to_string()),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+// Lets the servers hand our errors straight back as HTTP responses.
+impl From<Error> for DbError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e.into(),
+            Error::Corrupt(msg) => DbError::Corrupt(msg),
+            Error::Invalid(msg) => DbError::Invalid(msg),
+        }
+    }
+}
+
+pub struct Options {
+    // write out changed pages and fsync the file after every write rather
+    // than only on flush
+    pub sync_writes: bool,
+    // how many pages the buffer pool holds in memory, at least 1
+    pub pool_pages: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            sync_writes: false,
+            pool_pages: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub keys: u64,
+    // pages in the file, the header and free pages included
+    pub pages: u64,
+    // pages freed by merges, waiting to be reused
+    pub free_pages: u64,
+    // levels from the root to the leaves, 1 while the root is a leaf
+    pub depth: usize,
+    pub file_bytes: u64,
+    pub pool: PoolStats,
+}
+
+pub struct Db {
+    path: PathBuf,
+    options: Options,
+    tree: RwLock<Tree>,
+}
+
+// Opens the database kept in dir, creating it if needed.
+pub fn open<P: AsRef<Path>>(dir: P, options: Options) -> Result<Db, Error> {
+    std::fs::create_dir_all(dir.as_ref())?;
+    let path = dir.as_ref().join(FILE);
+    let tree = Tree::open(&path, options.pool_pages)?;
+    Ok(Db {
+        path,
+        options,
+        tree: RwLock::new(tree),
+    })
+}
+
+impl Db {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // None when the key was never written or has been deleted.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let tree = self.tree.read().unwrap();
+        match tree.get(key.as_bytes())? {
+            Some(value) => Ok(Some(utf8(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        check_record(key, value)?;
+        let mut tree = self.tree.write().unwrap();
+        tree.put(key.as_bytes(), value.as_bytes())?;
+        self.written(&tree)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        check_record(key, "")?;
+        let mut tree = self.tree.write().unwrap();
+        if tree.delete(key.as_bytes())? {
+            self.written(&tree)?;
+        }
+        Ok(())
+    }
+
+    // Every record, in key order.
+    pub fn scan(&self) -> Result<Vec<(String, String)>, Error> {
+        let tree = self.tree.read().unwrap();
+        let mut records = Vec::new();
+        for (key, value) in tree.scan()? {
+            records.push((utf8(key)?, utf8(value)?));
+        }
+        Ok(records)
+    }
+
+    // Makes every write so far durable, writing out every changed page.
+    pub fn flush(&self) -> Result<(), Error> {
+        Ok(self.tree.read().unwrap().sync()?)
+    }
+
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let tree = self.tree.read().unwrap().stats()?;
+        Ok(Stats {
+            keys: tree.keys,
+            pages: tree.pages,
+            free_pages: tree.free_pages,
+            depth: tree.depth,
+            file_bytes: tree.pages * PAGE_SIZE as u64,
+            pool: tree.pool,
+        })
+    }
+
+    // Flushes and lets go of the database.
+    pub fn close(self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    fn written(&self, tree: &Tree) -> Result<(), Error> {
+        if self.options.sync_writes {
+            tree.sync()?;
+        }
+        Ok(())
+    }
+}
+
+// A record has to fit in a quarter of a leaf, and keys are what the tree is
+// sorted by, so an empty one is turned away.
+fn check_record(key: &str, value: &str) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::Invalid("key can't be empty".to_string()));
+    }
+    if key.len() > MAX_KEY_BYTES {
+        return Err(Error::Invalid(format!("key is {} bytes, over the limit of {}", key.len(), MAX_KEY_BYTES)));
+    }
+    if key.len() + value.len() > MAX_RECORD_BYTES {
+        return Err(Error::Invalid(format!(
+            "key and value are {} bytes, over the limit of {}",
+            key.len() + value.len(),
+            MAX_RECORD_BYTES
+        )));
+    }
+    Ok(())
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(bytes).map_err(|_| Error::Corrupt("record is not utf-8".to_string()))
+}
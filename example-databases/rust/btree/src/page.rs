@@ -0,0 +1,294 @@
+// How the tree is laid out in pages. The file is a run of PAGE_SIZE pages,
+// each ending in the crc32 of the rest of it, so a torn or damaged page is
+// noticed when it is read. Page 0 is the header; every other page is a leaf,
+// an internal node or free.
+//
+// header:   magic u64 | page size u32 | root page u64 | page count u64
+//           | first free page u64 | free page count u64 | key count u64
+// leaf:     LEAF u8 | entry count u16 | next leaf page u64
+//           | (key length u16 | value length u16 | key | value) per entry
+// internal: INTERNAL u8 | key count u16 | first child page u64
+//           | (key length u16 | key | child page u64) per key
+// free:     FREE u8 | next free page u64
+//
+// Entries in a leaf are in key order, and each leaf points at the one after
+// it so a scan can walk them. An internal node with n keys has n + 1
+// children; the child after key i holds the keys from key i up to key i + 1.
+use std::convert::TryInto;
+use std::io;
+
+pub const PAGE_SIZE: usize = 4096;
+// what a page has room for, before its crc32
+pub const PAGE_BODY: usize = PAGE_SIZE - 4;
+
+// "nullbtr" and a format version
+const MAGIC: u64 = 0x6e75_6c6c_6274_7201;
+
+const FREE: u8 = 0;
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+
+const LEAF_HEADER_BYTES: usize = 11;
+const LEAF_ENTRY_BYTES: usize = 4;
+const INTERNAL_HEADER_BYTES: usize = 11;
+const INTERNAL_KEY_BYTES: usize = 10;
+
+// The biggest key, and the biggest key and value together, a leaf takes.
+// A quarter of a page each, so a node split in two always fits in two pages.
+pub const MAX_KEY_BYTES: usize = 512;
+pub const MAX_RECORD_BYTES: usize = PAGE_BODY / 4 - LEAF_ENTRY_BYTES;
+
+pub type Page = Vec<u8>;
+
+pub struct Header {
+    pub root: u64,
+    // pages in the file, the header included
+    pub pages: u64,
+    // 0 when there are none
+    pub free_head: u64,
+    pub free_pages: u64,
+    pub keys: u64,
+}
+
+pub enum Node {
+    Leaf {
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        // 0 for the last leaf
+        next: u64,
+    },
+    Internal {
+        keys: Vec<Vec<u8>>,
+        children: Vec<u64>,
+    },
+    Free {
+        next: u64,
+    },
+}
+
+impl Header {
+    pub fn encode(&self) -> Page {
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        page.extend_from_slice(&MAGIC.to_le_bytes());
+        page.extend_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        for n in &[self.root, self.pages, self.free_head, self.free_pages, self.keys] {
+            page.extend_from_slice(&n.to_le_bytes());
+        }
+        page
+    }
+
+    pub fn decode(page: &[u8]) -> io::Result<Self> {
+        if u64_at(page, 0)? != MAGIC {
+            return Err(invalid("not a btree file".to_string()));
+        }
+        let page_size = u32::from_le_bytes(page[8..12].try_into().unwrap());
+        if page_size as usize != PAGE_SIZE {
+            return Err(invalid(format!("file has {} byte pages, not {}", page_size, PAGE_SIZE)));
+        }
+        Ok(Header {
+            root: u64_at(page, 12)?,
+            pages: u64_at(page, 20)?,
+            free_head: u64_at(page, 28)?,
+            free_pages: u64_at(page, 36)?,
+            keys: u64_at(page, 44)?,
+        })
+    }
+}
+
+impl Node {
+    pub fn empty_leaf() -> Self {
+        Node::Leaf { entries: Vec::new(), next: 0 }
+    }
+
+    // What encode would take, so callers can tell whether a node fits in a
+    // page before writing it.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Node::Leaf { entries, .. } => {
+                LEAF_HEADER_BYTES + entries.iter().map(|(k, v)| LEAF_ENTRY_BYTES + k.len() + v.len()).sum::<usize>()
+            }
+            Node::Internal { keys, .. } => {
+                INTERNAL_HEADER_BYTES + keys.iter().map(|k| INTERNAL_KEY_BYTES + k.len()).sum::<usize>()
+            }
+            Node::Free { .. } => 9,
+        }
+    }
+
+    pub fn fits(&self) -> bool {
+        self.encoded_len() <= PAGE_BODY
+    }
+
+    pub fn encode(&self) -> Page {
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        match self {
+            Node::Leaf { entries, next } => {
+                page.push(LEAF);
+                page.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+                page.extend_from_slice(&next.to_le_bytes());
+                for (key, value) in entries {
+                    page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                    page.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                    page.extend_from_slice(key);
+                    page.extend_from_slice(value);
+                }
+            }
+            Node::Internal { keys, children } => {
+                page.push(INTERNAL);
+                page.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+                page.extend_from_slice(&children[0].to_le_bytes());
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+                    page.extend_from_slice(key);
+                    page.extend_from_slice(&child.to_le_bytes());
+                }
+            }
+            Node::Free { next } => {
+                page.push(FREE);
+                page.extend_from_slice(&next.to_le_bytes());
+            }
+        }
+        page
+    }
+
+    pub fn decode(page: &[u8]) -> io::Result<Self> {
+        let mut r = Reader { page, at: 0 };
+        match r.bytes(1)?[0] {
+            LEAF => {
+                let count = r.u16()?;
+                let next = r.u64()?;
+                let mut entries = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let key_len = r.u16()? as usize;
+                    let value_len = r.u16()? as usize;
+                    let key = r.bytes(key_len)?.to_vec();
+                    entries.push((key, r.bytes(value_len)?.to_vec()));
+                }
+                Ok(Node::Leaf { entries, next })
+            }
+            INTERNAL => {
+                let count = r.u16()?;
+                let mut children = vec![r.u64()?];
+                let mut keys = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let key_len = r.u16()? as usize;
+                    keys.push(r.bytes(key_len)?.to_vec());
+                    children.push(r.u64()?);
+                }
+                Ok(Node::Internal { keys, children })
+            }
+            FREE => Ok(Node::Free { next: r.u64()? }),
+            other => Err(invalid(format!("unknown page kind {}", other))),
+        }
+    }
+
+    // Splits a node too big for one page in two about halfway through its
+    // bytes, with the key that separates them. A leaf's right half keeps its
+    // next; the caller points the left half at wherever the right half goes.
+    pub fn split(self) -> (Node, Vec<u8>, Node) {
+        let half = self.encoded_len() / 2;
+        match self {
+            Node::Leaf { mut entries, next } => {
+                let mut bytes = LEAF_HEADER_BYTES;
+                let mut at = 0;
+                while at < entries.len() - 1 && bytes < half {
+                    bytes += LEAF_ENTRY_BYTES + entries[at].0.len() + entries[at].1.len();
+                    at += 1;
+                }
+                let right = entries.split_off(at.max(1));
+                let separator = right[0].0.clone();
+                (Node::Leaf { entries, next: 0 }, separator, Node::Leaf { entries: right, next })
+            }
+            Node::Internal { mut keys, mut children } => {
+                let mut bytes = INTERNAL_HEADER_BYTES;
+                let mut at = 0;
+                while at < keys.len() - 2 && bytes < half {
+                    bytes += INTERNAL_KEY_BYTES + keys[at].len();
+                    at += 1;
+                }
+                // the separator moves up, leaving at least one key each side
+                let at = at.max(1);
+                let right_keys = keys.split_off(at + 1);
+                let separator = keys.pop().unwrap();
+                let right_children = children.split_off(at + 1);
+                (
+                    Node::Internal { keys, children },
+                    separator,
+                    Node::Internal { keys: right_keys, children: right_children },
+                )
+            }
+            Node::Free { .. } => unreachable!("free pages are never split"),
+        }
+    }
+
+    // The node holding everything in left, then separator, then right, which
+    // must be siblings of the same kind, left first. The result may be too
+    // big for a page, for split to share out again.
+    pub fn join(left: Node, separator: Vec<u8>, right: Node) -> Node {
+        match (left, right) {
+            (Node::Leaf { mut entries, .. }, Node::Leaf { entries: right, next }) => {
+                entries.extend(right);
+                Node::Leaf { entries, next }
+            }
+            (Node::Internal { mut keys, mut children }, Node::Internal { keys: right_keys, children: right_children }) => {
+                keys.push(separator);
+                keys.extend(right_keys);
+                children.extend(right_children);
+                Node::Internal { keys, children }
+            }
+            _ => unreachable!("siblings are always the same kind"),
+        }
+    }
+}
+
+// Appends the crc32 of page, padded out to PAGE_BODY, making it a whole page.
+pub fn seal(mut page: Page) -> Page {
+    page.resize(PAGE_BODY, 0);
+    let crc = crc32fast::hash(&page);
+    page.extend_from_slice(&crc.to_le_bytes());
+    page
+}
+
+// The page without its crc32, once it passes it.
+pub fn check(mut page: Page, id: u64) -> io::Result<Page> {
+    let crc = u32::from_le_bytes(page[PAGE_BODY..].try_into().unwrap());
+    page.truncate(PAGE_BODY);
+    if crc32fast::hash(&page) != crc {
+        return Err(invalid(format!("page {} fails its checksum", id)));
+    }
+    Ok(page)
+}
+
+struct Reader<'a> {
+    page: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        match self.page.get(self.at..self.at + n) {
+            Some(bytes) => {
+                self.at += n;
+                Ok(bytes)
+            }
+            None => Err(invalid(format!("page entry at {} runs past the end", self.at))),
+        }
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+fn u64_at(page: &[u8], at: usize) -> io::Result<u64> {
+    match page.get(at..at + 8) {
+        Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(invalid(format!("offset {} is past the end", at))),
+    }
+}
+
+pub fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
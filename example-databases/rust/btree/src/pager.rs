@@ -0,0 +1,156 @@
+// Reads and writes whole pages of the tree's file by page number.
+//
+// Pages are changed in place, so a crash part way through writing out a set
+// of changes could leave a split half done. Every change since the last sync
+// can be rolled back instead: before a page the file held at the last sync is
+// first overwritten, its old contents go into a journal next to the file,
+// "null.btree-journal", and are fsynced there. The journal starts with the
+// page count at the last sync, so pages added since can be cut off too. A sync
+// fsyncs the file and then empties the journal. Opening a file with a
+// journal that isn't empty puts the old pages back, which is the file as it
+// was at the last sync.
+use crate::page::{self, Page, PAGE_SIZE};
+use null_common::fsync;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+// page id, then the page as it was in the file
+const JOURNAL_ENTRY_BYTES: usize = 8 + PAGE_SIZE;
+
+pub struct Pager {
+    file: File,
+    journal: Mutex<Journal>,
+}
+
+struct Journal {
+    file: File,
+    // whole pages in the file at the last sync
+    synced_pages: u64,
+    // whether the page count has been written since the last sync
+    started: bool,
+    // pages whose old contents are in the journal already
+    saved: HashSet<u64>,
+}
+
+impl Pager {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut journal_path = path.as_os_str().to_owned();
+        journal_path.push("-journal");
+        let mut journal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(journal_path)?;
+        // without the journal's directory entry a crash after pages were
+        // overwritten would leave nothing to roll back from
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fsync::sync_dir(dir)?;
+        roll_back(&file, &mut journal)?;
+        let synced_pages = file.metadata()?.len() / PAGE_SIZE as u64;
+        Ok(Pager {
+            file,
+            journal: Mutex::new(Journal { file: journal, synced_pages, started: false, saved: HashSet::new() }),
+        })
+    }
+
+    // Whole pages in the file. A page cut short by a crash part way through
+    // growing the file doesn't count.
+    pub fn pages(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len() / PAGE_SIZE as u64)
+    }
+
+    // The page without its crc32, once it passes it.
+    pub fn read(&self, id: u64) -> io::Result<Page> {
+        let mut page = vec![0; PAGE_SIZE];
+        self.file.read_exact_at(&mut page, id * PAGE_SIZE as u64)?;
+        page::check(page, id)
+    }
+
+    // Writes out page, which mustn't be more than PAGE_BODY bytes.
+    pub fn write(&self, id: u64, page: Page) -> io::Result<()> {
+        self.journal(&[id])?;
+        self.file.write_all_at(&page::seal(page), id * PAGE_SIZE as u64)
+    }
+
+    // Saves what ids hold in the file now, for any not saved since the last
+    // sync, with one fsync of the journal. Writing them afterwards is then
+    // safe from a crash.
+    pub fn journal(&self, ids: &[u64]) -> io::Result<()> {
+        let mut journal = self.journal.lock().unwrap();
+        let mut entries = Vec::new();
+        if !journal.started {
+            entries.extend_from_slice(&journal.synced_pages.to_le_bytes());
+        }
+        for &id in ids {
+            if id >= journal.synced_pages || journal.saved.contains(&id) {
+                continue;
+            }
+            let mut old = vec![0; PAGE_SIZE];
+            self.file.read_exact_at(&mut old, id * PAGE_SIZE as u64)?;
+            entries.extend_from_slice(&id.to_le_bytes());
+            entries.extend_from_slice(&old);
+            journal.saved.insert(id);
+        }
+        if entries.is_empty() {
+            return Ok(());
+        }
+        journal.file.seek(SeekFrom::End(0))?;
+        journal.file.write_all(&entries)?;
+        journal.file.sync_data()?;
+        journal.started = true;
+        Ok(())
+    }
+
+    // Makes every page written so far durable, then empties the journal, as
+    // there is nothing before this to go back to.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()?;
+        let mut journal = self.journal.lock().unwrap();
+        if journal.started {
+            journal.file.set_len(0)?;
+            journal.file.sync_data()?;
+        }
+        journal.synced_pages = self.pages()?;
+        journal.started = false;
+        journal.saved.clear();
+        Ok(())
+    }
+}
+
+// Puts back every page the journal saved and cuts off pages added since it
+// was started, then empties it.
+fn roll_back(file: &File, journal: &mut File) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    journal.seek(SeekFrom::Start(0))?;
+    journal.read_to_end(&mut bytes)?;
+    if bytes.len() < 8 {
+        if !bytes.is_empty() {
+            journal.set_len(0)?;
+            journal.sync_data()?;
+        }
+        return Ok(());
+    }
+
+    let pages = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    // a crash while an entry was being added leaves it short or failing its
+    // checksum, and its page was never overwritten
+    for entry in bytes[8..].chunks_exact(JOURNAL_ENTRY_BYTES) {
+        let id = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        let old = &entry[8..];
+        if page::check(old.to_vec(), id).is_err() {
+            break;
+        }
+        file.write_all_at(old, id * PAGE_SIZE as u64)?;
+    }
+    file.set_len(pages * PAGE_SIZE as u64)?;
+    file.sync_all()?;
+    journal.set_len(0)?;
+    journal.sync_data()
+}
@@ -0,0 +1,224 @@
+// The buffer pool: a fixed number of frames, each holding one page of the
+// file, between the tree and the pager. Whoever is using a page pins it, and
+// a pinned page stays in its frame. A page that has been written to is dirty
+// and is only written to the file when its frame is needed for another page,
+// on flush, or when the pool is dropped, so a page changed over and over
+// while it is in memory is written out once.
+//
+// Frames are reused CLOCK fashion: a hand sweeps the frames, skipping pinned
+// ones and clearing the mark that using a page sets, and takes the first
+// frame it finds unmarked. When every frame is pinned, fetching waits for one
+// to be unpinned.
+use crate::page::Page;
+use crate::pager::Pager;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+
+pub struct BufferPool {
+    pager: Pager,
+    frames: Mutex<Frames>,
+    // signalled whenever a page is unpinned, for fetches waiting on a frame
+    unpinned: Condvar,
+}
+
+struct Frames {
+    frames: Vec<Frame>,
+    // frame by page id
+    map: HashMap<u64, usize>,
+    hand: usize,
+    stats: PoolStats,
+}
+
+struct Frame {
+    // None while the frame is empty
+    id: Option<u64>,
+    data: Arc<RwLock<Page>>,
+    pins: usize,
+    // changed since it was read or last written out
+    dirty: bool,
+    // used since the hand last came by
+    referenced: bool,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PoolStats {
+    pub frames: usize,
+    // pages found in a frame, and pages that had to be read in or made
+    pub hits: u64,
+    pub misses: u64,
+    // pages put out of their frame to make room for another
+    pub evictions: u64,
+    // dirty pages written to the file
+    pub writebacks: u64,
+    pub dirty: usize,
+}
+
+// A page pinned in its frame, unpinned when this is dropped.
+pub struct Pinned<'a> {
+    pool: &'a BufferPool,
+    frame: usize,
+    data: Arc<RwLock<Page>>,
+}
+
+impl Pinned<'_> {
+    pub fn read(&self) -> RwLockReadGuard<'_, Page> {
+        self.data.read().unwrap()
+    }
+}
+
+impl Drop for Pinned<'_> {
+    fn drop(&mut self) {
+        let mut frames = self.pool.frames.lock().unwrap();
+        frames.frames[self.frame].pins -= 1;
+        self.pool.unpinned.notify_one();
+    }
+}
+
+impl BufferPool {
+    // A pool of at least one frame over the file at path.
+    pub fn open(path: &Path, frames: usize) -> io::Result<Self> {
+        let frames = frames.max(1);
+        Ok(BufferPool {
+            pager: Pager::open(path)?,
+            frames: Mutex::new(Frames {
+                frames: (0..frames)
+                    .map(|_| Frame {
+                        id: None,
+                        data: Arc::new(RwLock::new(Vec::new())),
+                        pins: 0,
+                        dirty: false,
+                        referenced: false,
+                    })
+                    .collect(),
+                map: HashMap::new(),
+                hand: 0,
+                stats: PoolStats { frames, ..PoolStats::default() },
+            }),
+            unpinned: Condvar::new(),
+        })
+    }
+
+    // Whole pages in the file, not counting dirty pages past its end.
+    pub fn file_pages(&self) -> io::Result<u64> {
+        self.pager.pages()
+    }
+
+    // Pins page id, reading it in if it isn't in a frame already.
+    pub fn fetch(&self, id: u64) -> io::Result<Pinned<'_>> {
+        let (mut frames, i) = self.frame_for(id, true)?;
+        let frame = &mut frames.frames[i];
+        frame.pins += 1;
+        Ok(Pinned { pool: self, frame: i, data: frame.data.clone() })
+    }
+
+    // Replaces page id, which needn't be in the file yet, leaving it dirty.
+    pub fn write(&self, id: u64, page: Page) -> io::Result<()> {
+        let (mut frames, i) = self.frame_for(id, false)?;
+        let frame = &mut frames.frames[i];
+        *frame.data.write().unwrap() = page;
+        frame.dirty = true;
+        Ok(())
+    }
+
+    // Writes out every dirty page and fsyncs the file. What the pages held
+    // before goes into the pager's journal first, in one go.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut frames = self.frames.lock().unwrap();
+        let Frames { frames: all, stats, .. } = &mut *frames;
+        let dirty: Vec<u64> = all.iter().filter(|f| f.dirty).filter_map(|f| f.id).collect();
+        self.pager.journal(&dirty)?;
+        for frame in all.iter_mut().filter(|f| f.dirty) {
+            if let Some(id) = frame.id {
+                self.pager.write(id, frame.data.read().unwrap().clone())?;
+                frame.dirty = false;
+                stats.writebacks += 1;
+            }
+        }
+        self.pager.sync()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let frames = self.frames.lock().unwrap();
+        PoolStats {
+            dirty: frames.frames.iter().filter(|f| f.dirty).count(),
+            ..frames.stats.clone()
+        }
+    }
+
+    // The frame holding page id, marked as used, after putting the page in
+    // one if it isn't in one already: read from the file if load, left empty
+    // for the caller to fill if not. Comes back with the frames still locked.
+    fn frame_for(&self, id: u64, load: bool) -> io::Result<(MutexGuard<'_, Frames>, usize)> {
+        let mut frames = self.frames.lock().unwrap();
+        loop {
+            if let Some(&i) = frames.map.get(&id) {
+                frames.stats.hits += 1;
+                frames.frames[i].referenced = true;
+                return Ok((frames, i));
+            }
+            if let Some(i) = frames.victim() {
+                self.evict(&mut frames, i)?;
+                let page = if load { self.pager.read(id)? } else { Vec::new() };
+                let frame = &mut frames.frames[i];
+                *frame.data.write().unwrap() = page;
+                frame.id = Some(id);
+                frame.referenced = true;
+                frames.map.insert(id, i);
+                frames.stats.misses += 1;
+                return Ok((frames, i));
+            }
+            frames = self.unpinned.wait(frames).unwrap();
+        }
+    }
+
+    // Empties frame i, which isn't pinned, writing its page out first if it
+    // is dirty.
+    fn evict(&self, frames: &mut Frames, i: usize) -> io::Result<()> {
+        let frame = &mut frames.frames[i];
+        let id = match frame.id {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        if frame.dirty {
+            self.pager.write(id, frame.data.read().unwrap().clone())?;
+            frame.dirty = false;
+            frames.stats.writebacks += 1;
+        }
+        frame.id = None;
+        frames.map.remove(&id);
+        frames.stats.evictions += 1;
+        Ok(())
+    }
+}
+
+// Nothing written is lost for want of a flush, only for want of an fsync.
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Frames {
+    // A frame to reuse: an empty one, or the first unpinned one the hand
+    // finds unused since it last came by. None when every frame is pinned.
+    fn victim(&mut self) -> Option<usize> {
+        if let Some(i) = self.frames.iter().position(|f| f.id.is_none()) {
+            return Some(i);
+        }
+        // two turns, as the first may only clear marks
+        for _ in 0..2 * self.frames.len() {
+            self.hand = (self.hand + 1) % self.frames.len();
+            let frame = &mut self.frames[self.hand];
+            if frame.pins > 0 {
+                continue;
+            }
+            if !frame.referenced {
+                return Some(self.hand);
+            }
+            frame.referenced = false;
+        }
+        None
+    }
+}
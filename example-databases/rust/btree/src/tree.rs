@@ -0,0 +1,294 @@
+// The B+tree itself: lookups, inserts and deletes over the pages of one file.
+// Every key and value is in a leaf; internal nodes only hold the keys that
+// separate their children. A node that no longer fits its page is split in
+// two and the key between the halves added to its parent, which may split in
+// turn, up to a new root. A node left under a quarter full by a delete is
+// merged with a sibling, or shares its entries out with it when the two don't
+// fit in one page, and a root left with one child hands the root to it.
+// Pages freed by merges go on a free list and are reused before the file
+// grows. Every page is read and written through the buffer pool.
+use crate::page::{invalid, Header, Node, PAGE_BODY};
+use crate::pool::{BufferPool, PoolStats};
+use std::io;
+use std::path::Path;
+
+// nodes holding less than this after a delete are merged or refilled
+const MIN_FILL: usize = PAGE_BODY / 4;
+
+pub struct Tree {
+    pool: BufferPool,
+    // kept in memory, and written back after every change
+    header: Header,
+}
+
+#[derive(Debug, Default)]
+pub struct TreeStats {
+    pub keys: u64,
+    // pages in the file, the header and free pages included
+    pub pages: u64,
+    pub free_pages: u64,
+    // levels from the root to the leaves, 1 while the root is a leaf
+    pub depth: usize,
+    pub pool: PoolStats,
+}
+
+// A node that split: the key between its halves and the page of the right half.
+type Split = Option<(Vec<u8>, u64)>;
+
+impl Tree {
+    // Opens the tree in the file at path, with a buffer pool of frames pages.
+    pub fn open(path: &Path, frames: usize) -> io::Result<Self> {
+        let pool = BufferPool::open(path, frames)?;
+        if pool.file_pages()? == 0 {
+            // a new file: the header and an empty leaf for a root
+            let header = Header { root: 1, pages: 2, free_head: 0, free_pages: 0, keys: 0 };
+            pool.write(1, Node::empty_leaf().encode())?;
+            pool.write(0, header.encode())?;
+            pool.flush()?;
+            return Ok(Tree { pool, header });
+        }
+        let header = Header::decode(&pool.fetch(0)?.read())?;
+        let pages = pool.file_pages()?;
+        if header.pages > pages {
+            return Err(invalid(format!("header counts {} pages but the file holds {}", header.pages, pages)));
+        }
+        Ok(Tree { pool, header })
+    }
+
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let mut id = self.header.root;
+        loop {
+            match self.node(id)? {
+                Node::Internal { keys, children } => id = children[child_index(&keys, key)],
+                Node::Leaf { mut entries, .. } => {
+                    return Ok(search(&entries, key).ok().map(|i| entries.swap_remove(i).1));
+                }
+                Node::Free { .. } => return Err(free_in_tree(id)),
+            }
+        }
+    }
+
+    // True when key had no value before.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<bool> {
+        let root = self.header.root;
+        let (added, split) = self.insert(root, key, value)?;
+        if let Some((separator, right)) = split {
+            let new_root = self.allocate()?;
+            let node = Node::Internal { keys: vec![separator], children: vec![root, right] };
+            self.write_node(new_root, &node)?;
+            self.header.root = new_root;
+        }
+        if added {
+            self.header.keys += 1;
+        }
+        self.write_header()?;
+        Ok(added)
+    }
+
+    // True when key had a value.
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<bool> {
+        let root = self.header.root;
+        if self.remove(root, key)?.is_none() {
+            return Ok(false);
+        }
+        if let Node::Internal { keys, children } = self.node(root)? {
+            if keys.is_empty() {
+                self.header.root = children[0];
+                self.free(root)?;
+            }
+        }
+        self.header.keys -= 1;
+        self.write_header()?;
+        Ok(true)
+    }
+
+    // Every key and value, in key order, walking the leaves left to right.
+    pub fn scan(&self) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut id = self.header.root;
+        while let Node::Internal { children, .. } = self.node(id)? {
+            id = children[0];
+        }
+        let mut records = Vec::new();
+        while id != 0 {
+            match self.node(id)? {
+                Node::Leaf { entries, next } => {
+                    records.extend(entries);
+                    id = next;
+                }
+                _ => return Err(invalid(format!("page {} is in the chain of leaves but isn't one", id))),
+            }
+        }
+        Ok(records)
+    }
+
+    // Writes every dirty page out to the file and fsyncs it.
+    pub fn sync(&self) -> io::Result<()> {
+        self.pool.flush()
+    }
+
+    pub fn stats(&self) -> io::Result<TreeStats> {
+        let mut depth = 1;
+        let mut id = self.header.root;
+        while let Node::Internal { children, .. } = self.node(id)? {
+            id = children[0];
+            depth += 1;
+        }
+        Ok(TreeStats {
+            keys: self.header.keys,
+            pages: self.header.pages,
+            free_pages: self.header.free_pages,
+            depth,
+            pool: self.pool.stats(),
+        })
+    }
+
+    // Puts key in the subtree at id, returning whether it is new there and
+    // how the node at id split, if it did.
+    fn insert(&mut self, id: u64, key: &[u8], value: &[u8]) -> io::Result<(bool, Split)> {
+        let mut node = self.node(id)?;
+        let added = match &mut node {
+            Node::Leaf { entries, .. } => match search(entries, key) {
+                Ok(i) => {
+                    entries[i].1 = value.to_vec();
+                    false
+                }
+                Err(i) => {
+                    entries.insert(i, (key.to_vec(), value.to_vec()));
+                    true
+                }
+            },
+            Node::Internal { keys, children } => {
+                let i = child_index(keys, key);
+                let (added, split) = self.insert(children[i], key, value)?;
+                if let Some((separator, right)) = split {
+                    keys.insert(i, separator);
+                    children.insert(i + 1, right);
+                }
+                added
+            }
+            Node::Free { .. } => return Err(free_in_tree(id)),
+        };
+        Ok((added, self.store(id, node)?))
+    }
+
+    // Takes key out of the subtree at id, returning what the node at id
+    // takes up now, or None if key wasn't there. The node may be left under
+    // MIN_FILL; its parent sees to that.
+    fn remove(&mut self, id: u64, key: &[u8]) -> io::Result<Option<usize>> {
+        let node = match self.node(id)? {
+            Node::Leaf { mut entries, next } => match search(&entries, key) {
+                Ok(i) => {
+                    entries.remove(i);
+                    Node::Leaf { entries, next }
+                }
+                Err(_) => return Ok(None),
+            },
+            Node::Internal { mut keys, mut children } => {
+                let i = child_index(&keys, key);
+                match self.remove(children[i], key)? {
+                    Some(len) if len < MIN_FILL => self.rebalance(&mut keys, &mut children, i)?,
+                    Some(_) => return Ok(Some(Node::Internal { keys, children }.encoded_len())),
+                    None => return Ok(None),
+                }
+                Node::Internal { keys, children }
+            }
+            Node::Free { .. } => return Err(free_in_tree(id)),
+        };
+        self.write_node(id, &node)?;
+        Ok(Some(node.encoded_len()))
+    }
+
+    // Merges child i, which is under MIN_FILL, with a sibling, or shares
+    // their entries out evenly when they don't fit in one page, updating the
+    // parent's keys and children to match.
+    fn rebalance(&mut self, keys: &mut Vec<Vec<u8>>, children: &mut Vec<u64>, i: usize) -> io::Result<()> {
+        let l = if i > 0 { i - 1 } else { i };
+        let (left_id, right_id) = (children[l], children[l + 1]);
+        let joined = Node::join(self.node(left_id)?, keys[l].clone(), self.node(right_id)?);
+        if joined.fits() {
+            self.write_node(left_id, &joined)?;
+            self.free(right_id)?;
+            keys.remove(l);
+            children.remove(l + 1);
+        } else {
+            let (mut left, separator, right) = joined.split();
+            if let Node::Leaf { next, .. } = &mut left {
+                *next = right_id;
+            }
+            self.write_node(left_id, &left)?;
+            self.write_node(right_id, &right)?;
+            keys[l] = separator;
+        }
+        Ok(())
+    }
+
+    // Writes node to page id, splitting it in two first if it doesn't fit.
+    fn store(&mut self, id: u64, node: Node) -> io::Result<Split> {
+        if node.fits() {
+            self.write_node(id, &node)?;
+            return Ok(None);
+        }
+        let (mut left, separator, right) = node.split();
+        let right_id = self.allocate()?;
+        if let Node::Leaf { next, .. } = &mut left {
+            *next = right_id;
+        }
+        self.write_node(right_id, &right)?;
+        self.write_node(id, &left)?;
+        Ok(Some((separator, right_id)))
+    }
+
+    // A page for a new node, off the free list if there is one there.
+    fn allocate(&mut self) -> io::Result<u64> {
+        if self.header.free_head == 0 {
+            self.header.pages += 1;
+            return Ok(self.header.pages - 1);
+        }
+        let id = self.header.free_head;
+        match self.node(id)? {
+            Node::Free { next } => {
+                self.header.free_head = next;
+                self.header.free_pages -= 1;
+                Ok(id)
+            }
+            _ => Err(invalid(format!("page {} is on the free list but in use", id))),
+        }
+    }
+
+    fn free(&mut self, id: u64) -> io::Result<()> {
+        self.write_node(id, &Node::Free { next: self.header.free_head })?;
+        self.header.free_head = id;
+        self.header.free_pages += 1;
+        Ok(())
+    }
+
+    fn node(&self, id: u64) -> io::Result<Node> {
+        if id == 0 || id >= self.header.pages {
+            return Err(invalid(format!("page {} is out of range", id)));
+        }
+        let page = self.pool.fetch(id)?;
+        let node = Node::decode(&page.read());
+        node
+    }
+
+    fn write_node(&self, id: u64, node: &Node) -> io::Result<()> {
+        self.pool.write(id, node.encode())
+    }
+
+    fn write_header(&self) -> io::Result<()> {
+        self.pool.write(0, self.header.encode())
+    }
+}
+
+// Which child of an internal node with keys holds key.
+fn child_index(keys: &[Vec<u8>], key: &[u8]) -> usize {
+    keys.partition_point(|k| k.as_slice() <= key)
+}
+
+fn search(entries: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> Result<usize, usize> {
+    entries.binary_search_by(|(k, _)| k.as_slice().cmp(key))
+}
+
+fn free_in_tree(id: u64) -> io::Error {
+    invalid(format!("page {} is free but in the tree", id))
+}
@@ -0,0 +1,272 @@
+use disk_btree::{open, Error, Options, FILE};
+use std::collections::BTreeMap;
+use tempfile::TempDir;
+
+// A fresh directory per test so they can run in parallel, removed once the
+// test is done with it.
+fn temp_dir(name: &str) -> TempDir {
+    tempfile::Builder::new().prefix(&format!("null-btree-{}-", name)).tempdir().unwrap()
+}
+
+// 0..n in a scrambled order, so keys don't arrive sorted.
+fn scrambled(n: usize) -> impl Iterator<Item = usize> {
+    (0..n).map(move |i| i * 7919 % n)
+}
+
+#[test]
+fn put_get_delete() {
+    let dir = temp_dir("put_get_delete");
+    let db = open(&dir, Options::default()).unwrap();
+
+    assert_eq!(db.get("foo").unwrap(), None);
+    db.put("foo", "bar").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("bar".to_string()));
+    db.put("foo", "baz").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("baz".to_string()));
+    db.delete("foo").unwrap();
+    assert_eq!(db.get("foo").unwrap(), None);
+    db.delete("foo").unwrap();
+    assert_eq!(db.stats().unwrap().keys, 0);
+
+    assert!(matches!(db.put("", "b"), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("big", &"v".repeat(4096)), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn inserts_split_nodes() {
+    let dir = temp_dir("inserts_split_nodes");
+    let db = open(&dir, Options::default()).unwrap();
+    for i in scrambled(10_000) {
+        db.put(&format!("key{:05}", i), &format!("value {}", i)).unwrap();
+    }
+
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.keys, 10_000);
+    assert!(stats.depth >= 2, "{:?}", stats);
+    for i in 0..10_000 {
+        assert_eq!(db.get(&format!("key{:05}", i)).unwrap(), Some(format!("value {}", i)));
+    }
+    assert_eq!(db.get("key10000").unwrap(), None);
+    let records = db.scan().unwrap();
+    assert_eq!(records.len(), 10_000);
+    assert!(records.windows(2).all(|w| w[0].0 < w[1].0));
+}
+
+#[test]
+fn deletes_merge_nodes_and_free_pages() {
+    let dir = temp_dir("deletes_merge_nodes_and_free_pages");
+    let db = open(&dir, Options::default()).unwrap();
+    let value = "v".repeat(100);
+    for i in scrambled(5000) {
+        db.put(&format!("key{:04}", i), &value).unwrap();
+    }
+    let full = db.stats().unwrap();
+
+    // keep every tenth key
+    for i in scrambled(5000).filter(|i| i % 10 != 0) {
+        db.delete(&format!("key{:04}", i)).unwrap();
+    }
+    let thinned = db.stats().unwrap();
+    assert_eq!(thinned.keys, 500);
+    assert!(thinned.free_pages > full.pages / 2, "{:?} {:?}", full, thinned);
+    assert!(thinned.depth <= full.depth);
+    for i in 0..5000 {
+        let expected = if i % 10 == 0 { Some(value.clone()) } else { None };
+        assert_eq!(db.get(&format!("key{:04}", i)).unwrap(), expected);
+    }
+    assert_eq!(db.scan().unwrap().len(), 500);
+
+    // the freed pages are used again before the file grows
+    for i in scrambled(5000).filter(|i| i % 10 != 0) {
+        db.put(&format!("key{:04}", i), &value).unwrap();
+    }
+    let refilled = db.stats().unwrap();
+    assert!(refilled.pages <= full.pages + full.pages / 10, "{:?} {:?}", full, refilled);
+
+    // and emptying the tree leaves a single leaf for a root
+    for i in 0..5000 {
+        db.delete(&format!("key{:04}", i)).unwrap();
+    }
+    let empty = db.stats().unwrap();
+    assert_eq!(empty.keys, 0);
+    assert_eq!(empty.depth, 1);
+    assert_eq!(empty.free_pages, empty.pages - 2);
+    assert!(db.scan().unwrap().is_empty());
+}
+
+#[test]
+fn random_writes_match_a_map() {
+    let dir = temp_dir("random_writes_match_a_map");
+    let db = open(&dir, Options::default()).unwrap();
+    let mut expected = BTreeMap::new();
+    // a small linear congruential generator, so the run is the same every time
+    let mut seed: u64 = 42;
+    let mut next = || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize
+    };
+    for _ in 0..20_000 {
+        let key = format!("k{}", next() % 3000);
+        if next() % 3 == 0 {
+            db.delete(&key).unwrap();
+            expected.remove(&key);
+        } else {
+            let value = "x".repeat(next() % 300);
+            db.put(&key, &value).unwrap();
+            expected.insert(key, value);
+        }
+    }
+    assert_eq!(db.scan().unwrap(), expected.into_iter().collect::<Vec<(String, String)>>());
+}
+
+#[test]
+fn reopen_keeps_the_tree() {
+    let dir = temp_dir("reopen_keeps_the_tree");
+    let db = open(&dir, Options::default()).unwrap();
+    for i in scrambled(3000) {
+        db.put(&format!("key{:04}", i), &format!("value {}", i)).unwrap();
+    }
+    for i in 0..1000 {
+        db.delete(&format!("key{:04}", i)).unwrap();
+    }
+    let stats = db.stats().unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, Options::default()).unwrap();
+    let reopened = db.stats().unwrap();
+    assert_eq!((reopened.keys, reopened.pages, reopened.free_pages), (stats.keys, stats.pages, stats.free_pages));
+    assert_eq!(db.get("key0999").unwrap(), None);
+    assert_eq!(db.get("key2999").unwrap(), Some("value 2999".to_string()));
+    assert_eq!(db.scan().unwrap().len(), 2000);
+}
+
+#[test]
+fn crash_rolls_back_to_the_last_flush() {
+    let dir = temp_dir("crash_rolls_back_to_the_last_flush");
+    // a pool this small writes pages out long before a flush
+    let options = || Options { pool_pages: 8, ..Options::default() };
+    let db = open(&dir, options()).unwrap();
+    for i in scrambled(2000) {
+        db.put(&format!("key{:04}", i), &format!("value {}", i)).unwrap();
+    }
+    db.flush().unwrap();
+    let stats = db.stats().unwrap();
+
+    // splits, merges and freed pages, some of them already in the file
+    for i in 2000..4000 {
+        db.put(&format!("key{:04}", i), &"v".repeat(200)).unwrap();
+    }
+    for i in 0..1500 {
+        db.delete(&format!("key{:04}", i)).unwrap();
+    }
+    assert!(db.stats().unwrap().pool.writebacks > 0);
+    assert!(dir.path().join(format!("{}-journal", FILE)).metadata().unwrap().len() > 0);
+    // a crash: nothing more is written
+    std::mem::forget(db);
+
+    let db = open(&dir, options()).unwrap();
+    let reopened = db.stats().unwrap();
+    assert_eq!((reopened.keys, reopened.pages, reopened.free_pages), (stats.keys, stats.pages, stats.free_pages));
+    let records = db.scan().unwrap();
+    assert_eq!(records.len(), 2000);
+    assert_eq!(records[0], ("key0000".to_string(), "value 0".to_string()));
+    assert_eq!(db.get("key2000").unwrap(), None);
+    assert_eq!(dir.path().join(format!("{}-journal", FILE)).metadata().unwrap().len(), 0);
+}
+
+#[test]
+fn corrupt_pages_are_reported() {
+    let dir = temp_dir("corrupt_pages_are_reported");
+    let db = open(&dir, Options::default()).unwrap();
+    db.put("foo", "bar").unwrap();
+    db.close().unwrap();
+
+    // page 1 is the root
+    let path = dir.path().join(FILE);
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[4096 + 20] ^= 0xff;
+    std::fs::write(&path, &bytes).unwrap();
+    let db = open(&dir, Options::default()).unwrap();
+    assert!(matches!(db.get("foo"), Err(Error::Corrupt(_))));
+
+    // and a file that isn't a tree doesn't open at all
+    std::fs::write(&path, vec![7; 8192]).unwrap();
+    assert!(matches!(open(&dir, Options::default()), Err(Error::Corrupt(_))));
+}
+
+#[test]
+fn tree_larger_than_the_buffer_pool() {
+    let dir = temp_dir("tree_larger_than_the_buffer_pool");
+    let options = || Options { pool_pages: 8, ..Options::default() };
+    let db = open(&dir, options()).unwrap();
+    let value = "v".repeat(200);
+    for i in scrambled(5000) {
+        db.put(&format!("key{:04}", i), &value).unwrap();
+    }
+    for i in (0..5000).step_by(3) {
+        db.delete(&format!("key{:04}", i)).unwrap();
+    }
+
+    let stats = db.stats().unwrap();
+    assert!(stats.pages > 100, "{:?}", stats);
+    assert_eq!(stats.pool.frames, 8);
+    assert!(stats.pool.evictions > 0 && stats.pool.writebacks > 0, "{:?}", stats.pool);
+    assert!(stats.pool.dirty <= 8);
+    let check = |db: &disk_btree::Db| {
+        for i in 0..5000 {
+            let expected = if i % 3 == 0 { None } else { Some(value.clone()) };
+            assert_eq!(db.get(&format!("key{:04}", i)).unwrap(), expected);
+        }
+        assert_eq!(db.scan().unwrap().len(), 3333);
+    };
+    check(&db);
+
+    // dropped without a flush, the pool still writes back its dirty pages
+    drop(db);
+    let db = open(&dir, options()).unwrap();
+    check(&db);
+}
+
+#[test]
+fn readers_share_a_tiny_buffer_pool() {
+    let dir = temp_dir("readers_share_a_tiny_buffer_pool");
+    // fewer frames than readers, so some wait for a page to be unpinned
+    let db = open(&dir, Options { pool_pages: 2, ..Options::default() }).unwrap();
+    for i in scrambled(2000) {
+        db.put(&format!("key{:04}", i), &format!("value {}", i)).unwrap();
+    }
+
+    std::thread::scope(|s| {
+        for t in 0..8 {
+            let db = &db;
+            s.spawn(move || {
+                for i in (t..2000).step_by(8) {
+                    assert_eq!(db.get(&format!("key{:04}", i)).unwrap(), Some(format!("value {}", i)));
+                }
+            });
+        }
+    });
+    let pool = db.stats().unwrap().pool;
+    assert!(pool.misses > pool.frames as u64, "{:?}", pool);
+}
+
+#[test]
+fn buffer_pool_keeps_hot_pages() {
+    let dir = temp_dir("buffer_pool_keeps_hot_pages");
+    let db = open(&dir, Options::default()).unwrap();
+    for i in 0..3000 {
+        db.put(&format!("key{:04}", i), "some value").unwrap();
+    }
+    db.flush().unwrap();
+    let before = db.stats().unwrap().pool;
+    assert_eq!(before.dirty, 0);
+
+    // the whole tree fits, so reading it again touches no disk
+    for i in 0..3000 {
+        db.get(&format!("key{:04}", i)).unwrap();
+    }
+    let after = db.stats().unwrap().pool;
+    assert_eq!(after.misses, before.misses);
+    assert_eq!(after.evictions, 0);
+    assert!(after.hits > before.hits);
+}
@@ -0,0 +1,156 @@
+use crate::fsync;
+use crate::replication;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Closed stretches of the replication log, "{start}-{end}.nlog" where start
+// and end are byte offsets into the log, so every record keeps the sequence
+// number it had when it was written.
+const CHUNK_EXTENSION: &str = "nlog";
+
+// How far to roll the database forward from a base backup.
+#[derive(Clone, Copy, Debug)]
+pub enum Target {
+    // every record up to and including this log offset
+    Sequence(u64),
+    // every record written at or before this unix millis timestamp
+    Timestamp(u64),
+}
+
+pub struct Archive {
+    dir: PathBuf,
+    retention: Duration,
+}
+
+struct Chunk {
+    start: u64,
+    end: u64,
+    path: PathBuf,
+}
+
+impl Archive {
+    pub fn new(dir: PathBuf, retention: Duration) -> Self {
+        Archive { dir, retention }
+    }
+
+    // Copies whatever the log gained since the last call into a new chunk,
+    // then drops chunks older than the retention. Returns the offset the
+    // archive now holds the log up to. Callers hold the write lock so the log
+    // doesn't grow under us.
+    pub fn roll(&self) -> Result<u64, Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let end = replication::log_end();
+
+        let mut chunks = chunks(&self.dir)?;
+        // A recovery rewound the log, whatever we archived past that point
+        // belongs to the timeline we rolled back from.
+        for chunk in chunks.iter().filter(|c| c.end > end) {
+            let mut abandoned = chunk.path.clone().into_os_string();
+            abandoned.push(".abandoned");
+            std::fs::rename(&chunk.path, abandoned)?;
+        }
+        chunks.retain(|c| c.end <= end);
+
+        // the log may have been dropped past the newest chunk before this
+        // archive was set up, the gap shows when a replay needs it
+        let start = chunks.last().map(|c| c.end).unwrap_or(0).max(replication::log_start());
+        if end > start {
+            let path = self.dir.join(format!("{:020}-{:020}.{}", start, end, CHUNK_EXTENSION));
+            let mut to = File::create(&path)?;
+            replication::copy_range(start, end, &mut to)?;
+            fsync::timed(|| to.sync_all())?;
+            chunks.push(Chunk { start, end, path });
+        }
+
+        // the newest chunk says where the next one starts, so it always stays
+        let newest = chunks.len().saturating_sub(1);
+        for chunk in &chunks[..newest] {
+            let age = std::fs::metadata(&chunk.path)?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if age > self.retention {
+                std::fs::remove_file(&chunk.path)?;
+            }
+        }
+        Ok(end)
+    }
+}
+
+// Hands every archived record after the `after` offset to apply, oldest
+// first, stopping at the target. Returns the offset replayed up to and how
+// many records that was.
+pub fn replay<F>(dir: &Path, after: u64, target: Target, mut apply: F) -> Result<(u64, usize), Error>
+where
+    F: FnMut(u64, &str, &str) -> Result<(), Error>,
+{
+    let chunks = chunks(dir)?;
+    let mut sequence = after;
+    let mut replayed = 0;
+    // how far the chunks so far reach; a chunk that starts past that means
+    // records are missing, which a replay can't skip over
+    let mut covered = after;
+    for chunk in chunks.iter().filter(|c| c.end > after) {
+        if chunk.start > covered {
+            let msg = if covered == after {
+                format!("archive starts at {}, after the backup at {}", chunk.start, after)
+            } else {
+                format!("archive is missing the log from {} to {}", covered, chunk.start)
+            };
+            return Err(Error::new(ErrorKind::NotFound, msg));
+        }
+        covered = chunk.end;
+        let mut text = String::new();
+        File::open(&chunk.path)?.read_to_string(&mut text)?;
+
+        let mut offset = chunk.start;
+        for line in text.split_inclusive('\n') {
+            offset += line.len() as u64;
+            if offset <= after {
+                continue;
+            }
+            let (ts, key, value) = match replication::parse_record(line.trim_end_matches('\n')) {
+                Some(record) => record,
+                None => continue,
+            };
+            let past = match target {
+                Target::Sequence(until) => offset > until,
+                // records from before timestamps were kept count as old enough
+                Target::Timestamp(until) => ts.map(|ts| ts > until).unwrap_or(false),
+            };
+            if past {
+                return Ok((sequence, replayed));
+            }
+            apply(ts.unwrap_or(0), key, value)?;
+            sequence = offset;
+            replayed += 1;
+        }
+    }
+    Ok((sequence, replayed))
+}
+
+// Archived chunks in log order.
+fn chunks(dir: &Path) -> Result<Vec<Chunk>, Error> {
+    let mut chunks = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(OsStr::to_str) != Some(CHUNK_EXTENSION) {
+            continue;
+        }
+        let range = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .and_then(|stem| stem.split_once('-'))
+            .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)));
+        if let Some((start, end)) = range {
+            chunks.push(Chunk { start, end, path });
+        }
+    }
+    chunks.sort_by_key(|c| c.start);
+    Ok(chunks)
+}
+
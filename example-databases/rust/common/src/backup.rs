@@ -0,0 +1,116 @@
+// Backups of the servers that keep their data in log segments, and restores
+// from them. Pack files are immutable, so a backup can hard link them, and only
+// the active segment has to be copied.
+use crate::{fsync, replication};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Written last, so a backup directory without one never finished.
+pub const MANIFEST: &str = "backup.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub created_unix_secs: u64,
+    // replication log offset the backup is consistent up to
+    pub sequence: u64,
+    // how far into its leader's log a follower had applied, when the backup
+    // was taken from one
+    #[serde(default)]
+    pub follower_offset: Option<u64>,
+    pub segments: Vec<String>,
+}
+
+// The segments a server keeps its data in.
+pub struct Segments {
+    // the data directory they are in
+    pub dir: PathBuf,
+    // the one still being appended to, which can't be linked
+    pub active: &'static str,
+    // every segment file in a directory, oldest first
+    pub list: fn(&Path) -> Result<Vec<PathBuf>, Error>,
+}
+
+impl Segments {
+    fn names(&self) -> Result<Vec<String>, Error> {
+        Ok((self.list)(&self.dir)?
+            .iter()
+            .filter_map(|f| f.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .collect())
+    }
+}
+
+// Takes a consistent copy of the database into dir while it keeps serving.
+// Under the write lock the segment set is frozen: pack files are hard linked
+// (copied if dir is on another filesystem) and the small active segment is
+// copied. The replication log isn't copied, only how long it was: a restore
+// starts the log again from there, and the archive holds what came before.
+// Every file and dir itself are synced before the manifest is written, and it
+// before this returns, so a backup reported as taken survives a crash.
+pub fn take(segments: &Segments, dir: &Path, file_mutex: &RwLock<bool>) -> Result<Manifest, Error> {
+    if dir.join(MANIFEST).exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, "directory already holds a backup"));
+    }
+    std::fs::create_dir_all(dir)?;
+
+    let (names, sequence, follower_offset) = {
+        let _write_lock = file_mutex.write();
+        let names = segments.names()?;
+        for name in &names {
+            let (from, to) = (segments.dir.join(name), dir.join(name));
+            if name == segments.active || std::fs::hard_link(&from, &to).is_err() {
+                std::fs::copy(&from, &to)?;
+            }
+        }
+        (names, replication::log_end(), replication::applied_offset(&segments.dir))
+    };
+    sync_files(dir, &names)?;
+
+    let manifest = Manifest {
+        created_unix_secs: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        sequence,
+        follower_offset,
+        segments: names,
+    };
+    std::fs::write(dir.join(MANIFEST), serde_json::to_vec_pretty(&manifest)?)?;
+    sync_files(dir, &[MANIFEST.to_string()])?;
+    Ok(manifest)
+}
+
+// Replaces the segments in the data directory with the ones in a backup.
+// Only run before the server starts taking requests.
+pub fn restore(segments: &Segments, dir: &Path, following: bool) -> Result<Manifest, Error> {
+    let manifest: Manifest = match std::fs::read(dir.join(MANIFEST)) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(_) => {
+            return Err(Error::new(ErrorKind::NotFound, "no backup.json, not a finished backup"))
+        }
+    };
+
+    for name in segments.names()? {
+        std::fs::remove_file(segments.dir.join(name))?;
+    }
+    for name in &manifest.segments {
+        std::fs::copy(dir.join(name), segments.dir.join(name))?;
+    }
+    sync_files(&segments.dir, &manifest.segments)?;
+    replication::restart_at(manifest.sequence)?;
+    // a backup of the leader is where its log stood, so a follower restored
+    // from one picks up the leader's log from there
+    let applied = manifest.follower_offset.unwrap_or(manifest.sequence);
+    replication::set_applied_offset(&segments.dir, if following { Some(applied) } else { None })?;
+    Ok(manifest)
+}
+
+// Makes names in dir durable, contents and directory entries both.
+fn sync_files(dir: &Path, names: &[String]) -> Result<(), Error> {
+    for name in names {
+        let file = File::open(dir.join(name))?;
+        fsync::timed(|| file.sync_all())?;
+    }
+    fsync::sync_dir(dir)
+}
@@ -0,0 +1,240 @@
+// A memory-bounded cache of blocks read from disk, keyed by the id of the file
+// they came from and their offset in it, and shared by every reader of a
+// database. Eviction is CLOCK: reading a block marks it, and the hand that
+// sweeps the slots for room unmarks marked blocks and evicts the first
+// unmarked one, so a block read again since the hand last passed stays.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Every file gets an id when a reader opens it, so blocks of a file that has
+// since been replaced under the same name are never mistaken for its own.
+static NEXT_FILE_ID: AtomicU64 = AtomicU64::new(1);
+
+pub fn next_file_id() -> u64 {
+    NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // what the cached blocks add up to
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+pub struct BlockCache<T> {
+    capacity: usize,
+    clock: Mutex<Clock<T>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Clock<T> {
+    slots: Vec<Option<Slot<T>>>,
+    // slot by file id and offset
+    map: HashMap<(u64, u64), usize>,
+    free: Vec<usize>,
+    hand: usize,
+    bytes: usize,
+}
+
+struct Slot<T> {
+    key: (u64, u64),
+    block: Arc<T>,
+    // roughly what the block takes in memory
+    charge: usize,
+    referenced: bool,
+}
+
+impl<T> BlockCache<T> {
+    // A capacity of 0 caches nothing.
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            clock: Mutex::new(Clock {
+                slots: Vec::new(),
+                map: HashMap::new(),
+                free: Vec::new(),
+                hand: 0,
+                bytes: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, file: u64, offset: u64) -> Option<Arc<T>> {
+        let mut clock = self.clock.lock().unwrap();
+        let found = match clock.map.get(&(file, offset)) {
+            Some(&i) => clock.slots[i].as_mut().map(|slot| {
+                slot.referenced = true;
+                slot.block.clone()
+            }),
+            None => None,
+        };
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    // Caches block, whose charge is roughly what it takes in memory, evicting
+    // others as needed to make room. Blocks bigger than the whole cache are
+    // handed back without being cached.
+    pub fn insert(&self, file: u64, offset: u64, block: T, charge: usize) -> Arc<T> {
+        let block = Arc::new(block);
+        if self.capacity == 0 || charge > self.capacity {
+            return block;
+        }
+        let mut clock = self.clock.lock().unwrap();
+        if let Some(i) = clock.map.remove(&(file, offset)) {
+            clock.evict(i);
+        }
+        while clock.bytes + charge > self.capacity {
+            clock.evict_next();
+        }
+        let slot = Slot {
+            key: (file, offset),
+            block: block.clone(),
+            charge,
+            referenced: false,
+        };
+        let i = match clock.free.pop() {
+            Some(i) => {
+                clock.slots[i] = Some(slot);
+                i
+            }
+            None => {
+                clock.slots.push(Some(slot));
+                clock.slots.len() - 1
+            }
+        };
+        clock.map.insert((file, offset), i);
+        clock.bytes += charge;
+        block
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes: self.clock.lock().unwrap().bytes,
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T> Clock<T> {
+    // Moves the hand on until it finds a block that hasn't been read since it
+    // last came by, and evicts it. Only called while something is cached.
+    fn evict_next(&mut self) {
+        loop {
+            self.hand = (self.hand + 1) % self.slots.len();
+            let hand = self.hand;
+            match &mut self.slots[hand] {
+                Some(slot) if slot.referenced => slot.referenced = false,
+                Some(slot) => {
+                    let key = slot.key;
+                    self.map.remove(&key);
+                    self.evict(hand);
+                    return;
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn evict(&mut self, i: usize) {
+        if let Some(slot) = self.slots[i].take() {
+            self.bytes -= slot.charge;
+            self.free.push(i);
+        }
+    }
+}
+
+// Files read through a cache of raw bytes are read, and cached, this many
+// bytes at a time.
+pub const CHUNK_BYTES: u64 = 4 * 1024;
+
+// The chunk of file starting at start, which must be a multiple of
+// CHUNK_BYTES. Only for files that never change once written other than by
+// growing: a full chunk of one never changes and can be cached, while the last
+// chunk may still be filling up, so it is read every time. file_id is what the
+// cache knows file by.
+pub fn read_chunk(file: &mut File, cache: &BlockCache<Vec<u8>>, file_id: u64, start: u64) -> io::Result<Arc<Vec<u8>>> {
+    if let Some(chunk) = cache.get(file_id, start) {
+        return Ok(chunk);
+    }
+    let mut chunk = Vec::with_capacity(CHUNK_BYTES as usize);
+    file.seek(SeekFrom::Start(start))?;
+    file.take(CHUNK_BYTES).read_to_end(&mut chunk)?;
+    if chunk.len() < CHUNK_BYTES as usize {
+        return Ok(Arc::new(chunk));
+    }
+    Ok(cache.insert(file_id, start, chunk, CHUNK_BYTES as usize))
+}
+
+// A file read through read_chunk, for readers that want Read and Seek. It
+// ends where the file did when it was opened.
+pub struct CachedFile<'a> {
+    file: File,
+    len: u64,
+    pos: u64,
+    cache: &'a BlockCache<Vec<u8>>,
+    file_id: u64,
+}
+
+impl<'a> CachedFile<'a> {
+    pub fn open<P: AsRef<Path>>(path: P, cache: &'a BlockCache<Vec<u8>>, file_id: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(CachedFile { file, len, pos: 0, cache, file_id })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for CachedFile<'_> {
+    // Fills as much of buf as the file has, even across chunks, for readers
+    // that expect one read to do.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0;
+        while read < buf.len() && self.pos < self.len {
+            let start = self.pos - self.pos % CHUNK_BYTES;
+            let chunk = read_chunk(&mut self.file, self.cache, self.file_id, start)?;
+            let from = (self.pos - start) as usize;
+            let to = chunk.len().min((self.len - start) as usize);
+            if from >= to {
+                break;
+            }
+            let n = (to - from).min(buf.len() - read);
+            buf[read..read + n].copy_from_slice(&chunk[from..from + n]);
+            read += n;
+            self.pos += n as u64;
+        }
+        Ok(read)
+    }
+}
+
+impl Seek for CachedFile<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file"))?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,115 @@
+use actix_web::{error::BlockingError, http::StatusCode, HttpResponse, ResponseError};
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+// Biggest value we take in one record. Bigger request bodies are refused by
+// actix before they reach us.
+pub const MAX_VALUE_BYTES: usize = 1024 * 1024;
+pub const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+// Everything a request can fail with. Storage code returns these and the
+// handlers hand them straight back, so each maps to one status code and a
+// JSON body of {"error": kind, "message": ...}. Not every engine can hit
+// every case.
+#[derive(Debug)]
+pub enum DbError {
+    // no live value for the key
+    NotFound(String),
+    // a record or file on disk we can't make sense of
+    Corrupt(String),
+    Io(io::Error),
+    // clashes with something that already exists, e.g. a backup directory
+    Conflict(String),
+    // asks for more than this node was set up to give, e.g. r > n
+    PreconditionFailed(String),
+    TooLarge { size: usize, limit: usize },
+    // a request we can't store as sent, e.g. a key holding ':'
+    Invalid(String),
+    // writes have to go to another node
+    ReadOnly(String),
+    // the node can't answer right now: no leader, too few replicas
+    Unavailable(String),
+    // asks for something this node had once but has since dropped, e.g.
+    // replication log older than every follower needs
+    Gone(String),
+}
+
+impl DbError {
+    fn kind(&self) -> &'static str {
+        match self {
+            DbError::NotFound(_) => "not_found",
+            DbError::Corrupt(_) => "corrupt",
+            DbError::Io(_) => "io",
+            DbError::Conflict(_) => "conflict",
+            DbError::PreconditionFailed(_) => "precondition_failed",
+            DbError::TooLarge { .. } => "too_large",
+            DbError::Invalid(_) => "invalid",
+            DbError::ReadOnly(_) => "read_only",
+            DbError::Unavailable(_) => "unavailable",
+            DbError::Gone(_) => "gone",
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::NotFound(key) => write!(f, "no value for key {}", key),
+            DbError::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+            DbError::Io(e) => write!(f, "{}", e),
+            DbError::TooLarge { size, limit } => {
+                write!(f, "{} bytes is over the limit of {}", size, limit)
+            }
+            DbError::Conflict(msg)
+            | DbError::PreconditionFailed(msg)
+            | DbError::Invalid(msg)
+            | DbError::ReadOnly(msg)
+            | DbError::Unavailable(msg)
+            | DbError::Gone(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl ResponseError for DbError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DbError::NotFound(_) => StatusCode::NOT_FOUND,
+            DbError::Corrupt(_) | DbError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            DbError::Conflict(_) => StatusCode::CONFLICT,
+            DbError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            DbError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            DbError::Invalid(_) => StatusCode::BAD_REQUEST,
+            DbError::ReadOnly(_) => StatusCode::METHOD_NOT_ALLOWED,
+            DbError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            DbError::Gone(_) => StatusCode::GONE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+        }))
+    }
+}
+
+impl From<io::Error> for DbError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            // lines that aren't utf-8, or files too short to hold what they claim
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => DbError::Corrupt(e.to_string()),
+            ErrorKind::AlreadyExists => DbError::Conflict(e.to_string()),
+            ErrorKind::InvalidInput => DbError::Invalid(e.to_string()),
+            _ => DbError::Io(e),
+        }
+    }
+}
+
+impl<E: Into<DbError> + fmt::Debug> From<BlockingError<E>> for DbError {
+    fn from(e: BlockingError<E>) -> Self {
+        match e {
+            BlockingError::Error(e) => e.into(),
+            BlockingError::Canceled => DbError::Unavailable("request was cancelled".to_string()),
+        }
+    }
+}
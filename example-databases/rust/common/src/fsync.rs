@@ -0,0 +1,30 @@
+// fsync, timed, so every server reports how long its disk keeps it waiting as
+// the same histogram.
+use prometheus::{register_histogram, Histogram};
+use std::fs::File;
+use std::io::Error;
+use std::path::Path;
+use std::time::Instant;
+
+lazy_static! {
+    static ref FSYNC_SECONDS: Histogram = register_histogram!(
+        "null_fsync_duration_seconds",
+        "Time spent waiting on fsync",
+        vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]
+    )
+    .unwrap();
+}
+
+// Runs a sync_all or sync_data, recording how long the disk took.
+pub fn timed<F: FnOnce() -> Result<(), Error>>(sync: F) -> Result<(), Error> {
+    let start = Instant::now();
+    let synced = sync();
+    FSYNC_SECONDS.observe(start.elapsed().as_secs_f64());
+    synced
+}
+
+// Makes the files created, renamed or removed in dir durable. A rename is only
+// safe from a crash once the directory holding it is synced.
+pub fn sync_dir(dir: &Path) -> Result<(), Error> {
+    timed(|| File::open(dir)?.sync_all())
+}
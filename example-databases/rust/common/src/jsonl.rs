@@ -0,0 +1,135 @@
+use crate::error::{DbError, MAX_VALUE_BYTES};
+use actix_web::{web, HttpResponse};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+// One key/value pair per line of an export or import. Values that aren't
+// plain text travel as base64 so every record stays on a single line.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_base64: Option<String>,
+}
+
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+// Longest import line we buffer while waiting for its newline: room for the
+// biggest value with every character escaped, or as base64, and its key.
+pub const MAX_LINE_BYTES: usize = 4 * MAX_VALUE_BYTES;
+
+pub fn encode(key: &str, value: &str) -> String {
+    let binary = value.chars().any(|c| c.is_control() && c != '\t');
+    let record = Record {
+        key: key.to_string(),
+        value: if binary { None } else { Some(value.to_string()) },
+        value_base64: if binary { Some(base64::encode(value)) } else { None },
+    };
+    format!("{}\n", serde_json::to_string(&record).unwrap())
+}
+
+pub fn decode(line: &str) -> Result<(String, String), String> {
+    let record: Record = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let value = match (record.value, record.value_base64) {
+        (Some(value), None) => value,
+        (None, Some(encoded)) => {
+            let bytes = base64::decode(encoded).map_err(|e| e.to_string())?;
+            // every engine stores values as strings for now
+            String::from_utf8(bytes).map_err(|_| "value_base64 is not utf-8".to_string())?
+        }
+        _ => return Err("needs exactly one of value and value_base64".to_string()),
+    };
+    Ok((record.key, value))
+}
+
+// Streams records out as they are encoded rather than building the whole
+// export in memory first.
+pub fn export<I>(records: I) -> HttpResponse
+where
+    I: Iterator<Item = (String, String)> + Unpin + 'static,
+{
+    let lines = records.map(|(key, value)| Ok::<_, actix_web::Error>(web::Bytes::from(encode(&key, &value))));
+    HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .streaming(futures::stream::iter(lines))
+}
+
+// Hands out an uploaded body one line at a time as it arrives, so an import
+// never has to fit in memory.
+pub struct Lines {
+    body: web::Payload,
+    buf: Vec<u8>,
+    done: bool,
+    pub line_number: usize,
+}
+
+impl Lines {
+    pub fn new(body: web::Payload) -> Self {
+        Lines {
+            body,
+            buf: Vec::new(),
+            done: false,
+            line_number: 0,
+        }
+    }
+
+    // A line that runs past MAX_LINE_BYTES ends the import, as there's no
+    // telling where the next one starts without reading it all.
+    pub async fn next(&mut self) -> Option<Result<String, DbError>> {
+        loop {
+            let end = self.buf.iter().position(|b| *b == b'\n');
+            let size = end.unwrap_or(self.buf.len());
+            if size > MAX_LINE_BYTES {
+                self.line_number += 1;
+                self.buf = Vec::new();
+                self.done = true;
+                return Some(Err(DbError::TooLarge { size, limit: MAX_LINE_BYTES }));
+            }
+            if let Some(end) = end {
+                let line = self.buf.drain(..=end).collect::<Vec<u8>>();
+                self.line_number += 1;
+                let line = match to_line(line) {
+                    Ok(line) => line,
+                    Err(e) => return Some(Err(e)),
+                };
+                if line.is_empty() {
+                    continue;
+                }
+                return Some(Ok(line));
+            }
+            if self.done {
+                // last line without a newline
+                if self.buf.is_empty() {
+                    return None;
+                }
+                self.line_number += 1;
+                return Some(to_line(std::mem::take(&mut self.buf)));
+            }
+            match self.body.next().await {
+                Some(Ok(chunk)) => self.buf.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some(Err(DbError::Invalid(e.to_string()))),
+                None => self.done = true,
+            }
+        }
+    }
+}
+
+fn to_line(bytes: Vec<u8>) -> Result<String, DbError> {
+    match String::from_utf8(bytes) {
+        Ok(line) => Ok(line.trim().to_string()),
+        Err(_) => Err(DbError::Invalid("line is not utf-8".to_string())),
+    }
+}
+
+// What an import did, and where it stopped if it didn't finish.
+pub fn import_result(imported: usize, failed: Option<(usize, String)>) -> HttpResponse {
+    match failed {
+        None => HttpResponse::Ok().json(serde_json::json!({ "imported": imported })),
+        Some((line, error)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "imported": imported,
+            "error": format!("line {}: {}", line, error),
+        })),
+    }
+}
@@ -0,0 +1,16 @@
+//! What every null server has in common, kept in one place so a fix made
+//! here reaches all of them.
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod archive;
+pub mod backup;
+pub mod cache;
+pub mod error;
+pub mod fsync;
+pub mod jsonl;
+pub mod logging;
+pub mod metrics;
+pub mod replication;
+pub mod resp;
@@ -0,0 +1,98 @@
+use crate::metrics;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue};
+use std::future::Future;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use tracing::{info, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+
+// Clients can pass their own id to follow a request across nodes.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// RUST_LOG picks the level and filters, e.g. RUST_LOG=info,disk_log::raft=debug.
+// Logs go to stderr as text, or one JSON object per line with NULL_LOG_FORMAT=json.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    if std::env::var("NULL_LOG_FORMAT").map(|f| f == "json").unwrap_or(false) {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+pub fn request_id(given: Option<&str>) -> String {
+    match given {
+        Some(id) => id.to_string(),
+        None => format!("{:x}", NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)),
+    }
+}
+
+// One span per request, tagged with the engine answering it. Engines that
+// count their work record lines_scanned and segments on it through
+// Span::current().
+pub fn request_span(id: &str, engine: &str, method: &str, route: &str, path: &str) -> Span {
+    // the key is always the last path segment on the routes that take one
+    let key = if route.ends_with("{key}") {
+        path.rsplit('/').next().unwrap_or("")
+    } else {
+        ""
+    };
+    tracing::info_span!(
+        "request",
+        id,
+        engine,
+        method,
+        route,
+        key,
+        lines_scanned = tracing::field::Empty,
+        segments = tracing::field::Empty,
+    )
+}
+
+// The middleware every server wraps its app in, as
+// .wrap_fn(|req, srv| logging::trace_request(ENGINE, req, srv)). It runs the
+// request in its span, counts and times it, logs it once it's answered, and
+// sends its id back. The id is also set on the request, so requests relayed
+// to other nodes carry it on.
+pub fn trace_request<S, B>(
+    engine: &'static str,
+    mut req: ServiceRequest,
+    srv: &mut S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let id = request_id(req.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+    let header = HeaderValue::from_str(&id).ok();
+    if let Some(value) = &header {
+        req.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value.clone());
+    }
+    let span = request_span(&id, engine, &method, &route, req.path());
+    let start = Instant::now();
+    let call = span.in_scope(|| srv.call(req));
+    async move {
+        let mut res = call.await?;
+        let elapsed = start.elapsed();
+        metrics::observe_request(&route, &method, res.status().as_u16(), elapsed);
+        info!(
+            status = res.status().as_u16(),
+            latency_ms = elapsed.as_secs_f64() * 1000.0,
+            "request finished"
+        );
+        if let Some(value) = header {
+            res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(res)
+    }
+    .instrument(span)
+}
@@ -0,0 +1,71 @@
+// What every server reports to Prometheus about its HTTP API, and the
+// /metrics response itself. Each server registers the gauges and counters of
+// its own engine next to these, in the same default registry.
+use actix_web::HttpResponse;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounter, IntCounterVec,
+    TextEncoder,
+};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::error;
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "null_http_requests_total",
+        "HTTP requests answered, by route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    static ref HTTP_SECONDS: HistogramVec = register_histogram_vec!(
+        "null_http_request_duration_seconds",
+        "Time spent answering HTTP requests, by route and method",
+        &["route", "method"]
+    )
+    .unwrap();
+    // two scrapes at once would both add the same difference
+    static ref CATCHING_UP: Mutex<()> = Mutex::new(());
+}
+
+pub fn observe_request(route: &str, method: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS
+        .with_label_values(&[route, method, &status.to_string()])
+        .inc();
+    HTTP_SECONDS
+        .with_label_values(&[route, method])
+        .observe(elapsed.as_secs_f64());
+}
+
+// The database keeps running totals, the counters catch up to them one scrape
+// at a time. A total below its counter, say a database reopened with fresh
+// totals, leaves the counter as it is until the total passes it again, as a
+// counter can't go down.
+pub fn catch_up(counters: &IntCounterVec, totals: &[(&str, u64)]) {
+    let _catching_up = CATCHING_UP.lock().unwrap();
+    for (label, total) in totals {
+        add_difference(&counters.with_label_values(&[label]), *total);
+    }
+}
+
+// catch_up for a counter without labels.
+pub fn catch_up_one(counter: &IntCounter, total: u64) {
+    let _catching_up = CATCHING_UP.lock().unwrap();
+    add_difference(counter, total);
+}
+
+fn add_difference(counter: &IntCounter, total: u64) {
+    counter.inc_by(total.saturating_sub(counter.get()));
+}
+
+// Every registered metric, in the Prometheus text format.
+pub fn render() -> HttpResponse {
+    let mut buf = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buf) {
+        error!(error = %e, "couldn't encode metrics");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buf)
+}
@@ -0,0 +1,438 @@
+// The replication log. Every write is also appended here, so followers can
+// pull it and the archive can keep it for point-in-time recovery. Offsets into
+// it are byte offsets from the first record ever written, and stay the same
+// when older segments are dropped, so an offset is a stable position a
+// follower can resume from.
+use crate::error::DbError;
+use crate::fsync;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, Error, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::thread;
+use tracing::warn;
+
+// The log is kept in segments, "{start}.nrepl" where start is the offset of
+// the segment's first byte.
+const SEGMENT_EXTENSION: &str = "nrepl";
+
+// Appends start a new segment once the newest holds this many bytes. Only
+// whole segments are dropped, so this is how much log is kept past what
+// everyone has read.
+const SEGMENT_BYTES: u64 = 4 * 1024 * 1024;
+
+// The log as it was kept before it was split into segments, taken as the
+// segment starting at 0.
+const UNSEGMENTED_LOG: &str = "null.replication";
+
+// Where a follower remembers how far into the leader's log it has applied, in
+// its data directory.
+const FOLLOWER_OFFSET: &str = "null.follower";
+
+// What a follower calls itself when it pulls, made up the first time and kept
+// in its data directory.
+const FOLLOWER_ID: &str = "null.follower-id";
+
+// Where a leader keeps how far each follower has applied its log, so it
+// knows what it can drop.
+const FOLLOWERS: &str = "null.followers";
+
+// Leader never ships more than this per pull, followers just ask again.
+pub const MAX_BATCH_BYTES: u64 = 64 * 1024;
+
+// Header the leader uses to tell the follower how long its log is right now.
+pub const LOG_END_HEADER: &str = "x-null-log-end";
+
+// Header a follower names itself with when it pulls.
+pub const FOLLOWER_HEADER: &str = "x-null-follower";
+
+lazy_static! {
+    // followers pull in parallel, but their offsets share one file
+    static ref ACKNOWLEDGING: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct FollowerStatus {
+    pub leader: String,
+    pub applied_offset: u64,
+    pub leader_log_end: u64,
+    pub lag_bytes: u64,
+    pub last_contact_unix_secs: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+struct Segment {
+    start: u64,
+    path: PathBuf,
+}
+
+// Records are "ts:key:value", ts in unix millis, so the log can be replayed
+// up to a moment in time. Logs written before timestamps were added hold plain
+// "key:value" records.
+pub fn append_at(ts: u64, key: &str, value: &str) -> Result<(), Error> {
+    let segments = segments()?;
+    let path = match segments.last() {
+        Some(newest) => {
+            let len = std::fs::metadata(&newest.path)?.len();
+            if len >= SEGMENT_BYTES {
+                segment_path(newest.start + len)
+            } else {
+                newest.path.clone()
+            }
+        }
+        None => segment_path(0),
+    };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}:{}:{}", ts, key, value)
+}
+
+// Splits a record into its timestamp, if it has one, key and value.
+pub fn parse_record(record: &str) -> Option<(Option<u64>, &str, &str)> {
+    let split = record.split(':').collect::<Vec<&str>>();
+    match split.len() {
+        3 => Some((Some(split[0].parse().ok()?), split[1], split[2])),
+        2 => Some((None, split[0], split[1])),
+        _ => None,
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+// The offset of the oldest record still kept.
+pub fn log_start() -> u64 {
+    match segments() {
+        Ok(segments) => segments.first().map(|s| s.start).unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+pub fn log_end() -> u64 {
+    segments().map(|segments| end_of(&segments)).unwrap_or(0)
+}
+
+// Reads whole records starting at offset. Returns the chunk and the log length
+// at the time of the read. A chunk never ends in the middle of a line. Gone
+// when the records at offset have already been dropped.
+pub fn read_from(offset: u64) -> Result<(String, u64), DbError> {
+    let segments = segments()?;
+    let end = end_of(&segments);
+    if offset >= end {
+        return Ok((String::new(), end));
+    }
+    // segments are searched newest first, one that starts at or before offset
+    // is there unless offset is before the oldest
+    let segment = match segments.iter().rev().find(|s| s.start <= offset) {
+        Some(segment) => segment,
+        None => {
+            return Err(DbError::Gone(format!(
+                "log starts at {}, records before it have been dropped",
+                segments[0].start
+            )))
+        }
+    };
+
+    let mut file = File::open(&segment.path)?;
+    file.seek(SeekFrom::Start(offset - segment.start))?;
+    let mut buf = Vec::new();
+    file.take(MAX_BATCH_BYTES).read_to_end(&mut buf)?;
+
+    match buf.iter().rposition(|b| *b == b'\n') {
+        Some(last) => buf.truncate(last + 1),
+        // a single record bigger than a batch, ship it whole
+        None => {
+            let mut file = File::open(&segment.path)?;
+            file.seek(SeekFrom::Start(offset - segment.start))?;
+            buf.clear();
+            BufReader::new(file).read_until(b'\n', &mut buf)?;
+        }
+    }
+
+    Ok((String::from_utf8_lossy(&buf).to_string(), end))
+}
+
+// Copies the log between two offsets into to.
+pub fn copy_range<W: Write>(start: u64, end: u64, to: &mut W) -> Result<(), Error> {
+    let segments = segments()?;
+    if start >= end {
+        return Ok(());
+    }
+    match segments.first() {
+        Some(oldest) if oldest.start <= start => {}
+        _ => return Err(Error::new(ErrorKind::NotFound, format!("log no longer holds {}", start))),
+    }
+    for (i, segment) in segments.iter().enumerate() {
+        let segment_end = segments.get(i + 1).map(|next| next.start).unwrap_or(u64::MAX);
+        if segment_end <= start || segment.start >= end {
+            continue;
+        }
+        let from = start.max(segment.start);
+        let mut file = File::open(&segment.path)?;
+        file.seek(SeekFrom::Start(from - segment.start))?;
+        std::io::copy(&mut file.take(end.min(segment_end) - from), to)?;
+    }
+    Ok(())
+}
+
+// Drops every segment that ends at or before offset. The newest segment is
+// always kept, it is where the next record goes.
+pub fn drop_before(offset: u64) -> Result<usize, Error> {
+    let segments = segments()?;
+    let mut dropped = 0;
+    for pair in segments.windows(2) {
+        if pair[1].start > offset {
+            break;
+        }
+        std::fs::remove_file(&pair[0].path)?;
+        dropped += 1;
+    }
+    if dropped > 0 {
+        fsync::sync_dir(Path::new("."))?;
+    }
+    Ok(dropped)
+}
+
+// Throws the log away and starts it again, empty, at offset. A restored
+// backup carries on from the offset it was taken at, so archived records
+// replayed on top of it land where they were first written.
+pub fn restart_at(offset: u64) -> Result<(), Error> {
+    for segment in segments()? {
+        std::fs::remove_file(segment.path)?;
+    }
+    let file = File::create(segment_path(offset))?;
+    fsync::timed(|| file.sync_all())?;
+    fsync::sync_dir(Path::new("."))
+}
+
+// Drops whatever every follower has applied and, when there is an archive,
+// the archive holds too. Followers seen pulling before are waited for even
+// while they are down, until they are forgotten.
+pub fn drop_acknowledged(archived: Option<u64>) -> Result<usize, Error> {
+    let followers = followers();
+    let cutoff = followers
+        .values()
+        .copied()
+        .chain(archived)
+        .min()
+        .unwrap_or_else(log_end);
+    drop_before(cutoff)
+}
+
+// How far each follower that has pulled from us has applied.
+pub fn followers() -> BTreeMap<String, u64> {
+    std::fs::read(FOLLOWERS)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+// Notes that follower has applied everything before offset, which it says by
+// pulling from there.
+pub fn acknowledge(follower: &str, offset: u64) -> Result<(), Error> {
+    let _acknowledging = ACKNOWLEDGING.lock().unwrap();
+    let mut followers = followers();
+    if followers.get(follower) == Some(&offset) {
+        return Ok(());
+    }
+    followers.insert(follower.to_string(), offset);
+    store_followers(&followers)
+}
+
+// Stops keeping log for a follower that is gone for good. False when we
+// never heard from it.
+pub fn forget(follower: &str) -> Result<bool, Error> {
+    let _acknowledging = ACKNOWLEDGING.lock().unwrap();
+    let mut followers = followers();
+    if followers.remove(follower).is_none() {
+        return Ok(false);
+    }
+    store_followers(&followers)?;
+    Ok(true)
+}
+
+fn store_followers(followers: &BTreeMap<String, u64>) -> Result<(), Error> {
+    replace_file(Path::new("."), FOLLOWERS, &serde_json::to_vec(followers)?)
+}
+
+// Writes name in dir whole or not at all: a crash leaves either the old
+// contents or the new ones, never part of either.
+fn replace_file(dir: &Path, name: &str, contents: &[u8]) -> Result<(), Error> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        fsync::timed(|| file.sync_all())?;
+    }
+    std::fs::rename(tmp, dir.join(name))?;
+    fsync::sync_dir(dir)
+}
+
+// The log segments oldest first. A log from before segments is renamed into
+// the first one.
+fn segments() -> Result<Vec<Segment>, Error> {
+    if Path::new(UNSEGMENTED_LOG).exists() && !segment_path(0).exists() {
+        std::fs::rename(UNSEGMENTED_LOG, segment_path(0))?;
+    }
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(".")? {
+        let path = entry?.path();
+        if path.extension().and_then(OsStr::to_str) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(start) = path.file_stem().and_then(OsStr::to_str).and_then(|s| s.parse().ok()) {
+            segments.push(Segment { start, path });
+        }
+    }
+    segments.sort_by_key(|s| s.start);
+    Ok(segments)
+}
+
+fn end_of(segments: &[Segment]) -> u64 {
+    match segments.last() {
+        Some(newest) => newest.start + std::fs::metadata(&newest.path).map(|m| m.len()).unwrap_or(0),
+        None => 0,
+    }
+}
+
+fn segment_path(start: u64) -> PathBuf {
+    PathBuf::from(format!("{:020}.{}", start, SEGMENT_EXTENSION))
+}
+
+// How far the node with its data in dir has applied its leader's log, if it
+// has ever followed one.
+pub fn applied_offset(dir: &Path) -> Option<u64> {
+    std::fs::read_to_string(dir.join(FOLLOWER_OFFSET))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+}
+
+fn store_offset(dir: &Path, offset: u64) -> Result<(), Error> {
+    replace_file(dir, FOLLOWER_OFFSET, offset.to_string().as_bytes())
+}
+
+// Where to start following from next time, or None to forget we ever did.
+pub fn set_applied_offset(dir: &Path, offset: Option<u64>) -> Result<(), Error> {
+    match offset {
+        Some(offset) => store_offset(dir, offset),
+        None => match std::fs::remove_file(dir.join(FOLLOWER_OFFSET)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+fn follower_id(dir: &Path) -> Result<String, Error> {
+    if let Ok(id) = std::fs::read_to_string(dir.join(FOLLOWER_ID)) {
+        return Ok(id.trim().to_string());
+    }
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    let id = format!("{:x}-{:x}", nanos, std::process::id());
+    replace_file(dir, FOLLOWER_ID, id.as_bytes())?;
+    Ok(id)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+// Pulls the leader's log forever, applying each record in order. `apply` is
+// handed key and value and must make the write durable before returning, as
+// the offset past it is stored in dir straight after.
+pub fn start_following<F>(
+    dir: &Path,
+    leader: String,
+    status: Arc<RwLock<FollowerStatus>>,
+    apply: F,
+) -> Result<(), Error>
+where
+    F: Fn(&str, &str) -> Result<(), Error> + Send + 'static,
+{
+    let dir = dir.to_path_buf();
+    let id = follower_id(&dir)?;
+    {
+        let mut s = status.write().unwrap();
+        s.leader = leader.clone();
+        s.applied_offset = applied_offset(&dir).unwrap_or(0);
+    }
+
+    thread::spawn(move || {
+        let client = reqwest::blocking::Client::new();
+        loop {
+            let offset = status.read().unwrap().applied_offset;
+            match pull(&client, &dir, &leader, &id, offset, &apply) {
+                Ok((applied, leader_end)) => {
+                    let mut s = status.write().unwrap();
+                    s.applied_offset = applied;
+                    s.leader_log_end = leader_end;
+                    s.lag_bytes = leader_end.saturating_sub(applied);
+                    s.last_contact_unix_secs = Some(now_secs());
+                    s.last_error = None;
+                    // caught up, no rush to ask again
+                    if applied >= leader_end {
+                        drop(s);
+                        thread::sleep(Duration::from_millis(500));
+                    }
+                }
+                Err(e) => {
+                    warn!(leader = %leader, error = %e, "replication pull failed");
+                    status.write().unwrap().last_error = Some(e);
+                    thread::sleep(Duration::from_secs(2));
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn pull<F>(
+    client: &reqwest::blocking::Client,
+    dir: &Path,
+    leader: &str,
+    id: &str,
+    offset: u64,
+    apply: &F,
+) -> Result<(u64, u64), String>
+where
+    F: Fn(&str, &str) -> Result<(), Error>,
+{
+    let resp = client
+        .get(format!("http://{}/_replication/{}", leader, offset))
+        .header(FOLLOWER_HEADER, id)
+        .send()
+        .map_err(|e| e.to_string())?;
+    if resp.status() == reqwest::StatusCode::GONE {
+        return Err(format!(
+            "leader has dropped its log from {}, restore this node from a backup of the leader",
+            offset
+        ));
+    }
+    if !resp.status().is_success() {
+        return Err(format!("leader answered {}", resp.status()));
+    }
+    let leader_end = resp
+        .headers()
+        .get(LOG_END_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(offset);
+    let body = resp.text().map_err(|e| e.to_string())?;
+
+    let mut applied = offset;
+    for line in body.split_inclusive('\n') {
+        let record = line.trim_end_matches('\n');
+        if let Some((_, key, value)) = parse_record(record) {
+            apply(key, value).map_err(|e| e.to_string())?;
+        }
+        applied += line.len() as u64;
+        // persist as we go so a crash never re-applies out of order
+        store_offset(dir, applied).map_err(|e| e.to_string())?;
+    }
+
+    Ok((applied, leader_end))
+}
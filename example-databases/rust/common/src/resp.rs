@@ -0,0 +1,601 @@
+// A Redis RESP2 listener next to the HTTP one, so redis-cli and Redis client
+// libraries can talk to the store. It speaks just enough Redis for keys and
+// values: GET, SET with EX/NX/XX, DEL, EXISTS, SCAN, PING, INFO and
+// MULTI/EXEC/DISCARD, which turns into one batch write on the store.
+//
+// Expiry lives here rather than in the store. Keys set with EX over RESP
+// disappear once their time is up, whichever API reads them, but the deadlines
+// are only kept in memory and are lost on restart.
+use crate::error::{DbError, MAX_BODY_BYTES};
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span};
+
+// the longest line we read before deciding the client isn't speaking RESP
+const MAX_LINE_BYTES: u64 = 64 * 1024;
+// Far more than any command we answer takes. The vector for them grows as
+// they arrive, so a client can't make us reserve room for this many up front.
+const MAX_ARGS: usize = 4096;
+// how often keys past their deadline are deleted when nobody reads them
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+const SCAN_COUNT: usize = 10;
+
+lazy_static! {
+    static ref RESP_COMMANDS: IntCounterVec = register_int_counter_vec!(
+        "null_resp_commands_total",
+        "RESP commands answered, by command and whether they succeeded",
+        &["command", "result"]
+    )
+    .unwrap();
+}
+
+// What the listener needs from the server's storage.
+pub trait Store: Send + Sync + 'static {
+    // None when the key was never written or has been deleted.
+    fn get(&self, key: &str) -> Result<Option<String>, DbError>;
+    // Applies every write so readers see all of them or none. A value of
+    // None deletes the key. Counting the bytes written is up to the store.
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError>;
+    fn keys(&self) -> Result<Vec<String>, DbError>;
+    // The same rules the HTTP API holds records to.
+    fn check(&self, key: &str, value: &str) -> Result<(), DbError>;
+}
+
+pub struct Server<S> {
+    store: Arc<S>,
+    // which engine answers, for INFO
+    engine: &'static str,
+    port: u16,
+    started: Instant,
+    clients: AtomicUsize,
+    // Held for the whole of every command, so SET NX/XX and EXEC see nothing
+    // change under them. Writes over HTTP don't take it.
+    deadlines: Mutex<HashMap<String, Instant>>,
+}
+
+// Binds the listener, then answers every connection on its own thread.
+pub fn start<S: Store>(port: u16, store: Arc<S>, engine: &'static str) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    info!(port, "RESP listening");
+    let server = Arc::new(Server {
+        store,
+        engine,
+        port,
+        started: Instant::now(),
+        clients: AtomicUsize::new(0),
+        deadlines: Mutex::new(HashMap::new()),
+    });
+
+    let sweeper = server.clone();
+    thread::spawn(move || loop {
+        thread::sleep(SWEEP_EVERY);
+        sweeper.sweep();
+    });
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!(error = %e, "couldn't accept RESP connection");
+                    continue;
+                }
+            };
+            let server = server.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                let span = info_span!("resp", peer = %peer);
+                let _enter = span.enter();
+                server.clients.fetch_add(1, Ordering::Relaxed);
+                if let Err(e) = server.serve(stream) {
+                    debug!(error = %e, "RESP connection closed");
+                }
+                server.clients.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+    });
+    Ok(())
+}
+
+impl<S: Store> Server<S> {
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut out = BufWriter::new(stream);
+        // the commands queued since MULTI, and whether any of them was refused
+        let mut queued: Option<(Vec<Vec<String>>, bool)> = None;
+        // the keys SCAN is paging through, sorted once when it starts
+        let mut scan: Option<Vec<String>> = None;
+        loop {
+            // answer pipelined commands together, before waiting on the client
+            if reader.buffer().is_empty() {
+                out.flush()?;
+            }
+            let args = match read_command(&mut reader) {
+                Ok(Some(args)) => args,
+                Ok(None) => return out.flush(),
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    Reply::Error(format!("ERR Protocol error: {}", e)).write_to(&mut out)?;
+                    return out.flush();
+                }
+                Err(e) => return Err(e),
+            };
+            if args.is_empty() {
+                continue;
+            }
+            let name = args[0].to_uppercase();
+            debug!(command = %name, "RESP command");
+
+            let reply = match (name.as_str(), queued.as_mut()) {
+                ("MULTI", Some(_)) => Reply::Error("ERR MULTI calls can not be nested".to_string()),
+                ("MULTI", None) => {
+                    queued = Some((Vec::new(), false));
+                    Reply::Ok
+                }
+                ("EXEC", None) => Reply::Error("ERR EXEC without MULTI".to_string()),
+                ("EXEC", Some(_)) => {
+                    let (commands, refused) = queued.take().unwrap();
+                    if refused {
+                        Reply::Error("EXECABORT Transaction discarded because of previous errors.".to_string())
+                    } else {
+                        self.run(&commands, true, &mut scan)
+                    }
+                }
+                ("DISCARD", None) => Reply::Error("ERR DISCARD without MULTI".to_string()),
+                ("DISCARD", Some(_)) => {
+                    queued = None;
+                    Reply::Ok
+                }
+                ("QUIT", _) => {
+                    Reply::Ok.write_to(&mut out)?;
+                    return out.flush();
+                }
+                (_, Some((commands, refused))) => match check_arity(&name, args.len()) {
+                    Ok(()) => {
+                        commands.push(args);
+                        Reply::Status("QUEUED")
+                    }
+                    Err(e) => {
+                        *refused = true;
+                        Reply::Error(e)
+                    }
+                },
+                (_, None) => match check_arity(&name, args.len()) {
+                    Ok(()) => self.run(&[args], false, &mut scan),
+                    Err(e) => Reply::Error(e),
+                },
+            };
+            observe_command(&name, !matches!(reply, Reply::Error(_)));
+            reply.write_to(&mut out)?;
+        }
+    }
+
+    // Runs commands against one view of the store, then writes everything they
+    // changed as a single batch. Answers EXEC with every command's reply, or
+    // a lone command with its own.
+    fn run(&self, commands: &[Vec<String>], exec: bool, scan: &mut Option<Vec<String>>) -> Reply {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let mut tx = Tx {
+            server: self,
+            deadlines: &deadlines,
+            scan,
+            now: Instant::now(),
+            overlay: HashMap::new(),
+            expiring: HashMap::new(),
+            writes: Vec::new(),
+        };
+        let mut replies = Vec::new();
+        for args in commands {
+            let name = args[0].to_uppercase();
+            replies.push(tx.command(&name, &args[1..]).unwrap_or_else(|e| {
+                Reply::Error(format!("ERR {}", e.to_string().replace(['\r', '\n'], " ")))
+            }));
+        }
+
+        let Tx { writes, expiring, .. } = tx;
+        if !writes.is_empty() {
+            if let Err(e) = self.store.write_batch(&writes) {
+                error!(error = %e, "couldn't write RESP batch");
+                return Reply::Error(format!("ERR {}", e));
+            }
+        }
+        for (key, deadline) in expiring {
+            match deadline {
+                Some(deadline) => deadlines.insert(key, deadline),
+                None => deadlines.remove(&key),
+            };
+        }
+
+        if exec {
+            Reply::Array(replies)
+        } else {
+            replies.pop().unwrap_or(Reply::Nil)
+        }
+    }
+
+    // Deletes the keys whose deadlines have passed.
+    fn sweep(&self) {
+        let mut deadlines = self.deadlines.lock().unwrap();
+        let now = Instant::now();
+        let expired = deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(key, _)| (key.clone(), None))
+            .collect::<Vec<(String, Option<String>)>>();
+        if expired.is_empty() {
+            return;
+        }
+        match self.store.write_batch(&expired) {
+            Ok(()) => {
+                for (key, _) in &expired {
+                    deadlines.remove(key);
+                }
+                debug!(keys = expired.len(), "expired keys");
+            }
+            Err(e) => error!(error = %e, "couldn't delete expired keys"),
+        }
+    }
+}
+
+// The store as the commands in one run see it: their own writes on top of what
+// is stored, with keys past their deadline gone.
+struct Tx<'a, S> {
+    server: &'a Server<S>,
+    deadlines: &'a HashMap<String, Instant>,
+    // the connection's SCAN in progress
+    scan: &'a mut Option<Vec<String>>,
+    now: Instant,
+    overlay: HashMap<String, Option<String>>,
+    // deadlines set (Some) or cleared (None) by this run
+    expiring: HashMap<String, Option<Instant>>,
+    writes: Vec<(String, Option<String>)>,
+}
+
+impl<'a, S: Store> Tx<'a, S> {
+    fn expired(&self, key: &str) -> bool {
+        let deadline = match self.expiring.get(key) {
+            Some(deadline) => *deadline,
+            None => self.deadlines.get(key).cloned(),
+        };
+        deadline.is_some_and(|deadline| deadline <= self.now)
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<String>, DbError> {
+        if self.expired(key) {
+            self.set(key, None, None);
+            return Ok(None);
+        }
+        match self.overlay.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.server.store.get(key),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: Option<String>, deadline: Option<Instant>) {
+        self.overlay.insert(key.to_string(), value.clone());
+        self.expiring.insert(key.to_string(), deadline);
+        self.writes.push((key.to_string(), value));
+    }
+
+    // Every live key, in no particular order.
+    fn keys(&mut self) -> Result<Vec<String>, DbError> {
+        let mut keys = self.server.store.keys()?;
+        keys.retain(|key| !self.overlay.contains_key(key));
+        keys.extend(
+            self.overlay
+                .iter()
+                .filter(|(_, value)| value.is_some())
+                .map(|(key, _)| key.clone()),
+        );
+        keys.retain(|key| !self.expired(key));
+        Ok(keys)
+    }
+
+    fn command(&mut self, name: &str, args: &[String]) -> Result<Reply, DbError> {
+        match name {
+            "PING" => Ok(match args.first() {
+                Some(message) => Reply::Bulk(message.clone()),
+                None => Reply::Status("PONG"),
+            }),
+            "GET" => Ok(self.get(&args[0])?.map_or(Reply::Nil, Reply::Bulk)),
+            "SET" => self.set_command(args),
+            "DEL" => {
+                let mut deleted = 0;
+                for key in args {
+                    if self.get(key)?.is_some() {
+                        self.set(key, None, None);
+                        deleted += 1;
+                    }
+                }
+                Ok(Reply::Integer(deleted))
+            }
+            "EXISTS" => {
+                let mut found = 0;
+                for key in args {
+                    if self.get(key)?.is_some() {
+                        found += 1;
+                    }
+                }
+                Ok(Reply::Integer(found))
+            }
+            "SCAN" => self.scan_command(args),
+            "INFO" => self.info_command(),
+            // redis-cli asks for command docs when it starts, and copes with none
+            "COMMAND" => Ok(Reply::Array(Vec::new())),
+            _ => unreachable!("check_arity lets through known commands only"),
+        }
+    }
+
+    // SET key value [EX seconds] [NX|XX]
+    fn set_command(&mut self, args: &[String]) -> Result<Reply, DbError> {
+        let (key, value) = (&args[0], &args[1]);
+        let mut deadline = None;
+        let mut only_if = None;
+        let mut options = args[2..].iter();
+        while let Some(option) = options.next() {
+            match option.to_uppercase().as_str() {
+                "EX" if deadline.is_none() => {
+                    let seconds = options
+                        .next()
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .filter(|s| *s > 0)
+                        .ok_or_else(|| DbError::Invalid("invalid expire time in 'set' command".to_string()))?;
+                    // a deadline past what Instant can hold is refused, not a panic
+                    // that would poison the deadlines for every other client
+                    deadline = Some(
+                        self.now
+                            .checked_add(Duration::from_secs(seconds))
+                            .ok_or_else(|| DbError::Invalid("invalid expire time in 'set' command".to_string()))?,
+                    );
+                }
+                "NX" if only_if.is_none() => only_if = Some(false),
+                "XX" if only_if.is_none() => only_if = Some(true),
+                _ => return Err(DbError::Invalid("syntax error".to_string())),
+            }
+        }
+        self.server.store.check(key, value)?;
+
+        if let Some(exists) = only_if {
+            if self.get(key)?.is_some() != exists {
+                return Ok(Reply::Nil);
+            }
+        }
+        self.set(key, Some(value.clone()), deadline);
+        Ok(Reply::Ok)
+    }
+
+    // SCAN cursor [MATCH pattern] [COUNT count]. Cursor 0 sorts the live keys
+    // once for the connection and later cursors are offsets into them, so a
+    // scan sees the keys there when it started. Keys deleted since can still
+    // turn up and new ones are missed, which Redis allows. A cursor with no
+    // scan behind it, e.g. from another connection, starts over from the keys
+    // there now.
+    fn scan_command(&mut self, args: &[String]) -> Result<Reply, DbError> {
+        let cursor = args[0]
+            .parse::<usize>()
+            .map_err(|_| DbError::Invalid("invalid cursor".to_string()))?;
+        let mut pattern = None;
+        let mut count = SCAN_COUNT;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            match (option.to_uppercase().as_str(), options.next()) {
+                ("MATCH", Some(p)) => pattern = Some(p.clone()),
+                ("COUNT", Some(c)) => {
+                    count = c
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|c| *c > 0)
+                        .ok_or_else(|| DbError::Invalid("value is not an integer or out of range".to_string()))?;
+                }
+                _ => return Err(DbError::Invalid("syntax error".to_string())),
+            }
+        }
+
+        if cursor == 0 || self.scan.is_none() {
+            let mut keys = self.keys()?;
+            keys.sort();
+            *self.scan = Some(keys);
+        }
+        let keys = self.scan.as_ref().unwrap();
+        let end = cursor.saturating_add(count).min(keys.len());
+        let page = keys.get(cursor..end).unwrap_or(&[]).to_vec();
+        let next = if end >= keys.len() { 0 } else { end };
+        if next == 0 {
+            *self.scan = None;
+        }
+        Ok(Reply::Scan { next, page, pattern })
+    }
+
+    fn info_command(&mut self) -> Result<Reply, DbError> {
+        let server = self.server;
+        let keys = self.keys()?.len();
+        let expires = self.deadlines.len();
+        let lines = [
+            "# Server".to_string(),
+            // what clients that check versions see, SCAN arrived in 2.8
+            "redis_version:2.8.0".to_string(),
+            format!("null_version:{}", env!("CARGO_PKG_VERSION")),
+            format!("null_engine:{}", server.engine),
+            format!("tcp_port:{}", server.port),
+            format!("uptime_in_seconds:{}", server.started.elapsed().as_secs()),
+            String::new(),
+            "# Clients".to_string(),
+            format!("connected_clients:{}", server.clients.load(Ordering::Relaxed)),
+            String::new(),
+            "# Keyspace".to_string(),
+            format!("db0:keys={},expires={},avg_ttl=0", keys, expires),
+        ];
+        Ok(Reply::Bulk(lines.join("\r\n") + "\r\n"))
+    }
+}
+
+fn observe_command(command: &str, ok: bool) {
+    // anything a client sends would otherwise become a label
+    let command = match command {
+        "PING" | "GET" | "SET" | "DEL" | "EXISTS" | "SCAN" | "INFO" | "COMMAND" | "MULTI" | "EXEC"
+        | "DISCARD" => command,
+        _ => "unknown",
+    };
+    RESP_COMMANDS
+        .with_label_values(&[command, if ok { "ok" } else { "error" }])
+        .inc();
+}
+
+// Turns away unknown commands and wrong argument counts before they run or
+// are queued, the way Redis does.
+fn check_arity(name: &str, args: usize) -> Result<(), String> {
+    // counting the command itself, and None for no upper bound
+    let (min, max) = match name {
+        "PING" => (1, Some(2)),
+        "GET" => (2, Some(2)),
+        "SET" => (3, None),
+        "DEL" | "EXISTS" => (2, None),
+        "SCAN" => (2, None),
+        "INFO" => (1, Some(2)),
+        "COMMAND" => (1, None),
+        _ => return Err(format!("ERR unknown command '{}'", name)),
+    };
+    if args < min || max.is_some_and(|max| args > max) {
+        return Err(format!("ERR wrong number of arguments for '{}' command", name.to_lowercase()));
+    }
+    Ok(())
+}
+
+enum Reply {
+    Ok,
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Nil,
+    Array(Vec<Reply>),
+    // A page of SCAN keys, only matched against the pattern when written out,
+    // once the deadlines aren't held any more.
+    Scan { next: usize, page: Vec<String>, pattern: Option<String> },
+}
+
+impl Reply {
+    fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Reply::Ok => out.write_all(b"+OK\r\n"),
+            Reply::Status(status) => write!(out, "+{}\r\n", status),
+            Reply::Error(message) => write!(out, "-{}\r\n", message),
+            Reply::Integer(n) => write!(out, ":{}\r\n", n),
+            Reply::Bulk(value) => write!(out, "${}\r\n{}\r\n", value.len(), value),
+            Reply::Nil => out.write_all(b"$-1\r\n"),
+            Reply::Array(replies) => {
+                write!(out, "*{}\r\n", replies.len())?;
+                for reply in replies {
+                    reply.write_to(out)?;
+                }
+                Ok(())
+            }
+            Reply::Scan { next, page, pattern } => {
+                let page = page
+                    .iter()
+                    .filter(|key| pattern.as_ref().is_none_or(|p| glob_match(p.as_bytes(), key.as_bytes())))
+                    .map(|key| Reply::Bulk(key.clone()))
+                    .collect();
+                Reply::Array(vec![Reply::Bulk(next.to_string()), Reply::Array(page)]).write_to(out)
+            }
+        }
+    }
+}
+
+// One command, either as an array of bulk strings or as an inline line of
+// words the way telnet sends it. None once the client hangs up.
+fn read_command(reader: &mut impl BufRead) -> io::Result<Option<Vec<String>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    if !line.starts_with('*') {
+        return Ok(Some(line.split_whitespace().map(String::from).collect()));
+    }
+
+    let count = parse_length(&line[1..], MAX_ARGS)?;
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let header = read_line(reader)?.ok_or_else(|| protocol_error("unexpected end of command"))?;
+        if !header.starts_with('$') {
+            return Err(protocol_error(&format!("expected '$', got '{}'", header)));
+        }
+        let len = parse_length(&header[1..], MAX_BODY_BYTES)?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg)?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string not followed by CRLF"));
+        }
+        arg.truncate(len);
+        args.push(String::from_utf8(arg).map_err(|_| protocol_error("arguments must be UTF-8"))?);
+    }
+    Ok(Some(args))
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.take(MAX_LINE_BYTES).read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        return Err(protocol_error("line too long"));
+    }
+    let line = String::from_utf8(line).map_err(|_| protocol_error("lines must be UTF-8"))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn parse_length(s: &str, limit: usize) -> io::Result<usize> {
+    match s.parse::<usize>() {
+        Ok(n) if n <= limit => Ok(n),
+        _ => Err(protocol_error(&format!("invalid length '{}'", s))),
+    }
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Redis glob patterns, less character classes: * for any run of characters,
+// ? for any one, and \ to match the next character as is. On a mismatch only
+// the last * is tried again, one character further on: what the stars before
+// it matched can't change whether the rest matches, so this takes time in
+// proportion to the pattern times the key, however many stars there are.
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // just past the last * seen, and where in s it stopped matching
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        // how much of the pattern matches s[i], if it does
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p + 1, i));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == s[i]).then_some(2),
+            Some(c) => (*c == s[i]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(step), _) => {
+                p += step;
+                i += 1;
+            }
+            // the last * takes one more character and the rest goes again
+            (None, Some((after, from))) => {
+                star = Some((after, from + 1));
+                p = after;
+                i = from + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
@@ -0,0 +1,53 @@
+use actix_web::dev::Body;
+use actix_web::error::BlockingError;
+use actix_web::ResponseError;
+use null_common::error::DbError;
+use serde_json::Value;
+use std::io::{self, ErrorKind};
+
+// The status and JSON body a handler returning e sends back.
+fn response(e: DbError) -> (u16, Value) {
+    let mut resp = e.error_response();
+    let body = match resp.take_body().as_ref() {
+        Some(Body::Bytes(bytes)) => serde_json::from_slice(bytes).unwrap(),
+        _ => panic!("expected a JSON body"),
+    };
+    (resp.status().as_u16(), body)
+}
+
+#[test]
+fn every_error_has_its_own_status_and_kind() {
+    let cases = vec![
+        (DbError::NotFound("k".to_string()), 404, "not_found", "no value for key k"),
+        (DbError::Corrupt("bad line".to_string()), 500, "corrupt", "corrupt data: bad line"),
+        (DbError::Io(io::Error::other("disk gone")), 500, "io", "disk gone"),
+        (DbError::Conflict("taken".to_string()), 409, "conflict", "taken"),
+        (DbError::PreconditionFailed("r > n".to_string()), 412, "precondition_failed", "r > n"),
+        (DbError::TooLarge { size: 10, limit: 5 }, 413, "too_large", "10 bytes is over the limit of 5"),
+        (DbError::Invalid("has ':'".to_string()), 400, "invalid", "has ':'"),
+        (DbError::ReadOnly("follower".to_string()), 405, "read_only", "follower"),
+        (DbError::Unavailable("no leader".to_string()), 503, "unavailable", "no leader"),
+        (DbError::Gone("log dropped".to_string()), 410, "gone", "log dropped"),
+    ];
+    for (e, status, kind, message) in cases {
+        let (got, body) = response(e);
+        assert_eq!(got, status, "{}", kind);
+        assert_eq!(body["error"], kind);
+        assert_eq!(body["message"], message);
+    }
+}
+
+#[test]
+fn io_errors_map_by_kind() {
+    let kind_of = |kind: ErrorKind| response(DbError::from(io::Error::new(kind, "x"))).1["error"].clone();
+    assert_eq!(kind_of(ErrorKind::InvalidData), "corrupt");
+    assert_eq!(kind_of(ErrorKind::UnexpectedEof), "corrupt");
+    assert_eq!(kind_of(ErrorKind::AlreadyExists), "conflict");
+    assert_eq!(kind_of(ErrorKind::InvalidInput), "invalid");
+    assert_eq!(kind_of(ErrorKind::PermissionDenied), "io");
+
+    let blocked: DbError = BlockingError::Error(DbError::Gone("x".to_string())).into();
+    assert_eq!(response(blocked).0, 410);
+    let cancelled: DbError = BlockingError::<DbError>::Canceled.into();
+    assert_eq!(response(cancelled).0, 503);
+}
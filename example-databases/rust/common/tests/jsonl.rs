@@ -0,0 +1,41 @@
+use actix_web::{rt::System, test::TestRequest, web};
+use null_common::error::DbError;
+use null_common::jsonl::{Lines, MAX_LINE_BYTES};
+
+// Every line of body, or the error reading it.
+fn read_lines(body: Vec<u8>) -> Vec<Result<String, DbError>> {
+    let (_, payload) = TestRequest::default().set_payload(body).to_http_parts();
+    let mut lines = Lines::new(web::Payload(payload));
+    System::new("jsonl").block_on(async move {
+        let mut read = Vec::new();
+        while let Some(line) = lines.next().await {
+            read.push(line);
+        }
+        read
+    })
+}
+
+#[test]
+fn lines_are_read_one_at_a_time() {
+    let lines = read_lines(b"{\"a\":1}\n\n  {\"b\":2}  \n{\"c\":3}".to_vec());
+    let lines: Vec<String> = lines.into_iter().map(Result::unwrap).collect();
+    assert_eq!(lines, ["{\"a\":1}", "{\"b\":2}", "{\"c\":3}"]);
+}
+
+#[test]
+fn line_past_the_limit_ends_the_import() {
+    let mut body = b"{\"a\":1}\n".to_vec();
+    body.extend(vec![b'x'; MAX_LINE_BYTES + 1]);
+    body.extend(b"\n{\"b\":2}\n");
+    let lines = read_lines(body);
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].is_ok());
+    assert!(matches!(lines[1], Err(DbError::TooLarge { limit: MAX_LINE_BYTES, .. })));
+}
+
+#[test]
+fn line_that_isnt_utf8_is_invalid() {
+    let lines = read_lines(b"{\"a\":1}\n{\"b\":\"\xff\"}\n".to_vec());
+    assert!(lines[0].is_ok());
+    assert!(matches!(lines[1], Err(DbError::Invalid(_))));
+}
@@ -0,0 +1,21 @@
+use null_common::metrics;
+use prometheus::{IntCounter, IntCounterVec, Opts};
+
+#[test]
+fn counters_catch_up_without_going_down() {
+    let counters = IntCounterVec::new(Opts::new("test_reads_total", "reads"), &["result"]).unwrap();
+    metrics::catch_up(&counters, &[("hit", 10), ("miss", 3)]);
+    assert_eq!(counters.with_label_values(&["hit"]).get(), 10);
+
+    // a total reset by a reopen waits until it passes the counter again
+    metrics::catch_up(&counters, &[("hit", 4), ("miss", 3)]);
+    assert_eq!(counters.with_label_values(&["hit"]).get(), 10);
+    metrics::catch_up(&counters, &[("hit", 12), ("miss", 5)]);
+    assert_eq!(counters.with_label_values(&["hit"]).get(), 12);
+    assert_eq!(counters.with_label_values(&["miss"]).get(), 5);
+
+    let compactions = IntCounter::new("test_compactions_total", "compactions").unwrap();
+    metrics::catch_up_one(&compactions, 2);
+    metrics::catch_up_one(&compactions, 0);
+    assert_eq!(compactions.get(), 2);
+}
@@ -0,0 +1,107 @@
+use null_common::archive::{self, Archive, Target};
+use null_common::error::DbError;
+use null_common::replication;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// The log lives in the working directory, so everything that touches it runs
+// in this one test, in a directory of its own.
+fn enter_temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("null-common-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_current_dir(&dir).unwrap();
+    dir
+}
+
+// Every key the log holds from offset on, in order.
+fn keys_from(mut offset: u64) -> Vec<String> {
+    let mut keys = Vec::new();
+    loop {
+        let (records, end) = replication::read_from(offset).unwrap();
+        for line in records.lines() {
+            keys.push(replication::parse_record(line).unwrap().1.to_string());
+        }
+        offset += records.len() as u64;
+        if offset >= end {
+            return keys;
+        }
+    }
+}
+
+#[test]
+fn log_is_dropped_once_followers_and_the_archive_have_it() {
+    let dir = enter_temp_dir("log_is_dropped");
+
+    // a log from before segments is carried over as the first one
+    std::fs::write("null.replication", "1:old:value\n").unwrap();
+    assert_eq!(replication::log_end(), 12);
+
+    // enough to fill a few segments
+    let value = "x".repeat(10 * 1024);
+    for i in 0..1000 {
+        replication::append_at(replication::now_millis(), &format!("k{}", i), &value).unwrap();
+    }
+    let end = replication::log_end();
+    assert_eq!(keys_from(0).len(), 1001);
+
+    // a follower part way through holds the log back
+    replication::acknowledge("f1", 100).unwrap();
+    replication::acknowledge("f2", end).unwrap();
+    assert_eq!(replication::drop_acknowledged(None).unwrap(), 0);
+    assert_eq!(replication::log_start(), 0);
+
+    // and so does an archive that is behind
+    let archive = Archive::new(dir.join("archive"), Duration::from_secs(3600));
+    replication::acknowledge("f1", end).unwrap();
+    assert_eq!(replication::drop_acknowledged(Some(0)).unwrap(), 0);
+
+    assert_eq!(archive.roll().unwrap(), end);
+    assert!(replication::drop_acknowledged(Some(end)).unwrap() >= 2);
+    let start = replication::log_start();
+    assert!(start > 0 && start < end);
+    assert!(matches!(replication::read_from(0), Err(DbError::Gone(_))));
+    assert!(keys_from(start).len() < 1001);
+    assert_eq!(keys_from(start).last().unwrap(), "k999");
+
+    // the archive still has everything the log dropped
+    let mut replayed = Vec::new();
+    let (sequence, count) = archive::replay(&dir.join("archive"), 0, Target::Sequence(end), |_, key, _| {
+        replayed.push(key.to_string());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!((sequence, count), (end, 1001));
+    assert_eq!(replayed.first().unwrap(), "old");
+
+    // a follower that is gone for good stops holding the log back
+    assert!(replication::forget("f1").unwrap());
+    assert!(!replication::forget("f1").unwrap());
+    assert_eq!(replication::followers().keys().collect::<Vec<_>>(), vec!["f2"]);
+
+    // a restored backup picks the log up where the backup was taken
+    replication::restart_at(12345).unwrap();
+    assert_eq!((replication::log_start(), replication::log_end()), (12345, 12345));
+    replication::append_at(1, "after", "restore").unwrap();
+    assert_eq!(keys_from(12345), vec!["after"]);
+    assert!(matches!(replication::read_from(0), Err(DbError::Gone(_))));
+}
+
+#[test]
+fn applied_offset_is_kept_in_the_data_dir() {
+    let dir = std::env::temp_dir().join(format!("null-common-applied-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    assert_eq!(replication::applied_offset(&dir), None);
+
+    replication::set_applied_offset(&dir, Some(42)).unwrap();
+    replication::set_applied_offset(&dir, Some(43)).unwrap();
+    assert_eq!(replication::applied_offset(&dir), Some(43));
+    // written to the side and renamed into place, with nothing left over
+    let names: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(names, ["null.follower"]);
+
+    replication::set_applied_offset(&dir, None).unwrap();
+    assert_eq!(replication::applied_offset(&dir), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
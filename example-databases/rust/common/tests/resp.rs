@@ -0,0 +1,163 @@
+use null_common::error::DbError;
+use null_common::resp::{self, Store};
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Memory(Mutex<HashMap<String, String>>);
+
+impl Store for Memory {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(self.0.lock().unwrap().get(key).cloned())
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        let mut map = self.0.lock().unwrap();
+        for (key, value) in batch {
+            match value {
+                Some(value) => map.insert(key.clone(), value.clone()),
+                None => map.remove(key),
+            };
+        }
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>, DbError> {
+        Ok(self.0.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn check(&self, _key: &str, _value: &str) -> Result<(), DbError> {
+        Ok(())
+    }
+}
+
+// A listener over an empty store, on a port of the test's own so they can
+// run in parallel.
+fn connect(port: u16) -> (BufReader<TcpStream>, TcpStream) {
+    resp::start(port, Arc::new(Memory::default()), "test").unwrap();
+    dial(port)
+}
+
+fn dial(port: u16) -> (BufReader<TcpStream>, TcpStream) {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    (BufReader::new(stream.try_clone().unwrap()), stream)
+}
+
+// Sends a command as RESP bulk strings and returns the first line of the reply.
+fn call(conn: &mut (BufReader<TcpStream>, TcpStream), args: &[&str]) -> String {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command += &format!("${}\r\n{}\r\n", arg.len(), arg);
+    }
+    conn.1.write_all(command.as_bytes()).unwrap();
+    read_line(&mut conn.0)
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    line.trim_end().to_string()
+}
+
+#[test]
+fn set_get_del() {
+    let mut conn = connect(16391);
+    assert_eq!(call(&mut conn, &["PING"]), "+PONG");
+    assert_eq!(call(&mut conn, &["SET", "foo", "bar"]), "+OK");
+    assert_eq!(call(&mut conn, &["GET", "foo"]), "$3");
+    assert_eq!(read_line(&mut conn.0), "bar");
+    assert_eq!(call(&mut conn, &["SET", "foo", "baz", "NX"]), "$-1");
+    assert_eq!(call(&mut conn, &["DEL", "foo", "missing"]), ":1");
+    assert_eq!(call(&mut conn, &["GET", "foo"]), "$-1");
+}
+
+#[test]
+fn expiry_past_what_instant_holds_is_refused() {
+    let mut conn = connect(16392);
+    assert_eq!(
+        call(&mut conn, &["SET", "foo", "bar", "EX", &u64::MAX.to_string()]),
+        "-ERR invalid expire time in 'set' command"
+    );
+    // the deadlines are still usable, by this client and by others
+    assert_eq!(call(&mut conn, &["SET", "foo", "bar", "EX", "1"]), "+OK");
+    let mut other = dial(16392);
+    assert_eq!(call(&mut other, &["EXISTS", "foo"]), ":1");
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(call(&mut other, &["GET", "foo"]), "$-1");
+}
+
+#[test]
+fn multi_exec_writes_together() {
+    let mut conn = connect(16393);
+    assert_eq!(call(&mut conn, &["MULTI"]), "+OK");
+    assert_eq!(call(&mut conn, &["SET", "a", "1"]), "+QUEUED");
+    assert_eq!(call(&mut conn, &["SET", "b", "2"]), "+QUEUED");
+    assert_eq!(call(&mut conn, &["EXEC"]), "*2");
+    assert_eq!(read_line(&mut conn.0), "+OK");
+    assert_eq!(read_line(&mut conn.0), "+OK");
+    assert_eq!(call(&mut conn, &["EXISTS", "a", "b"]), ":2");
+}
+
+#[test]
+fn too_many_arguments_is_a_protocol_error() {
+    let mut conn = connect(16394);
+    conn.1.write_all(b"*1048576\r\n").unwrap();
+    assert_eq!(read_line(&mut conn.0), "-ERR Protocol error: invalid length '1048576'");
+}
+
+// Sends SCAN and returns the next cursor and the page of keys.
+fn scan(conn: &mut (BufReader<TcpStream>, TcpStream), args: &[&str]) -> (String, Vec<String>) {
+    assert_eq!(call(conn, &[&["SCAN"], args].concat()), "*2");
+    read_line(&mut conn.0);
+    let next = read_line(&mut conn.0);
+    let count: usize = read_line(&mut conn.0)[1..].parse().unwrap();
+    let keys = (0..count)
+        .map(|_| {
+            read_line(&mut conn.0);
+            read_line(&mut conn.0)
+        })
+        .collect();
+    (next, keys)
+}
+
+#[test]
+fn scan_pages_through_the_keys_there_when_it_started() {
+    let mut conn = connect(16395);
+    for i in 0..25 {
+        assert_eq!(call(&mut conn, &["SET", &format!("k{:02}", i), "v"]), "+OK");
+    }
+    let (mut cursor, mut seen) = scan(&mut conn, &["0", "COUNT", "10"]);
+    assert_eq!(seen.len(), 10);
+    // what's written part way through doesn't shift the pages
+    assert_eq!(call(&mut conn, &["SET", "a-first", "v"]), "+OK");
+    while cursor != "0" {
+        let (next, keys) = scan(&mut conn, &[&cursor, "COUNT", "10"]);
+        seen.extend(keys);
+        cursor = next;
+    }
+    let want: Vec<String> = (0..25).map(|i| format!("k{:02}", i)).collect();
+    assert_eq!(seen, want);
+
+    let (cursor, keys) = scan(&mut conn, &["0", "MATCH", "k?5", "COUNT", "100"]);
+    assert_eq!(cursor, "0");
+    assert_eq!(keys, ["k05", "k15"]);
+}
+
+#[test]
+fn scan_match_with_many_stars_is_quick() {
+    let mut conn = connect(16396);
+    let long = "a".repeat(1000);
+    assert_eq!(call(&mut conn, &["SET", &long, "v"]), "+OK");
+    assert_eq!(call(&mut conn, &["SET", &format!("{}b", long), "v"]), "+OK");
+    // tried every way the stars could split the key, this would take ages
+    let (_, keys) = scan(&mut conn, &["0", "MATCH", "*a*a*a*a*a*a*a*a*b"]);
+    assert_eq!(keys, [format!("{}b", long)]);
+    let (_, keys) = scan(&mut conn, &["0", "MATCH", "a\\*"]);
+    assert!(keys.is_empty());
+}
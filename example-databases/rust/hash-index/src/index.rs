@@ -1,7 +1,68 @@
+use crate::segments::{self, TOMBSTONE};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use null_common::cache::{read_chunk, BlockCache, CHUNK_BYTES};
 
+// Where the newest write of a key starts.
+#[derive(Clone)]
+pub struct NullIndex {
+    pub file: PathBuf,
+    pub offset: u64,
+}
 
+// Replays every segment oldest first, so each live key ends up pointing at
+// its newest record.
+pub fn build(dir: &Path) -> Result<HashMap<String, NullIndex>, Error> {
+    let mut index = HashMap::new();
+    for file in segments::segment_files(dir)? {
+        for (offset, key, value) in segments::read_records(&file)? {
+            if value == TOMBSTONE {
+                index.remove(&key);
+            } else {
+                index.insert(
+                    key,
+                    NullIndex {
+                        file: file.clone(),
+                        offset,
+                    },
+                );
+            }
+        }
+    }
+    Ok(index)
+}
 
-pub struct NullIndex {
-    pub file: String,
-    pub offset: i64,
-}
\ No newline at end of file
+// The value of the record an index entry points at, read in CHUNK_BYTES chunks
+// through cache. file_id is what the cache knows at.file by.
+pub fn read_at(at: &NullIndex, cache: &BlockCache<Vec<u8>>, file_id: u64) -> Result<String, Error> {
+    let mut file = File::open(&at.file)?;
+    let mut line = Vec::new();
+    let mut offset = at.offset;
+    loop {
+        let start = offset - offset % CHUNK_BYTES;
+        let chunk = read_chunk(&mut file, cache, file_id, start)?;
+        let from = ((offset - start) as usize).min(chunk.len());
+        match chunk[from..].iter().position(|b| *b == b'\n') {
+            Some(end) => {
+                line.extend_from_slice(&chunk[from..from + end]);
+                break;
+            }
+            None => line.extend_from_slice(&chunk[from..]),
+        }
+        // the end of the file
+        if chunk.len() < CHUNK_BYTES as usize {
+            break;
+        }
+        offset = start + CHUNK_BYTES;
+    }
+    let line = String::from_utf8(line).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    match line.split_once(':') {
+        Some((_, value)) => Ok(value.to_string()),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("no record at {}:{}", at.file.display(), at.offset),
+        )),
+    }
+}
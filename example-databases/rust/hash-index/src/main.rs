@@ -5,153 +5,366 @@ use actix_web::{
     web::{self, Data}, 
     App, 
     Responder, 
+    HttpRequest,
     HttpResponse,
     HttpServer
 };
+use clap::Parser;
+use serde::Deserialize;
 #[macro_use]
 extern crate lazy_static;
-mod file_reader;
-//use easy_reader::EasyReader;
-use file_reader::EasyReader;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::collections::HashSet;
-use std::sync::mpsc;
-use std::{
-    fs::{
-        File,
-        write,
-        copy
-    },
-    io::{
-        self,
-        Error
-    }
-};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::io::BufReader;
-use std::sync::RwLock; // read heavy -- probably better period.
-mod file_compactor;
-mod file_manager;
+use null_hash_index::{Db, Options, TOMBSTONE};
+use std::io::Error;
+use std::sync::{Arc, RwLock}; // read heavy -- probably better period.
+use std::time::Duration;
+mod metrics;
+use null_common::error::{self, DbError};
+use null_common::{archive, backup, jsonl, logging, replication};
+use replication::FollowerStatus;
+use tracing::{error, info};
+
+// what request logs call this server
+const ENGINE: &str = "hash-index";
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(long, default_value_t = 8080)]
+    port: u16,
+    /// Replace this directory's segments with a backup before starting
+    #[clap(long)]
+    restore_from: Option<String>,
+    /// Keep closed stretches of the replication log here for point-in-time recovery
+    #[clap(long)]
+    archive_dir: Option<String>,
+    /// Hours archived log is kept before it is dropped
+    #[clap(long, default_value_t = 168)]
+    archive_retention_hours: u64,
+    /// After restoring, replay the archive up to this log sequence number
+    #[clap(long, requires_all = &["restore-from", "archive-dir"], conflicts_with = "recover-until-ms")]
+    recover_until_seq: Option<u64>,
+    /// After restoring, replay the archive up to this unix timestamp in millis
+    #[clap(long, requires_all = &["restore-from", "archive-dir"])]
+    recover_until_ms: Option<u64>,
+    /// Run as a read-only follower of the leader at host:port
+    #[clap(long)]
+    follow: Option<String>,
+}
 
-const TOMBSTONE: &'static str = "-tombstone-";
+// None when this node takes writes itself.
+type Follower = Option<Arc<RwLock<FollowerStatus>>>;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {    
-    let file_mutex = Data::new(RwLock::new(false));
+    let args = Args::parse();
+    logging::init();
+    let file_mutex = Arc::new(RwLock::new(false));
+
+    let restored = match &args.restore_from {
+        Some(dir) => Some(backup::restore(&segments(), std::path::Path::new(dir), args.follow.is_some())?),
+        None => None,
+    };
+    // segments are all in place now, so the index can be built from them
+    let db = Arc::new(null_hash_index::open(".", Options {
+        on_compaction: Some(Arc::new(metrics::observe_compaction)),
+        ..Options::default()
+    })?);
+
+    if let Some(manifest) = restored {
+        info!(segments = manifest.segments.len(), sequence = manifest.sequence, "restored backup");
+
+        let target = match (args.recover_until_seq, args.recover_until_ms) {
+            (Some(seq), _) => Some(archive::Target::Sequence(seq)),
+            (_, Some(ms)) => Some(archive::Target::Timestamp(ms)),
+            _ => None,
+        };
+        if let (Some(target), Some(archive_dir)) = (target, &args.archive_dir) {
+            // replayed records keep their timestamps so the log reads as it did
+            let (sequence, replayed) = archive::replay(
+                std::path::Path::new(archive_dir),
+                manifest.sequence,
+                target,
+                |ts, key, value| write_record_at(&db, ts, key, value),
+            )?;
+            info!(records = replayed, sequence, "recovered from archive");
+        }
+    }
+
+    let archive = args.archive_dir.as_ref().map(|dir| {
+        archive::Archive::new(dir.into(), std::time::Duration::from_secs(args.archive_retention_hours * 3600))
+    });
+    start_log_upkeep(archive, file_mutex.clone());
 
-    let (tx, rx) = mpsc::channel();
-    file_compactor::start_compaction(rx);
+    let follower: Follower = match args.follow {
+        Some(leader) => {
+            let status = Arc::new(RwLock::new(FollowerStatus::default()));
+            let dir = db.dir().to_path_buf();
+            let (lock, db) = (file_mutex.clone(), db.clone());
+            replication::start_following(&dir, leader, status.clone(), move |key, value| {
+                let _write_lock = lock.write();
+                write_record(&db, key, value)?;
+                Ok(db.flush()?)
+            })?;
+            Some(status)
+        }
+        None => None,
+    };
 
-    let _thing = file_manager::FileManager::new("my dude".to_string());
+    let file_mutex = Data::from(file_mutex);
+    let db = Data::from(db);
+    let follower = Data::new(follower);
 
     HttpServer::new(move || {
         App::new()
             .app_data(file_mutex.clone())
+            .app_data(db.clone())
+            .app_data(follower.clone())
+            .app_data(web::PayloadConfig::new(error::MAX_BODY_BYTES))
+            .wrap_fn(|req, srv| logging::trace_request(ENGINE, req, srv))
+            // ahead of /{key}, which would take these for keys
+            .service(metrics::get_metrics)
+            .service(export_records)
+            .service(import_records)
             .service(get_value_for_key)
             .service(put_value_for_key)
             .service(delete_value_for_key)
+            .service(get_replication_log)
+            .service(get_admin_stats)
+            .service(delete_admin_follower)
+            .service(post_admin_backup)
         })
-        .bind("127.0.0.1:8080")?
+        .bind(("127.0.0.1", args.port))?
         .run()
         .await
 }
 
-#[get("/{key}")]
-pub async fn get_value_for_key( 
-    file_mutex: Data<RwLock<bool>>, 
-    web::Path(key): web::Path<String>
-) -> impl Responder {
-    let _reader = file_mutex.read();
-    let file = File::open("null.database").unwrap();
-    let mut reader = EasyReader::new(file).unwrap();
-    // Generate index (optional)
-    reader.build_index();
-    reader.eof();
-    while let Some(line) = reader.prev_line().unwrap() {
-        let split = line.split(":").collect::<Vec<&str>>();
-        if split.len() == 2 {
-            if split[0] == key {
-                let val = split[1].to_string().clone();
-                if val == TOMBSTONE {
-                    return HttpResponse::Ok().body("value not found");
-                }
-                return HttpResponse::Ok().body(split[1].to_string().clone());
+// Every 30 seconds archives the replication log written since the last run,
+// if there is an archive, then drops the log segments that every follower and
+// the archive are done with. The lock is the same one requests take, so a
+// backup never sees a chunk half archived and a follower never reads a
+// segment as it goes.
+fn start_log_upkeep(archive: Option<archive::Archive>, file_mutex: Arc<RwLock<bool>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(30));
+        let _write_lock = file_mutex.write();
+        let archived = match archive.as_ref().map(|archive| archive.roll()).transpose() {
+            Ok(archived) => archived,
+            Err(e) => {
+                // nothing is dropped that the archive might not have
+                error!(error = %e, "couldn't archive log");
+                continue;
             }
+        };
+        match replication::drop_acknowledged(archived) {
+            Ok(0) => {}
+            Ok(dropped) => info!(segments = dropped, "dropped replication log"),
+            Err(e) => error!(error = %e, "couldn't drop replication log"),
         }
-        println!("{}", line);
+    });
+}
+
+// Writes a record to the database and the replication log. Callers must hold
+// the write lock so both see writes in the same order.
+fn write_record(db: &Db, key: &str, value: &str) -> Result<(), Error> {
+    write_record_at(db, replication::now_millis(), key, value)
+}
+
+fn write_record_at(db: &Db, ts: u64, key: &str, value: &str) -> Result<(), Error> {
+    if value == TOMBSTONE {
+        db.delete(key)?;
+    } else {
+        db.put(key, value)?;
     }
+    metrics::BYTES_WRITTEN.inc_by((key.len() + value.len() + 2) as u64);
+    replication::append_at(ts, key, value)
+}
 
-    // Repeat process by seeking back by chunk_size again.;
-    HttpResponse::Ok().body("value not found")
+#[get("/{key}")]
+pub async fn get_value_for_key( 
+    db: Data<Db>, 
+    web::Path(key): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    match db.get(&key)? {
+        Some(value) => Ok(HttpResponse::Ok().body(value)),
+        None => Err(DbError::NotFound(key)),
+    }
 }
 
 #[post("/{key}")]
 pub async fn put_value_for_key(
     file_mutex: Data<RwLock<bool>>, 
+    db: Data<Db>,
+    follower: Data<Follower>,
     web::Path(key): web::Path<String>,
     req_body: String
-) -> impl Responder {
+) -> Result<HttpResponse, DbError> {
+    writable(&follower)?;
+    check_value(&req_body)?;
+
     // Locking lets us protect the integraty of our file for now
     // 
     let _write_lock = file_mutex.write();
-    let mut line_count = 0;
-    {
-        let file = File::open("null.database").unwrap(); 
+    write_record(&db, &key, &req_body)?;
 
-        // make new file if over our 64 lines max
-        let f = BufReader::new(file);
-        line_count = f.lines().count();
-    }
+    Ok(HttpResponse::Ok().body("It is saved, no log file needed"))
+}
+
+#[delete("/{key}")]
+pub async fn delete_value_for_key(
+    file_mutex: Data<RwLock<bool>>, 
+    db: Data<Db>,
+    follower: Data<Follower>,
+    web::Path(key): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    writable(&follower)?;
+
+    let _write_lock = file_mutex.write();
+    write_record(&db, &key, TOMBSTONE)?;
     
-    if line_count > 64 {
-
-        let start = SystemTime::now();
-        let since_the_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .unwrap();
-        std::fs::copy("null.database", format!("{:?}.{}", since_the_epoch, "nnpack")).unwrap();
-
-        let mut tun = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open("null.database")
-            .unwrap(); 
-        if let Err(e) = writeln!(tun,"{}:{}",key, req_body) {
-            eprintln!("Couldn't write to file: {}", e);
-        }
-        return HttpResponse::Ok().body("Saved and made log file");
+    Ok(HttpResponse::Ok().body("It has been deleted!"))
+}
+
+fn writable(follower: &Follower) -> Result<(), DbError> {
+    match follower {
+        Some(_) => Err(DbError::ReadOnly("read-only follower, write to the leader".to_string())),
+        None => Ok(()),
     }
+}
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("null.database")
-        .unwrap();  
-        
-    if let Err(e) = writeln!(file,"{}:{}",key, req_body) {
-        eprintln!("Couldn't write to file: {}", e);
+// The database turns away records it can't store, this only keeps out values
+// too big to take in one request.
+fn check_value(value: &str) -> Result<(), DbError> {
+    if value.len() > error::MAX_VALUE_BYTES {
+        return Err(DbError::TooLarge { size: value.len(), limit: error::MAX_VALUE_BYTES });
     }
+    Ok(())
+}
 
-    HttpResponse::Ok().body("It is saved, no log file needed")
+// Followers pull the leader's log from here, starting at a byte offset. A
+// follower asking from an offset has applied everything before it, so the log
+// up to there can go once every follower has.
+#[get("/_replication/{offset}")]
+pub async fn get_replication_log(
+    file_mutex: Data<RwLock<bool>>,
+    req: HttpRequest,
+    web::Path(offset): web::Path<u64>
+) -> Result<HttpResponse, DbError> {
+    let _reader = file_mutex.read();
+    if let Some(follower) = req.headers().get(replication::FOLLOWER_HEADER).and_then(|v| v.to_str().ok()) {
+        replication::acknowledge(follower, offset)?;
+    }
+    let (records, end) = replication::read_from(offset).map_err(|e| {
+        if !matches!(e, DbError::Gone(_)) {
+            error!(error = %e, "couldn't read replication log");
+        }
+        e
+    })?;
+    Ok(HttpResponse::Ok()
+        .header(replication::LOG_END_HEADER, end.to_string())
+        .body(records))
 }
 
-#[delete("/{key}")]
-pub async fn delete_value_for_key(
-    file_mutex: Data<RwLock<bool>>, 
-    web::Path(key): web::Path<String>
+#[get("/_admin/stats")]
+pub async fn get_admin_stats(
+    follower: Data<Follower>
 ) -> impl Responder {
-    
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("null.database")
-        .unwrap();
-    
-    if let Err(e) = writeln!(file,"{}:{}",key, TOMBSTONE) {
-        eprintln!("Couldn't write to file: {}", e);
+    let stats = match follower.as_ref() {
+        Some(status) => serde_json::json!({
+            "role": "follower",
+            "replication": *status.read().unwrap(),
+        }),
+        None => serde_json::json!({
+            "role": "leader",
+            "replication_log_start": replication::log_start(),
+            "replication_log_end": replication::log_end(),
+            "followers": replication::followers(),
+        }),
+    };
+    HttpResponse::Ok().json(stats)
+}
+
+// Stops keeping replication log for a follower that is gone for good. Until
+// then the leader keeps everything the follower hasn't read.
+#[delete("/_admin/followers/{id}")]
+pub async fn delete_admin_follower(
+    file_mutex: Data<RwLock<bool>>,
+    web::Path(id): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    let _reader = file_mutex.read();
+    if replication::forget(&id)? {
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Err(DbError::NotFound(id))
     }
-    
-    HttpResponse::Ok().body("It has been deleted!")
 }
 
+#[derive(Deserialize)]
+pub struct BackupTarget {
+    dir: String,
+}
+
+// Our segments, in the working directory, for backups to copy.
+fn segments() -> backup::Segments {
+    backup::Segments {
+        dir: ".".into(),
+        active: null_hash_index::ACTIVE_SEGMENT,
+        list: null_hash_index::segment_files,
+    }
+}
+
+// Takes a consistent backup while we keep serving, e.g. POST /_admin/backup?dir=/backups/monday
+#[post("/_admin/backup")]
+pub async fn post_admin_backup(
+    file_mutex: Data<RwLock<bool>>,
+    target: web::Query<BackupTarget>
+) -> Result<HttpResponse, DbError> {
+    let lock = file_mutex.into_inner();
+    let dir = target.into_inner().dir;
+    // AlreadyExists, a backup is already in dir, comes back as a 409
+    let manifest = web::block(move || backup::take(&segments(), std::path::Path::new(&dir), &lock))
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "couldn't take backup");
+            e
+        })?;
+    Ok(HttpResponse::Ok().json(manifest))
+}
+
+// Every live record as JSON Lines.
+#[get("/_export")]
+pub async fn export_records(
+    db: Data<Db>
+) -> Result<HttpResponse, DbError> {
+    Ok(jsonl::export(db.scan()?.into_iter()))
+}
+
+#[post("/_import")]
+pub async fn import_records(
+    file_mutex: Data<RwLock<bool>>,
+    db: Data<Db>,
+    follower: Data<Follower>,
+    body: web::Payload
+) -> Result<HttpResponse, DbError> {
+    writable(&follower)?;
+
+    let mut lines = jsonl::Lines::new(body);
+    let mut imported = 0;
+    while let Some(line) = lines.next().await {
+        let record = line.map_err(|e| e.to_string()).and_then(|line| jsonl::decode(&line)).and_then(|(key, value)| {
+            check_value(&value).map_err(|e| e.to_string())?;
+            Ok((key, value))
+        });
+        let (key, value) = match record {
+            Ok(record) => record,
+            Err(e) => return Ok(jsonl::import_result(imported, Some((lines.line_number, e)))),
+        };
+
+        let _write_lock = file_mutex.write();
+        if let Err(e) = write_record(&db, &key, &value) {
+            error!(error = %e, "couldn't write to file");
+            return Ok(jsonl::import_result(imported, Some((lines.line_number, e.to_string()))));
+        }
+        imported += 1;
+    }
+    Ok(jsonl::import_result(imported, None))
+}
@@ -0,0 +1,101 @@
+use actix_web::{get, web::Data, Responder};
+use null_common::metrics::{catch_up, render};
+use null_hash_index::{Compaction, Db};
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    Histogram, IntCounter, IntCounterVec, IntGaugeVec,
+};
+use std::ffi::OsStr;
+use std::io::Error;
+use std::path::Path;
+use tracing::error;
+
+lazy_static! {
+    pub static ref BYTES_WRITTEN: IntCounter = register_int_counter!(
+        "null_bytes_written_total",
+        "Bytes of records appended to the active segment"
+    )
+    .unwrap();
+    static ref SEGMENTS: IntGaugeVec = register_int_gauge_vec!(
+        "null_segments",
+        "Segment files on disk, by kind",
+        &["kind"]
+    )
+    .unwrap();
+    static ref SEGMENT_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_segment_bytes",
+        "Size of the segment files on disk, by kind",
+        &["kind"]
+    )
+    .unwrap();
+    static ref COMPACTIONS: IntCounter = register_int_counter!(
+        "null_compactions_total",
+        "Compaction runs finished"
+    )
+    .unwrap();
+    static ref COMPACTION_SECONDS: Histogram = register_histogram!(
+        "null_compaction_duration_seconds",
+        "Time a compaction run took"
+    )
+    .unwrap();
+    static ref COMPACTION_BYTES_RECLAIMED: IntCounter = register_int_counter!(
+        "null_compaction_bytes_reclaimed_total",
+        "Bytes of segment files compaction has freed"
+    )
+    .unwrap();
+    static ref CACHE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "null_block_cache_requests_total",
+        "Segment chunk reads asked of the block cache, by result",
+        &["result"]
+    )
+    .unwrap();
+    static ref CACHE_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_block_cache_bytes",
+        "Bytes of segment chunks in the block cache, and what it can hold",
+        &["kind"]
+    )
+    .unwrap();
+}
+
+// Handed to the database, which runs compactions itself.
+pub fn observe_compaction(compaction: &Compaction) {
+    COMPACTIONS.inc();
+    COMPACTION_SECONDS.observe(compaction.duration.as_secs_f64());
+    COMPACTION_BYTES_RECLAIMED.inc_by(compaction.bytes_before.saturating_sub(compaction.bytes_after));
+}
+
+// Segment gauges are read off the disk at scrape time, so they are never stale.
+fn refresh_segments() -> Result<(), Error> {
+    for kind in &["active", "rolled", "compacted"] {
+        SEGMENTS.with_label_values(&[kind]).set(0);
+        SEGMENT_BYTES.with_label_values(&[kind]).set(0);
+    }
+    for file in null_hash_index::segment_files(Path::new("."))? {
+        let kind = match Path::new(&file).extension().and_then(OsStr::to_str) {
+            Some("npack") => "compacted",
+            Some("nnpack") => "rolled",
+            _ => "active",
+        };
+        let size = std::fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+        SEGMENTS.with_label_values(&[kind]).inc();
+        SEGMENT_BYTES.with_label_values(&[kind]).add(size as i64);
+    }
+    Ok(())
+}
+
+#[get("/metrics")]
+pub async fn get_metrics(db: Data<Db>) -> impl Responder {
+    if let Err(e) = refresh_segments() {
+        error!(error = %e, "couldn't read segments");
+    }
+    match db.stats() {
+        Ok(stats) => {
+            catch_up(&CACHE_REQUESTS, &[("hit", stats.cache.hits), ("miss", stats.cache.misses)]);
+            CACHE_BYTES.with_label_values(&["used"]).set(stats.cache.bytes as i64);
+            CACHE_BYTES.with_label_values(&["capacity"]).set(stats.cache.capacity as i64);
+        }
+        Err(e) => error!(error = %e, "couldn't read database stats"),
+    }
+
+    render()
+}
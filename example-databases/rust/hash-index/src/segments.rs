@@ -0,0 +1,158 @@
+use crate::{Compaction, Options};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, Error};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub const TOMBSTONE: &str = "-tombstone-";
+pub const ACTIVE_SEGMENT: &str = "null.database";
+
+// Where a record landed. moved means older records changed files, because
+// the active segment was rolled over or segments were compacted.
+pub struct Written {
+    pub file: PathBuf,
+    pub offset: u64,
+    pub moved: bool,
+}
+
+// Every file that holds records, oldest first: compacted npack files, then the
+// rolled over nnpack segments, then the segment currently taking writes.
+pub fn segment_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut compacted = Vec::new();
+    let mut rolled = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.extension().and_then(OsStr::to_str) {
+            Some("npack") => compacted.push(path),
+            Some("nnpack") => rolled.push(path),
+            _ => {}
+        }
+    }
+    compacted.sort();
+    rolled.sort();
+
+    let mut files = compacted;
+    files.append(&mut rolled);
+    let active = dir.join(ACTIVE_SEGMENT);
+    if active.exists() {
+        files.push(active);
+    }
+    Ok(files)
+}
+
+// Each key:value line in a file with the byte offset it starts at.
+pub fn read_records(file: &Path) -> Result<Vec<(u64, String, String)>, Error> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let mut records = Vec::new();
+    let mut offset = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            return Ok(records);
+        }
+        let split = line.trim_end_matches('\n').split(":").collect::<Vec<&str>>();
+        if split.len() == 2 {
+            records.push((offset, split[0].to_string(), split[1].to_string()));
+        }
+        offset += read as u64;
+    }
+}
+
+// Appends a record to the active segment, rolling it over first if it is full
+// and compacting once enough segments have rolled. Callers must hold the
+// write lock.
+pub fn write_record(dir: &Path, key: &str, value: &str, options: &Options) -> Result<Written, Error> {
+    let active = dir.join(ACTIVE_SEGMENT);
+    let line_count = match File::open(&active) {
+        Ok(file) => BufReader::new(file).lines().count(),
+        Err(_) => 0,
+    };
+
+    let mut moved = false;
+    if line_count >= options.max_segment_lines {
+        std::fs::rename(&active, dir.join(format!("{:020}.nnpack", now_nanos())))?;
+        moved = true;
+        let rolled = segment_files(dir)?.iter().filter(|f| has_extension(f, "nnpack")).count();
+        if rolled >= options.compact_after {
+            let report = compact(dir)?;
+            if let Some(on_compaction) = &options.on_compaction {
+                on_compaction(&report);
+            }
+        }
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&active)?;
+    let offset = file.metadata()?.len();
+    writeln!(file, "{}:{}", key, value)?;
+    if options.sync_writes {
+        file.sync_data()?;
+    }
+    Ok(Written {
+        file: active,
+        offset,
+        moved,
+    })
+}
+
+// Merges every rolled and compacted segment into a single npack file. Nothing
+// older is left for a tombstone to hide, so deleted keys are dropped outright.
+fn compact(dir: &Path) -> Result<Compaction, Error> {
+    let start = Instant::now();
+    let old = segment_files(dir)?
+        .into_iter()
+        .filter(|f| !f.ends_with(ACTIVE_SEGMENT))
+        .collect::<Vec<PathBuf>>();
+    let mut records = HashMap::new();
+    let mut bytes_before = 0;
+    for file in &old {
+        bytes_before += std::fs::metadata(file)?.len();
+        for (_, key, value) in read_records(file)? {
+            records.insert(key, value);
+        }
+    }
+
+    let name = format!("{:020}", now_nanos());
+    let tmp = dir.join(format!("{}.tmp", name));
+    let bytes_after = {
+        let mut out = File::create(&tmp)?;
+        for (key, value) in records.iter().filter(|(_, v)| *v != TOMBSTONE) {
+            writeln!(out, "{}:{}", key, value)?;
+        }
+        out.sync_all()?;
+        out.metadata()?.len()
+    };
+    std::fs::rename(&tmp, dir.join(format!("{}.npack", name)))?;
+    for file in &old {
+        std::fs::remove_file(file)?;
+    }
+    Ok(Compaction {
+        segments: old.len(),
+        bytes_before,
+        bytes_after,
+        duration: start.elapsed(),
+    })
+}
+
+pub fn sync(dir: &Path) -> Result<(), Error> {
+    let active = dir.join(ACTIVE_SEGMENT);
+    if active.exists() {
+        File::open(active)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn has_extension(file: &Path, extension: &str) -> bool {
+    file.extension().and_then(OsStr::to_str) == Some(extension)
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+}
@@ -41,8 +41,6 @@
 //!     println!("First line: {}", reader.next_line()?.unwrap());
 //!     println!("Second line: {}", reader.next_line()?.unwrap());
 //!     println!("First line: {}", reader.prev_line()?.unwrap());
-//!     #[cfg(feature = "rand")]
-//!     println!("Random line: {}", reader.random_line()?.unwrap());
 //!
 //!     // Iteration through the entire file (reverse)
 //!     reader.eof();
@@ -53,44 +51,15 @@
 //!     // You can always start/restart reading from the end of file (EOF)
 //!     reader.eof();
 //!     println!("Last line: {}", reader.prev_line()?.unwrap());
-//!     // Or the begin of file (BOF)
-//!     reader.bof();
-//!     println!("First line: {}", reader.next_line()?.unwrap());
 //!
 //!     Ok(())
 //! }
 //! ```
 //!
-//! ### Example: read random lines endlessly
-//!
-//! ```no_run
-//! use easy_reader::EasyReader;
-//! use std::{
-//!     fs::File,
-//!     io::{
-//!         self,
-//!         Error
-//!     }
-//! };
-//!
-//! fn navigate_forever() -> Result<(), Error> {
-//!     let file = File::open("resources/test-file-lf")?;
-//!     let mut reader = EasyReader::new(file)?;
-//!
-//!     // Generate index (optional)
-//!     reader.build_index();
-//!
-//!     #[cfg(feature = "rand")]
-//!     loop {
-//!         println!("{}", reader.random_line()?.unwrap());
-//!     }
-//!     Ok(())
-//! }
-//! ```
+//! Only the parts the segment readers use are kept here; random lines and
+//! moving back to the start of the file are left out.
 
 use fnv::FnvHashMap;
-#[cfg(feature = "rand")]
-use rand::Rng;
 use std::io::{self, prelude::*, Error, ErrorKind, SeekFrom};
 
 const CR_BYTE: u8 = b'\r';
@@ -101,8 +70,6 @@ enum ReadMode {
     Prev,
     Current,
     Next,
-    #[cfg(feature = "rand")]
-    Random,
 }
 
 pub struct EasyReader<R> {
@@ -135,17 +102,6 @@ impl<R: Read + Seek> EasyReader<R> {
         })
     }
 
-    pub fn chunk_size(&mut self, size: usize) -> &mut Self {
-        self.chunk_size = size;
-        self
-    }
-
-    pub fn bof(&mut self) -> &mut Self {
-        self.current_start_line_offset = 0;
-        self.current_end_line_offset = 0;
-        self
-    }
-
     pub fn eof(&mut self) -> &mut Self {
         self.current_start_line_offset = self.file_size;
         self.current_end_line_offset = self.file_size;
@@ -153,7 +109,7 @@ impl<R: Read + Seek> EasyReader<R> {
     }
 
     pub fn build_index(&mut self) -> io::Result<&mut Self> {
-        if self.file_size > usize::max_value() as u64 {
+        if self.file_size > usize::MAX as u64 {
             // 32bit ¯\_(ツ)_/¯
             return Err(Error::new(
                 ErrorKind::InvalidData,
@@ -179,19 +135,10 @@ impl<R: Read + Seek> EasyReader<R> {
         self.read_line(ReadMode::Prev)
     }
 
-    pub fn current_line(&mut self) -> io::Result<Option<String>> {
-        self.read_line(ReadMode::Current)
-    }
-
     pub fn next_line(&mut self) -> io::Result<Option<String>> {
         self.read_line(ReadMode::Next)
     }
 
-    #[cfg(feature = "rand")]
-    pub fn random_line(&mut self) -> io::Result<Option<String>> {
-        self.read_line(ReadMode::Random)
-    }
-
     fn read_line(&mut self, mode: ReadMode) -> io::Result<Option<String>> {
         match mode {
             ReadMode::Prev => {
@@ -214,11 +161,10 @@ impl<R: Read + Seek> EasyReader<R> {
             ReadMode::Current => {
                 if self.current_start_line_offset == self.current_end_line_offset {
                     if self.current_start_line_offset == self.file_size {
-                        self.current_start_line_offset =
-                            self.find_start_line(ReadMode::Prev)? as u64;
+                        self.current_start_line_offset = self.find_start_line(ReadMode::Prev)?;
                     }
                     if self.current_end_line_offset == 0 {
-                        self.current_end_line_offset = self.find_end_line()? as u64;
+                        self.current_end_line_offset = self.find_end_line()?;
                     }
                 }
             }
@@ -239,18 +185,6 @@ impl<R: Read + Seek> EasyReader<R> {
                     self.current_start_line_offset = self.current_end_line_offset;
                 }
             }
-            #[cfg(feature = "rand")]
-            ReadMode::Random => {
-                if self.indexed {
-                    let rnd_idx = rand::thread_rng().gen_range(0..self.offsets_index.len() - 1);
-                    self.current_start_line_offset = self.offsets_index[rnd_idx].0 as u64;
-                    self.current_end_line_offset = self.offsets_index[rnd_idx].1 as u64;
-                    return self.read_line(ReadMode::Current);
-                } else {
-                    self.current_start_line_offset =
-                        rand::thread_rng().gen_range(0..self.file_size);
-                }
-            }
         }
 
         if mode != ReadMode::Current {
@@ -264,15 +198,12 @@ impl<R: Read + Seek> EasyReader<R> {
 
         let line = String::from_utf8(buffer)
             .map_err(|err| {
-                Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "The line starting at byte: {} and ending at byte: {} is not valid UTF-8. Conversion error: {}",
-                        self.current_start_line_offset,
-                        self.current_end_line_offset,
-                        err
-                    )
-                )
+                Error::other(format!(
+                    "The line starting at byte: {} and ending at byte: {} is not valid UTF-8. Conversion error: {}",
+                    self.current_start_line_offset,
+                    self.current_end_line_offset,
+                    err
+                ))
             })?;
 
         Ok(Some(line))
@@ -329,20 +260,9 @@ impl<R: Read + Seek> EasyReader<R> {
                             if n_chunks == 0
                                 && self.current_start_line_offset == new_start_line_offset
                             {
-                                #[cfg(feature = "rand")]
-                                {
-                                    if mode != ReadMode::Random {
-                                        // Not moved yet
-                                        new_start_line_offset -= 1;
-                                        continue;
-                                    }
-                                }
-                                #[cfg(not(feature = "rand"))]
-                                {
-                                    // Not moved yet
-                                    new_start_line_offset -= 1;
-                                    continue;
-                                }
+                                // Not moved yet
+                                new_start_line_offset -= 1;
+                                continue;
                             }
 
                             if *chunk_el == LF_BYTE {
@@ -413,13 +333,12 @@ impl<R: Read + Seek> EasyReader<R> {
         self.read_bytes(offset, chunk_size)
     }
 
+    // A chunk can run past the end of the file, and reads as zeros there.
     fn read_bytes(&mut self, offset: u64, bytes: usize) -> io::Result<Vec<u8>> {
         let mut buffer = vec![0; bytes];
-        self.file.seek(SeekFrom::Start(offset as u64))?;
-        self.file.read(&mut buffer)?;
+        let in_file = self.file_size.saturating_sub(offset).min(bytes as u64) as usize;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buffer[..in_file])?;
         Ok(buffer)
     }
 }
-
-#[cfg(test)]
-mod tests;
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// How often we probe one member.
+const PROTOCOL_PERIOD: Duration = Duration::from_secs(1);
+// How long a direct or indirect ping gets before we give up on it.
+const PING_TIMEOUT: Duration = Duration::from_millis(300);
+// Members asked to ping a target for us when it didn't answer directly.
+const INDIRECT_PROBES: usize = 3;
+// How long a suspect has to refute before we declare it failed.
+const SUSPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Ordered so that, for the same incarnation, the worse news wins.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum State {
+    Alive,
+    Suspect,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Update {
+    pub id: String,
+    pub incarnation: u64,
+    pub state: State,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Ping {
+    pub from: String,
+    pub updates: Vec<Update>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Ack {
+    pub updates: Vec<Update>,
+}
+
+// Asks the receiver to ping target on our behalf.
+#[derive(Serialize, Deserialize)]
+pub struct PingReq {
+    pub from: String,
+    pub target: String,
+    pub updates: Vec<Update>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PingReqAck {
+    pub acked: bool,
+    pub updates: Vec<Update>,
+}
+
+#[derive(Serialize)]
+pub struct Member {
+    pub id: String,
+    pub incarnation: u64,
+    pub state: State,
+    pub since_secs: u64,
+}
+
+struct Entry {
+    incarnation: u64,
+    state: State,
+    changed: Instant,
+}
+
+// SWIM style membership: every period we ping one member, and if it doesn't
+// answer we ask a few others to try before suspecting it. Suspects that don't
+// refute in time are declared failed. The whole member list rides along on
+// every ping and ack, which is plenty for clusters this small.
+pub struct Gossip {
+    id: String,
+    members: Mutex<BTreeMap<String, Entry>>,
+    on_join: Box<dyn Fn(&str) + Send + Sync>,
+    client: reqwest::blocking::Client,
+}
+
+impl Gossip {
+    pub fn new(id: String, seeds: Vec<String>, on_join: Box<dyn Fn(&str) + Send + Sync>) -> Arc<Self> {
+        let mut members = BTreeMap::new();
+        for member in seeds.into_iter().chain(std::iter::once(id.clone())) {
+            members.insert(member, Entry {
+                incarnation: 0,
+                state: State::Alive,
+                changed: Instant::now(),
+            });
+        }
+        Arc::new(Gossip {
+            id,
+            members: Mutex::new(members),
+            on_join,
+            client: reqwest::blocking::Client::builder()
+                .timeout(PING_TIMEOUT)
+                .build()
+                .unwrap(),
+        })
+    }
+
+    pub fn view(&self) -> Vec<Member> {
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, e)| Member {
+                id: id.clone(),
+                incarnation: e.incarnation,
+                state: e.state,
+                since_secs: e.changed.elapsed().as_secs(),
+            })
+            .collect()
+    }
+
+    pub fn start(self: &Arc<Self>) {
+        let gossip = self.clone();
+        thread::spawn(move || {
+            let mut round: Vec<String> = Vec::new();
+            loop {
+                thread::sleep(PROTOCOL_PERIOD);
+                gossip.expire_suspects();
+
+                // walk the members in a fresh random order each time round
+                if round.is_empty() {
+                    round = gossip.probe_targets();
+                    shuffle(&mut round);
+                }
+                if let Some(target) = round.pop() {
+                    gossip.probe(&target);
+                }
+            }
+        });
+    }
+
+    pub fn handle_ping(&self, ping: Ping) -> Ack {
+        self.merge(ping.updates);
+        Ack { updates: self.updates() }
+    }
+
+    pub fn handle_ping_req(&self, req: PingReq) -> PingReqAck {
+        self.merge(req.updates);
+        let acked = self.ping(&req.target).is_ok();
+        PingReqAck {
+            acked,
+            updates: self.updates(),
+        }
+    }
+
+    fn probe_targets(&self) -> Vec<String> {
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, e)| **id != self.id && e.state != State::Failed)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    fn probe(&self, target: &str) {
+        if self.ping(target).is_ok() {
+            return;
+        }
+
+        let mut helpers = self.probe_targets();
+        helpers.retain(|h| h != target);
+        shuffle(&mut helpers);
+        helpers.truncate(INDIRECT_PROBES);
+
+        let acked = helpers.iter().any(|helper| {
+            let req = PingReq {
+                from: self.id.clone(),
+                target: target.to_string(),
+                updates: self.updates(),
+            };
+            self.client
+                .post(format!("http://{}/_gossip/ping-req", helper))
+                // they need time for their own ping to the target
+                .timeout(PING_TIMEOUT * 2)
+                .json(&req)
+                .send()
+                .and_then(|r| r.json::<PingReqAck>())
+                .map(|ack| {
+                    self.merge(ack.updates);
+                    ack.acked
+                })
+                .unwrap_or(false)
+        });
+
+        if !acked {
+            let mut members = self.members.lock().unwrap();
+            if let Some(e) = members.get_mut(target) {
+                if e.state == State::Alive {
+                    warn!(member = %target, "suspecting member");
+                    e.state = State::Suspect;
+                    e.changed = Instant::now();
+                }
+            }
+        }
+    }
+
+    fn ping(&self, target: &str) -> Result<(), String> {
+        let ping = Ping {
+            from: self.id.clone(),
+            updates: self.updates(),
+        };
+        let ack: Ack = self
+            .client
+            .post(format!("http://{}/_gossip/ping", target))
+            .json(&ping)
+            .send()
+            .and_then(|r| r.json())
+            .map_err(|e| e.to_string())?;
+        self.merge(ack.updates);
+        Ok(())
+    }
+
+    fn expire_suspects(&self) {
+        let mut members = self.members.lock().unwrap();
+        for (id, e) in members.iter_mut() {
+            if e.state == State::Suspect && e.changed.elapsed() >= SUSPECT_TIMEOUT {
+                warn!(member = %id, "declaring member failed");
+                e.state = State::Failed;
+                e.changed = Instant::now();
+            }
+        }
+    }
+
+    fn updates(&self) -> Vec<Update> {
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, e)| Update {
+                id: id.clone(),
+                incarnation: e.incarnation,
+                state: e.state,
+            })
+            .collect()
+    }
+
+    fn merge(&self, updates: Vec<Update>) {
+        let mut joined = Vec::new();
+        {
+            let mut members = self.members.lock().unwrap();
+            for update in updates {
+                if update.id == self.id {
+                    // someone thinks we are down, outbid them
+                    let me = members.get_mut(&self.id).unwrap();
+                    if update.state != State::Alive && update.incarnation >= me.incarnation {
+                        me.incarnation = update.incarnation + 1;
+                        info!(state = ?update.state, incarnation = me.incarnation, "refuting");
+                    }
+                    continue;
+                }
+
+                match members.get_mut(&update.id) {
+                    None => {
+                        if update.state != State::Failed {
+                            joined.push(update.id.clone());
+                        }
+                        members.insert(update.id, Entry {
+                            incarnation: update.incarnation,
+                            state: update.state,
+                            changed: Instant::now(),
+                        });
+                    }
+                    Some(e) => {
+                        let newer = update.incarnation > e.incarnation
+                            || (update.incarnation == e.incarnation && update.state > e.state);
+                        if newer {
+                            if e.state == State::Failed && update.state == State::Alive {
+                                joined.push(update.id.clone());
+                            }
+                            if e.state != update.state {
+                                e.changed = Instant::now();
+                            }
+                            e.incarnation = update.incarnation;
+                            e.state = update.state;
+                        }
+                    }
+                }
+            }
+        }
+
+        for id in joined {
+            info!(member = %id, "member joined");
+            (self.on_join)(&id);
+        }
+    }
+}
+
+fn shuffle(items: &mut [String]) {
+    for i in (1..items.len()).rev() {
+        let random = RandomState::new().build_hasher().finish();
+        items.swap(i, (random % (i as u64 + 1)) as usize);
+    }
+}
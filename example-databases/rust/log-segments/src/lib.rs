@@ -0,0 +1,308 @@
+//! A key/value store kept in log segments on disk. Writes are appended to the
+//! active segment, which is rolled over once full, and rolled segments are
+//! compacted into one. Reads walk the segments backwards, newest first, until
+//! they find the newest line for their key, with recently read parts of the
+//! segments kept in a block cache.
+//!
+//! ```no_run
+//! let db = null_log_segments::open("/tmp/null", null_log_segments::Options::default())?;
+//! db.put("foo", "bar")?;
+//! assert_eq!(db.get("foo")?, Some("bar".to_string()));
+//! db.close()?;
+//! # Ok::<(), null_log_segments::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use null_common::cache::{self, BlockCache, CachedFile};
+use null_common::error::DbError;
+use tracing::trace;
+
+mod file_reader;
+mod segments;
+
+pub use null_common::cache::CacheStats;
+use file_reader::EasyReader;
+pub use segments::{segment_files, ACTIVE_SEGMENT, TOMBSTONE};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // a record or file on disk we can't make sense of
+    Corrupt(String),
+    // a key or value we can't store as given
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// For callers that only deal in io::Error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Corrupt(msg) => io::Error::new(io::ErrorKind::InvalidData, msg),
+            Error::Invalid(msg) => io::Error::new(io::ErrorKind::InvalidInput, msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => Error::Corrupt(e.to_string()),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+// Lets the servers hand our errors straight back as HTTP responses.
+impl From<Error> for DbError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e.into(),
+            Error::Corrupt(msg) => DbError::Corrupt(msg),
+            Error::Invalid(msg) => DbError::Invalid(msg),
+        }
+    }
+}
+
+// What a compaction did, for callers that want to report on it.
+pub struct Compaction {
+    pub segments: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub duration: Duration,
+}
+
+// Called after each compaction, from the thread whose write set it off.
+pub type OnCompaction = Arc<dyn Fn(&Compaction) + Send + Sync>;
+
+pub struct Options {
+    // the active segment is rolled over once it holds this many records
+    pub max_segment_lines: usize,
+    // rolled segments are compacted into one once there are this many
+    pub compact_after: usize,
+    // fsync after every write rather than only on flush
+    pub sync_writes: bool,
+    pub on_compaction: Option<OnCompaction>,
+    // how many bytes of segment reads to keep in memory; 0 reads every
+    // lookup from disk
+    pub block_cache_bytes: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_segment_lines: 64,
+            compact_after: 4,
+            sync_writes: false,
+            on_compaction: None,
+            block_cache_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub keys: usize,
+    pub segments: usize,
+    pub disk_bytes: u64,
+    pub cache: CacheStats,
+}
+
+pub struct Db {
+    dir: PathBuf,
+    options: Options,
+    //it's just protecting the OS's file access
+    lock: RwLock<()>,
+    cache: BlockCache<Vec<u8>>,
+    // what the cache knows each segment by
+    file_ids: Mutex<HashMap<PathBuf, u64>>,
+}
+
+// Opens the database kept in dir, creating it if needed.
+pub fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<Db, Error> {
+    let dir = path.as_ref().to_path_buf();
+    std::fs::create_dir_all(&dir)?;
+    Ok(Db {
+        dir,
+        cache: BlockCache::new(options.block_cache_bytes),
+        options,
+        lock: RwLock::new(()),
+        file_ids: Mutex::new(HashMap::new()),
+    })
+}
+
+impl Db {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // None when the key was never written or has been deleted.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let _reader = self.lock.read().unwrap();
+        Ok(self.find(key)?.filter(|value| value != TOMBSTONE))
+    }
+
+    // Whether the segments hold any line for key, a delete included. A delete
+    // only counts until compaction drops it.
+    pub fn has_record(&self, key: &str) -> Result<bool, Error> {
+        let _reader = self.lock.read().unwrap();
+        Ok(self.find(key)?.is_some())
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        check_record(key, value)?;
+        if value == TOMBSTONE {
+            return Err(Error::Invalid(format!("{} is reserved for deletes", TOMBSTONE)));
+        }
+        let _writer = self.lock.write().unwrap();
+        self.write_locked(key, value)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        check_record(key, "")?;
+        let _writer = self.lock.write().unwrap();
+        self.write_locked(key, TOMBSTONE)
+    }
+
+    // Applies every write in batch under one lock, so readers see all of them
+    // or none. A value of None deletes the key. Nothing is written unless every
+    // record in the batch can be stored.
+    pub fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), Error> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => {
+                    check_record(key, value)?;
+                    if value == TOMBSTONE {
+                        return Err(Error::Invalid(format!("{} is reserved for deletes", TOMBSTONE)));
+                    }
+                }
+                None => check_record(key, "")?,
+            }
+        }
+        let _writer = self.lock.write().unwrap();
+        for (key, value) in batch {
+            self.write_locked(key, value.as_deref().unwrap_or(TOMBSTONE))?;
+        }
+        Ok(())
+    }
+
+    // Every live record, in no particular order. Replays every segment so
+    // later writes win.
+    pub fn scan(&self) -> Result<Vec<(String, String)>, Error> {
+        let _reader = self.lock.read().unwrap();
+        Ok(segments::live_records(&self.dir)?.into_iter().collect())
+    }
+
+    // Makes every write so far durable.
+    pub fn flush(&self) -> Result<(), Error> {
+        let _writer = self.lock.write().unwrap();
+        Ok(segments::sync(&self.dir)?)
+    }
+
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let keys = self.scan()?.len();
+        let _reader = self.lock.read().unwrap();
+        let files = segment_files(&self.dir)?;
+        let mut disk_bytes = 0;
+        for file in &files {
+            disk_bytes += std::fs::metadata(file)?.len();
+        }
+        Ok(Stats {
+            keys,
+            segments: files.len(),
+            disk_bytes,
+            cache: self.cache.stats(),
+        })
+    }
+
+    // Flushes and lets go of the database.
+    pub fn close(self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    // The newest value written for key, tombstones included, reading each
+    // segment backwards from its end, newest segment first, so recent writes
+    // are found without reading older ones. Callers must hold the read lock.
+    fn find(&self, key: &str) -> Result<Option<String>, Error> {
+        // callers tracing a request see how far back the key was
+        let span = tracing::Span::current();
+        let mut scanned = 0u64;
+        let mut searched = 0u64;
+        for path in segment_files(&self.dir)?.iter().rev() {
+            let file = CachedFile::open(path, &self.cache, self.file_id(path))?;
+            if file.is_empty() {
+                continue;
+            }
+            searched += 1;
+            let mut reader = EasyReader::new(file)?;
+            // Generate index (optional)
+            reader.build_index()?;
+            reader.eof();
+            while let Some(line) = reader.prev_line()? {
+                scanned += 1;
+                if let Some((k, value)) = line.split_once(':') {
+                    if k == key {
+                        span.record("segments", searched);
+                        span.record("lines_scanned", scanned);
+                        return Ok(Some(value.to_string()));
+                    }
+                }
+                trace!(line = %line, "scanned");
+            }
+        }
+        span.record("segments", searched);
+        span.record("lines_scanned", scanned);
+        Ok(None)
+    }
+
+    // Callers must hold the write lock.
+    fn write_locked(&self, key: &str, value: &str) -> Result<(), Error> {
+        if segments::write_record(&self.dir, key, value, &self.options)? {
+            // a new active segment took the old one's name, and compaction
+            // may have removed others
+            self.file_ids
+                .lock()
+                .unwrap()
+                .retain(|file, _| !file.ends_with(ACTIVE_SEGMENT) && file.exists());
+        }
+        Ok(())
+    }
+
+    fn file_id(&self, file: &Path) -> u64 {
+        *self
+            .file_ids
+            .lock()
+            .unwrap()
+            .entry(file.to_path_buf())
+            .or_insert_with(cache::next_file_id)
+    }
+}
+
+// Records are stored as key:value lines, so neither half can hold a ':' or a
+// newline.
+fn check_record(key: &str, value: &str) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::Invalid("key can't be empty".to_string()));
+    }
+    if key.contains([':', '\n']) || value.contains([':', '\n']) {
+        return Err(Error::Invalid("key and value can't hold ':' or newlines".to_string()));
+    }
+    Ok(())
+}
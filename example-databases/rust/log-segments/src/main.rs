@@ -2,154 +2,935 @@ use actix_web::{
     get, 
     post, 
     delete, 
+    error::BlockingError,
+    http::StatusCode,
     web::{self, Data}, 
     App, 
     Responder, 
+    HttpRequest,
     HttpResponse,
-    HttpServer
+    HttpServer,
+    ResponseError
 };
+use clap::Parser;
+use serde::Deserialize;
 #[macro_use]
 extern crate lazy_static;
-mod file_reader;
-//use easy_reader::EasyReader;
-use file_reader::EasyReader;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::collections::HashSet;
-use std::sync::mpsc;
-use std::{
-    fs::{
-        File,
-        write,
-        copy
-    },
-    io::{
-        self,
-        Error
-    }
-};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::io::BufReader;
-use std::sync::RwLock; // read heavy -- probably better period.
-mod file_compactor;
+use null_log_segments::{Db, Options, TOMBSTONE};
+use std::collections::HashMap;
+use std::io::Error;
+use std::sync::{Arc, RwLock}; // read heavy -- probably better period.
+use std::time::Duration;
+mod gossip;
+mod merkle;
+mod metrics;
+mod quorum;
+mod raft;
+mod ring;
+use null_common::error::{self, DbError};
+use null_common::{archive, backup, jsonl, logging, replication};
+use gossip::Gossip;
+use merkle::AntiEntropy;
+use quorum::{Quorum, Versioned, DELETED};
+use raft::{ProposeError, Raft};
+use replication::FollowerStatus;
+use ring::Ring;
+use tracing::{error, info};
+
+// what request logs call this server
+const ENGINE: &str = "log-segments";
 
-const TOMBSTONE: &'static str = "-tombstone-";
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(long, default_value_t = 8080)]
+    port: u16,
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1")]
+    bind: String,
+    /// host:port the other members reach this node at, when it isn't the bind
+    /// address and port
+    #[clap(long)]
+    advertise: Option<String>,
+    /// Replace this directory's segments with a backup before starting
+    #[clap(long)]
+    restore_from: Option<String>,
+    /// Keep closed stretches of the replication log here for point-in-time recovery
+    #[clap(long)]
+    archive_dir: Option<String>,
+    /// Hours archived log is kept before compaction drops it
+    #[clap(long, default_value_t = 168)]
+    archive_retention_hours: u64,
+    /// After restoring, replay the archive up to this log sequence number
+    #[clap(long, requires_all = &["restore-from", "archive-dir"], conflicts_with = "recover-until-ms")]
+    recover_until_seq: Option<u64>,
+    /// After restoring, replay the archive up to this unix timestamp in millis
+    #[clap(long, requires_all = &["restore-from", "archive-dir"])]
+    recover_until_ms: Option<u64>,
+    /// Run as a read-only follower of the leader at host:port
+    #[clap(long)]
+    follow: Option<String>,
+    /// Join a raft cluster, once per other member at host:port
+    #[clap(long = "raft-peer", multiple_occurrences = true)]
+    raft_peers: Vec<String>,
+    /// Partition keys across a ring, once per member at host:port
+    #[clap(long = "ring-node", multiple_occurrences = true)]
+    ring_nodes: Vec<String>,
+    /// Virtual nodes each ring member places on the ring
+    #[clap(long, default_value_t = 64)]
+    vnodes: usize,
+    /// Store every key on this many ring members and coordinate quorums
+    #[clap(long)]
+    replicas: Option<usize>,
+    /// Replicas a read waits for unless the request asks otherwise
+    #[clap(long, default_value_t = 2)]
+    read_quorum: usize,
+    /// Replicas a write waits for unless the request asks otherwise
+    #[clap(long, default_value_t = 2)]
+    write_quorum: usize,
+    /// Seconds between merkle tree comparisons with the other replicas
+    #[clap(long, default_value_t = 30)]
+    anti_entropy_secs: u64,
+    /// Track cluster membership with SWIM gossip
+    #[clap(long)]
+    gossip: bool,
+    /// Member to first gossip with, once per seed at host:port
+    #[clap(long = "gossip-seed", multiple_occurrences = true)]
+    gossip_seeds: Vec<String>,
+}
+
+// Per request overrides for quorum mode, e.g. GET /key?r=1
+#[derive(Deserialize)]
+pub struct Consistency {
+    r: Option<usize>,
+    w: Option<usize>,
+}
+
+// How this node takes writes.
+pub enum Mode {
+    // Writes go straight to local segments.
+    Standalone,
+    // Writes only arrive from the leader's log, clients can just read.
+    Follower(Arc<RwLock<FollowerStatus>>),
+    // Writes are acknowledged once a majority of the cluster has them.
+    Raft(Arc<Raft>),
+    // Each node owns a slice of the keys and passes the rest on to their owner.
+    Ring(Arc<Ring>),
+    // Every key lives on several ring members, reads and writes wait for a quorum.
+    Quorum(Arc<Quorum>, Arc<AntiEntropy>),
+}
+
+impl Mode {
+    fn ring(&self) -> Option<&Arc<Ring>> {
+        match self {
+            Mode::Ring(ring) => Some(ring),
+            Mode::Quorum(quorum, _) => Some(quorum.ring()),
+            _ => None,
+        }
+    }
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {    
-    let file_mutex = Data::new(RwLock::new(false));
+    let args = Args::parse();
+    logging::init();
+    let file_mutex = Arc::new(RwLock::new(false));
+
+    let restored = match &args.restore_from {
+        Some(dir) => Some(backup::restore(&segments(), std::path::Path::new(dir), args.follow.is_some())?),
+        None => None,
+    };
+    // segments are all in place now
+    let db = Arc::new(null_log_segments::open(".", Options {
+        on_compaction: Some(Arc::new(metrics::observe_compaction)),
+        ..Options::default()
+    })?);
+
+    if let Some(manifest) = restored {
+        info!(segments = manifest.segments.len(), sequence = manifest.sequence, "restored backup");
+
+        let target = match (args.recover_until_seq, args.recover_until_ms) {
+            (Some(seq), _) => Some(archive::Target::Sequence(seq)),
+            (_, Some(ms)) => Some(archive::Target::Timestamp(ms)),
+            _ => None,
+        };
+        if let (Some(target), Some(archive_dir)) = (target, &args.archive_dir) {
+            // replayed records keep their timestamps so the log reads as it did
+            let (sequence, replayed) = archive::replay(
+                std::path::Path::new(archive_dir),
+                manifest.sequence,
+                target,
+                |ts, key, value| write_record_at(&db, ts, key, value),
+            )?;
+            info!(records = replayed, sequence, "recovered from archive");
+        }
+    }
+
+    let archive = args.archive_dir.as_ref().map(|dir| {
+        archive::Archive::new(dir.into(), Duration::from_secs(args.archive_retention_hours * 3600))
+    });
+    start_log_upkeep(archive, file_mutex.clone());
+
+    // ring and raft members are worth gossiping with from the start
+    let mut seeds = args.gossip_seeds.clone();
+    seeds.extend(args.ring_nodes.iter().cloned());
+    seeds.extend(args.raft_peers.iter().cloned());
 
-    let (tx, rx) = mpsc::channel();
-    file_compactor::start_compaction(rx);
+    // what raft, the ring and gossip know this node by
+    let id = args.advertise.clone().unwrap_or_else(|| format!("{}:{}", args.bind, args.port));
+    let mode = if let Some(leader) = args.follow {
+        let status = Arc::new(RwLock::new(FollowerStatus::default()));
+        let dir = db.dir().to_path_buf();
+        let (lock, db) = (file_mutex.clone(), db.clone());
+        replication::start_following(&dir, leader, status.clone(), move |key, value| {
+            let _write_lock = lock.write();
+            write_record(&db, key, value)?;
+            Ok(db.flush()?)
+        })?;
+        Mode::Follower(status)
+    } else if !args.raft_peers.is_empty() {
+        let raft = Raft::new(id.clone(), args.raft_peers)?;
+        let (apply_lock, snapshot_lock, install_lock) =
+            (file_mutex.clone(), file_mutex.clone(), file_mutex.clone());
+        let (apply_db, snapshot_db, install_db) = (db.clone(), db.clone(), db.clone());
+        raft.start(
+            move |key, value| {
+                let _write_lock = apply_lock.write();
+                write_record(&apply_db, key, value)
+            },
+            move || {
+                let _reader = snapshot_lock.read();
+                live_records(&snapshot_db)
+            },
+            move |data| {
+                let _write_lock = install_lock.write();
+                for key in live_records(&install_db)?.keys() {
+                    if !data.contains_key(key) {
+                        write_record(&install_db, key, TOMBSTONE)?;
+                    }
+                }
+                for (key, value) in data {
+                    write_record(&install_db, key, value)?;
+                }
+                Ok(())
+            },
+        );
+        Mode::Raft(raft)
+    } else if !args.ring_nodes.is_empty() && args.replicas.is_some() {
+        let ring = Ring::new(id.clone(), args.ring_nodes, args.vnodes)?;
+        let (get_lock, put_lock, scan_lock) =
+            (file_mutex.clone(), file_mutex.clone(), file_mutex.clone());
+        let (get_db, put_db, scan_db) = (db.clone(), db.clone(), db.clone());
+        let quorum = Quorum::new(
+            ring,
+            args.replicas.unwrap(),
+            args.read_quorum,
+            args.write_quorum,
+            Box::new(move |key| {
+                let _reader = get_lock.read();
+                Ok(get_db.get(key)?.and_then(|raw| Versioned::decode(&raw)))
+            }),
+            Box::new(move |key, version| {
+                let _write_lock = put_lock.write();
+                write_if_newer(&put_db, key, version)
+            }),
+        )?;
+        quorum.ring().announce();
+        let anti_entropy = AntiEntropy::new(quorum.clone(), Box::new(move || {
+            let _reader = scan_lock.read();
+            live_records(&scan_db)
+        }));
+        anti_entropy.start(std::time::Duration::from_secs(args.anti_entropy_secs));
+        Mode::Quorum(quorum, anti_entropy)
+    } else if !args.ring_nodes.is_empty() {
+        let ring = Ring::new(id.clone(), args.ring_nodes, args.vnodes)?;
+        ring.announce();
+        // we may have been down while the ring changed
+        start_handoff(&ring, file_mutex.clone(), db.clone());
+        Mode::Ring(ring)
+    } else {
+        Mode::Standalone
+    };
+
+    let file_mutex = Data::from(file_mutex);
+    let db = Data::from(db);
+    let mode = Data::new(mode);
+
+    let gossip = if args.gossip {
+        let (join_mode, join_lock, join_db) = (mode.clone(), file_mutex.clone(), db.clone());
+        let gossip = Gossip::new(id, seeds, Box::new(move |member| {
+            // new members take their share of the ring
+            if let Some(ring) = join_mode.ring() {
+                match ring.merge_members(vec![member.to_string()]) {
+                    Ok(true) => {
+                        if let Mode::Ring(_) = join_mode.get_ref() {
+                            start_handoff(ring, join_lock.clone().into_inner(), join_db.clone().into_inner());
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => error!(error = %e, "couldn't save ring members"),
+                }
+            }
+        }));
+        gossip.start();
+        Some(gossip)
+    } else {
+        None
+    };
+    let gossip = Data::new(gossip);
 
     HttpServer::new(move || {
         App::new()
             .app_data(file_mutex.clone())
+            .app_data(db.clone())
+            .app_data(mode.clone())
+            .app_data(gossip.clone())
+            // raft snapshots carry the whole data set
+            .app_data(web::JsonConfig::default().limit(256 * 1024 * 1024))
+            .app_data(web::PayloadConfig::new(error::MAX_BODY_BYTES))
+            .wrap_fn(|req, srv| logging::trace_request(ENGINE, req, srv))
+            // ahead of /{key}, which would take these for keys
+            .service(metrics::get_metrics)
+            .service(export_records)
+            .service(import_records)
             .service(get_value_for_key)
             .service(put_value_for_key)
             .service(delete_value_for_key)
+            .service(get_replication_log)
+            .service(get_admin_stats)
+            .service(delete_admin_follower)
+            .service(raft_vote)
+            .service(raft_append)
+            .service(raft_snapshot)
+            .service(ring_members)
+            .service(ring_handoff)
+            .service(get_replica)
+            .service(put_replica)
+            .service(merkle_hashes)
+            .service(merkle_records)
+            .service(gossip_ping)
+            .service(gossip_ping_req)
+            .service(get_admin_members)
+            .service(post_admin_backup)
         })
-        .bind("127.0.0.1:8080")?
+        .bind((args.bind.as_str(), args.port))?
         .run()
         .await
 }
 
+// Every 30 seconds archives the replication log written since the last run,
+// if there is an archive, then drops the log segments that every follower and
+// the archive are done with. The lock is the same one requests take, so a
+// backup never sees a chunk half archived and a follower never reads a
+// segment as it goes.
+fn start_log_upkeep(archive: Option<archive::Archive>, file_mutex: Arc<RwLock<bool>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(30));
+        let _write_lock = file_mutex.write();
+        let archived = match archive.as_ref().map(|archive| archive.roll()).transpose() {
+            Ok(archived) => archived,
+            Err(e) => {
+                // nothing is dropped that the archive might not have
+                error!(error = %e, "couldn't archive log");
+                continue;
+            }
+        };
+        match replication::drop_acknowledged(archived) {
+            Ok(0) => {}
+            Ok(dropped) => info!(segments = dropped, "dropped replication log"),
+            Err(e) => error!(error = %e, "couldn't drop replication log"),
+        }
+    });
+}
+
+// Writes a record to the database and the replication log. Callers must hold
+// the write lock so both see writes in the same order.
+fn write_record(db: &Db, key: &str, value: &str) -> Result<(), Error> {
+    write_record_at(db, replication::now_millis(), key, value)
+}
+
+fn write_record_at(db: &Db, ts: u64, key: &str, value: &str) -> Result<(), Error> {
+    if value == TOMBSTONE {
+        db.delete(key)?;
+    } else {
+        db.put(key, value)?;
+    }
+    metrics::BYTES_WRITTEN.inc_by((key.len() + value.len() + 2) as u64);
+    replication::append_at(ts, key, value)
+}
+
+// Every live record, for the cluster modes that work on the whole data set.
+fn live_records(db: &Db) -> Result<HashMap<String, String>, Error> {
+    Ok(db.scan()?.into_iter().collect())
+}
+
 #[get("/{key}")]
 pub async fn get_value_for_key( 
-    file_mutex: Data<RwLock<bool>>, 
+    db: Data<Db>,
+    mode: Data<Mode>,
+    req: HttpRequest,
+    consistency: web::Query<Consistency>,
     web::Path(key): web::Path<String>
-) -> impl Responder {
-    let _reader = file_mutex.read();
-    let file = File::open("null.database").unwrap();
-    let mut reader = EasyReader::new(file).unwrap();
-    // Generate index (optional)
-    reader.build_index();
-    reader.eof();
-    while let Some(line) = reader.prev_line().unwrap() {
-        let split = line.split(":").collect::<Vec<&str>>();
-        if split.len() == 2 {
-            if split[0] == key {
-                let val = split[1].to_string().clone();
-                if val == TOMBSTONE {
-                    return HttpResponse::Ok().body("value not found");
-                }
-                return HttpResponse::Ok().body(split[1].to_string().clone());
+) -> Result<HttpResponse, DbError> {
+    match mode.get_ref() {
+        Mode::Ring(ring) => {
+            if let Some(resp) = forward_to_owner(ring, &req, &key, None).await {
+                return resp;
             }
         }
-        println!("{}", line);
+        Mode::Quorum(quorum, _) => {
+            let (quorum, r) = (quorum.clone(), consistency.r);
+            let k = key.clone();
+            return match web::block(move || quorum.read(&k, r)).await? {
+                Some(Versioned { ts, value: Some(value) }) => Ok(HttpResponse::Ok()
+                    .header("x-null-version", ts.to_string())
+                    .body(value)),
+                _ => Err(DbError::NotFound(key)),
+            };
+        }
+        _ => {}
     }
 
-    // Repeat process by seeking back by chunk_size again.;
-    HttpResponse::Ok().body("value not found")
+    match db.get(&key)? {
+        Some(value) => Ok(HttpResponse::Ok().body(value)),
+        None => Err(DbError::NotFound(key)),
+    }
 }
 
 #[post("/{key}")]
 pub async fn put_value_for_key(
     file_mutex: Data<RwLock<bool>>, 
+    db: Data<Db>,
+    mode: Data<Mode>,
+    req: HttpRequest,
+    consistency: web::Query<Consistency>,
     web::Path(key): web::Path<String>,
     req_body: String
-) -> impl Responder {
+) -> Result<HttpResponse, DbError> {
+    check_record(&key, &req_body)?;
+    if let Some(resp) = write_through_mode(&mode, &req, &consistency, &key, &req_body).await {
+        return resp;
+    }
+
     // Locking lets us protect the integraty of our file for now
     // 
     let _write_lock = file_mutex.write();
-    let mut line_count = 0;
-    {
-        let file = File::open("null.database").unwrap(); 
+    write_record(&db, &key, &req_body)?;
+
+    Ok(HttpResponse::Ok().body("It is saved, no log file needed"))
+}
 
-        // make new file if over our 64 lines max
-        let f = BufReader::new(file);
-        line_count = f.lines().count();
+#[delete("/{key}")]
+pub async fn delete_value_for_key(
+    file_mutex: Data<RwLock<bool>>, 
+    db: Data<Db>,
+    mode: Data<Mode>,
+    req: HttpRequest,
+    consistency: web::Query<Consistency>,
+    web::Path(key): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    check_record(&key, "")?;
+    if let Some(resp) = write_through_mode(&mode, &req, &consistency, &key, TOMBSTONE).await {
+        return resp;
     }
+
+    let _write_lock = file_mutex.write();
+    write_record(&db, &key, TOMBSTONE)?;
     
-    if line_count > 64 {
+    Ok(HttpResponse::Ok().body("It has been deleted!"))
+}
 
-        let start = SystemTime::now();
-        let since_the_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .unwrap();
-        std::fs::copy("null.database", format!("{:?}.{}", since_the_epoch, "nnpack")).unwrap();
+// Records are stored as key:value lines, so neither half can hold a ':' or a
+// newline, and neither the tombstone nor quorum mode's deleted marker can be
+// written as a value.
+fn check_record(key: &str, value: &str) -> Result<(), DbError> {
+    if key.contains([':', '\n']) || value.contains([':', '\n']) {
+        return Err(DbError::Invalid("key and value can't hold ':' or newlines".to_string()));
+    }
+    if value == TOMBSTONE || value == DELETED {
+        return Err(DbError::Invalid(format!("{} is reserved for deletes", value)));
+    }
+    if value.len() > error::MAX_VALUE_BYTES {
+        return Err(DbError::TooLarge { size: value.len(), limit: error::MAX_VALUE_BYTES });
+    }
+    Ok(())
+}
 
-        let mut tun = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open("null.database")
-            .unwrap(); 
-        if let Err(e) = writeln!(tun,"{}:{}",key, req_body) {
-            eprintln!("Couldn't write to file: {}", e);
+// Handles writes for nodes that don't just append locally. None means the
+// caller should write to its own segments as usual.
+async fn write_through_mode(
+    mode: &Mode,
+    req: &HttpRequest,
+    consistency: &Consistency,
+    key: &str,
+    value: &str
+) -> Option<Result<HttpResponse, DbError>> {
+    match mode {
+        Mode::Standalone => None,
+        Mode::Follower(_) => Some(Err(DbError::ReadOnly(
+            "read-only follower, write to the leader".to_string(),
+        ))),
+        Mode::Raft(raft) => {
+            let raft = raft.clone();
+            let (k, v) = (key.to_string(), value.to_string());
+            let proposed = web::block(move || raft.propose(&k, &v)).await;
+            Some(match proposed {
+                Ok(()) => Ok(HttpResponse::Ok().body("It is saved, on a majority of nodes")),
+                Err(BlockingError::Error(ProposeError::NotLeader(Some(leader)))) => {
+                    Ok(HttpResponse::TemporaryRedirect()
+                        .header("location", format!("http://{}/{}", leader, key))
+                        .finish())
+                }
+                Err(e) => {
+                    let e = DbError::from(e);
+                    error!(error = %e, "couldn't commit write");
+                    Err(e)
+                }
+            })
+        }
+        Mode::Ring(ring) => {
+            let body = if value == TOMBSTONE { None } else { Some(value.to_string()) };
+            forward_to_owner(ring, req, key, body).await
+        }
+        Mode::Quorum(quorum, _) => {
+            let value = if value == TOMBSTONE { None } else { Some(value.to_string()) };
+            let (quorum, key, w) = (quorum.clone(), key.to_string(), consistency.w);
+            Some(match web::block(move || quorum.write(&key, value, w)).await {
+                Ok(acks) => Ok(HttpResponse::Ok()
+                    .header("x-null-acks", acks.to_string())
+                    .body("It is saved, on a quorum of replicas")),
+                Err(e) => Err(e.into()),
+            })
         }
-        return HttpResponse::Ok().body("Saved and made log file");
     }
+}
+
+// Replicas keep whichever version of a key is newest, whatever order they arrive in.
+fn write_if_newer(db: &Db, key: &str, version: &Versioned) -> Result<(), Error> {
+    let current = db.get(key)?.and_then(|raw| Versioned::decode(&raw));
+    match current {
+        Some(current) if current.ts >= version.ts => Ok(()),
+        _ => write_record(db, key, &version.encode()),
+    }
+}
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("null.database")
-        .unwrap();  
-        
-    if let Err(e) = writeln!(file,"{}:{}",key, req_body) {
-        eprintln!("Couldn't write to file: {}", e);
+// Relays the request to the key's owner. None when this node owns the key, or
+// the request was already relayed once and we should serve it regardless.
+async fn forward_to_owner(
+    ring: &Arc<Ring>,
+    req: &HttpRequest,
+    key: &str,
+    body: Option<String>
+) -> Option<Result<HttpResponse, DbError>> {
+    if req.headers().contains_key(ring::FORWARDED_HEADER) {
+        return None;
+    }
+    let owner = ring.owner(key);
+    if owner == ring.id() {
+        return None;
     }
 
-    //HttpResponse::Ok().body("It is saved, no log file needed")
-    HttpResponse::NotImplemented()
+    let ring = ring.clone();
+    let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes()).unwrap();
+    let key = key.to_string();
+    let to = owner.clone();
+    let id = req.headers().get(logging::REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let forwarded = web::block(move || ring.forward(method, &to, &key, body, id)).await;
+    Some(match forwarded {
+        Ok((status, body)) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+            let mut resp = HttpResponse::build(status);
+            if !status.is_success() {
+                // the owner's errors are already JSON
+                resp.content_type("application/json");
+            }
+            Ok(resp.body(body))
+        }
+        Err(e) => {
+            error!(error = ?e, "couldn't reach key owner");
+            Err(DbError::Unavailable(format!("couldn't reach {}, the key's owner", owner)))
+        }
+    })
 }
 
-#[delete("/{key}")]
-pub async fn delete_value_for_key(
-    file_mutex: Data<RwLock<bool>>, 
+// Moves keys this node no longer owns over to their owners in the background.
+fn start_handoff(ring: &Arc<Ring>, file_mutex: Arc<RwLock<bool>>, db: Arc<Db>) {
+    let (scan_lock, scan_db) = (file_mutex.clone(), db.clone());
+    ring.hand_off(
+        move || {
+            let _reader = scan_lock.read();
+            live_records(&scan_db)
+        },
+        move |key| {
+            let _write_lock = file_mutex.write();
+            write_record(&db, key, TOMBSTONE)
+        },
+    );
+}
+
+// Followers pull the leader's log from here, starting at a byte offset. A
+// follower asking from an offset has applied everything before it, so the log
+// up to there can go once every follower has.
+#[get("/_replication/{offset}")]
+pub async fn get_replication_log(
+    file_mutex: Data<RwLock<bool>>,
+    req: HttpRequest,
+    web::Path(offset): web::Path<u64>
+) -> Result<HttpResponse, DbError> {
+    let _reader = file_mutex.read();
+    if let Some(follower) = req.headers().get(replication::FOLLOWER_HEADER).and_then(|v| v.to_str().ok()) {
+        replication::acknowledge(follower, offset)?;
+    }
+    let (records, end) = replication::read_from(offset).map_err(|e| {
+        if !matches!(e, DbError::Gone(_)) {
+            error!(error = %e, "couldn't read replication log");
+        }
+        e
+    })?;
+    Ok(HttpResponse::Ok()
+        .header(replication::LOG_END_HEADER, end.to_string())
+        .body(records))
+}
+
+#[get("/_admin/stats")]
+pub async fn get_admin_stats(
+    mode: Data<Mode>
+) -> impl Responder {
+    let stats = match mode.get_ref() {
+        Mode::Follower(status) => serde_json::json!({
+            "role": "follower",
+            "replication": *status.read().unwrap(),
+        }),
+        Mode::Standalone => serde_json::json!({
+            "role": "leader",
+            "replication_log_start": replication::log_start(),
+            "replication_log_end": replication::log_end(),
+            "followers": replication::followers(),
+        }),
+        Mode::Raft(raft) => serde_json::json!({
+            "role": "raft",
+            "raft": raft.status(),
+        }),
+        Mode::Ring(ring) => serde_json::json!({
+            "role": "ring",
+            "ring": ring.status(),
+        }),
+        Mode::Quorum(quorum, anti_entropy) => serde_json::json!({
+            "role": "quorum",
+            "ring": quorum.ring().status(),
+            "quorum": quorum.status(),
+            "anti_entropy": anti_entropy.reports(),
+        }),
+    };
+    HttpResponse::Ok().json(stats)
+}
+
+#[post("/_raft/vote")]
+pub async fn raft_vote(mode: Data<Mode>, req: web::Json<raft::VoteRequest>) -> impl Responder {
+    match mode.get_ref() {
+        Mode::Raft(raft) => rpc_response(raft.handle_vote(req.into_inner())),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[post("/_raft/append")]
+pub async fn raft_append(mode: Data<Mode>, req: web::Json<raft::AppendRequest>) -> impl Responder {
+    match mode.get_ref() {
+        Mode::Raft(raft) => rpc_response(raft.handle_append(req.into_inner())),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[post("/_raft/snapshot")]
+pub async fn raft_snapshot(mode: Data<Mode>, req: web::Json<raft::SnapshotRequest>) -> impl Responder {
+    match mode.get_ref() {
+        Mode::Raft(raft) => rpc_response(raft.handle_snapshot(req.into_inner())),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn rpc_response<T: serde::Serialize>(resp: Result<T, Error>) -> HttpResponse {
+    match resp {
+        Ok(resp) => HttpResponse::Ok().json(resp),
+        Err(e) => {
+            error!(error = %e, "raft rpc failed");
+            DbError::from(e).error_response()
+        }
+    }
+}
+
+// Another member telling us who is in the ring.
+#[post("/_ring/members")]
+pub async fn ring_members(
+    file_mutex: Data<RwLock<bool>>,
+    db: Data<Db>,
+    mode: Data<Mode>,
+    members: web::Json<Vec<String>>
+) -> impl Responder {
+    let ring = match mode.ring() {
+        Some(ring) => ring,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    match ring.merge_members(members.into_inner()) {
+        Ok(true) => {
+            // quorum replicas are brought up to date by read repair instead
+            if let Mode::Ring(_) = mode.get_ref() {
+                start_handoff(ring, file_mutex.into_inner(), db.into_inner());
+            }
+            HttpResponse::Ok().json(ring.members())
+        }
+        Ok(false) => HttpResponse::Ok().json(ring.members()),
+        Err(e) => {
+            error!(error = %e, "couldn't save ring members");
+            DbError::from(e).error_response()
+        }
+    }
+}
+
+// Records another member is handing over because we own them now. Its scan
+// was taken after the ring changed, so any put or delete we took for a key
+// since is newer than what it sends, and only keys we have no record of are
+// filled in.
+#[post("/_ring/handoff")]
+pub async fn ring_handoff(
+    file_mutex: Data<RwLock<bool>>,
+    db: Data<Db>,
+    mode: Data<Mode>,
+    records: web::Json<Vec<(String, String)>>
+) -> Result<HttpResponse, DbError> {
+    // quorum replicas store versions, which a plain handoff would clobber
+    if !matches!(mode.get_ref(), Mode::Ring(_)) {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let _write_lock = file_mutex.write();
+    for (key, value) in records.iter() {
+        if !db.has_record(key)? {
+            write_record(&db, key, value)?;
+        }
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+// A coordinator reading this node's copy of a key, as "ts|value".
+#[get("/_replica/{key}")]
+pub async fn get_replica(
+    db: Data<Db>,
+    mode: Data<Mode>,
     web::Path(key): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    // only quorum replicas store versions
+    if !matches!(mode.get_ref(), Mode::Quorum(..)) {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    match db.get(&key)? {
+        Some(raw) => Ok(HttpResponse::Ok().body(raw)),
+        None => Err(DbError::NotFound(key)),
+    }
+}
+
+// A coordinator writing or repairing this node's copy of a key.
+#[post("/_replica/{key}")]
+pub async fn put_replica(
+    file_mutex: Data<RwLock<bool>>,
+    db: Data<Db>,
+    mode: Data<Mode>,
+    web::Path(key): web::Path<String>,
+    req_body: String
+) -> Result<HttpResponse, DbError> {
+    // a version written over a plain record would read back as garbage
+    if !matches!(mode.get_ref(), Mode::Quorum(..)) {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let version = Versioned::decode(&req_body)
+        .ok_or_else(|| DbError::Invalid("expected ts|value".to_string()))?;
+    let _write_lock = file_mutex.write();
+    write_if_newer(&db, &key, &version)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// A replica walking our merkle tree, one level at a time.
+#[post("/_merkle/hashes")]
+pub async fn merkle_hashes(
+    mode: Data<Mode>,
+    req: web::Json<merkle::HashesRequest>
 ) -> impl Responder {
-    
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open("null.database")
-        .unwrap();
-    
-    if let Err(e) = writeln!(file,"{}:{}",key, TOMBSTONE) {
-        eprintln!("Couldn't write to file: {}", e);
+    let anti_entropy = match mode.get_ref() {
+        Mode::Quorum(_, anti_entropy) => anti_entropy.clone(),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let peer = req.peer.clone();
+    match web::block(move || anti_entropy.tree_for(&peer)).await {
+        Ok(tree) => HttpResponse::Ok().json(tree.hashes(req.level, &req.nodes)),
+        Err(e) => {
+            error!(error = %e, "couldn't build merkle tree");
+            DbError::from(e).error_response()
+        }
     }
-    
-    HttpResponse::Ok().body("It has been deleted!")
 }
 
+// The records behind leaves that a replica found don't match its own.
+#[post("/_merkle/records")]
+pub async fn merkle_records(
+    mode: Data<Mode>,
+    req: web::Json<merkle::RecordsRequest>
+) -> impl Responder {
+    let anti_entropy = match mode.get_ref() {
+        Mode::Quorum(_, anti_entropy) => anti_entropy.clone(),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let peer = req.peer.clone();
+    match web::block(move || anti_entropy.tree_for(&peer)).await {
+        Ok(tree) => HttpResponse::Ok().json(tree.records(&req.buckets)),
+        Err(e) => {
+            error!(error = %e, "couldn't build merkle tree");
+            DbError::from(e).error_response()
+        }
+    }
+}
+
+#[post("/_gossip/ping")]
+pub async fn gossip_ping(
+    gossip: Data<Option<Arc<Gossip>>>,
+    ping: web::Json<gossip::Ping>
+) -> impl Responder {
+    match gossip.as_ref() {
+        Some(gossip) => HttpResponse::Ok().json(gossip.handle_ping(ping.into_inner())),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[post("/_gossip/ping-req")]
+pub async fn gossip_ping_req(
+    gossip: Data<Option<Arc<Gossip>>>,
+    req: web::Json<gossip::PingReq>
+) -> impl Responder {
+    let gossip = match gossip.as_ref() {
+        Some(gossip) => gossip.clone(),
+        None => return HttpResponse::NotFound().finish(),
+    };
+    // pings the target itself, so keep it off the async workers
+    match web::block(move || Ok::<_, DbError>(gossip.handle_ping_req(req.into_inner()))).await {
+        Ok(ack) => HttpResponse::Ok().json(ack),
+        Err(e) => DbError::from(e).error_response(),
+    }
+}
+
+// Who this node thinks is in the cluster, and whether they are up.
+#[get("/_admin/members")]
+pub async fn get_admin_members(
+    gossip: Data<Option<Arc<Gossip>>>
+) -> impl Responder {
+    match gossip.as_ref() {
+        Some(gossip) => HttpResponse::Ok().json(gossip.view()),
+        None => HttpResponse::NotFound().body("gossip is not enabled, start with --gossip"),
+    }
+}
+
+// Stops keeping replication log for a follower that is gone for good. Until
+// then the leader keeps everything the follower hasn't read.
+#[delete("/_admin/followers/{id}")]
+pub async fn delete_admin_follower(
+    file_mutex: Data<RwLock<bool>>,
+    web::Path(id): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    let _reader = file_mutex.read();
+    if replication::forget(&id)? {
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Err(DbError::NotFound(id))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BackupTarget {
+    dir: String,
+}
+
+// Our segments, in the working directory, for backups to copy.
+fn segments() -> backup::Segments {
+    backup::Segments {
+        dir: ".".into(),
+        active: null_log_segments::ACTIVE_SEGMENT,
+        list: null_log_segments::segment_files,
+    }
+}
+
+// Takes a consistent backup while we keep serving, e.g. POST /_admin/backup?dir=/backups/monday
+#[post("/_admin/backup")]
+pub async fn post_admin_backup(
+    file_mutex: Data<RwLock<bool>>,
+    target: web::Query<BackupTarget>
+) -> Result<HttpResponse, DbError> {
+    let lock = file_mutex.into_inner();
+    let dir = target.into_inner().dir;
+    // AlreadyExists, a backup is already in dir, comes back as a 409
+    let manifest = web::block(move || backup::take(&segments(), std::path::Path::new(&dir), &lock))
+        .await
+        .map_err(|e| {
+            error!(error = ?e, "couldn't take backup");
+            e
+        })?;
+    Ok(HttpResponse::Ok().json(manifest))
+}
+
+// Every live record as JSON Lines. Ring and quorum nodes only export the keys
+// they hold themselves.
+#[get("/_export")]
+pub async fn export_records(
+    db: Data<Db>,
+    mode: Data<Mode>
+) -> Result<HttpResponse, DbError> {
+    let records = db.scan()?;
+    let versioned = matches!(mode.get_ref(), Mode::Quorum(..));
+    Ok(jsonl::export(records.into_iter().filter_map(move |(key, raw)| {
+        if !versioned {
+            return Some((key, raw));
+        }
+        // replicas store "ts|value", deleted keys have no value left
+        Versioned::decode(&raw)?.value.map(|value| (key, value))
+    })))
+}
+
+// Writes each JSON Lines record the same way a POST /{key} would, so imports
+// into a cluster are replicated like any other write.
+#[post("/_import")]
+pub async fn import_records(
+    file_mutex: Data<RwLock<bool>>,
+    db: Data<Db>,
+    mode: Data<Mode>,
+    req: HttpRequest,
+    consistency: web::Query<Consistency>,
+    body: web::Payload
+) -> impl Responder {
+    let mut lines = jsonl::Lines::new(body);
+    let mut imported = 0;
+    while let Some(line) = lines.next().await {
+        let record = line.map_err(|e| e.to_string()).and_then(|line| jsonl::decode(&line)).and_then(|(key, value)| {
+            check_record(&key, &value).map_err(|e| e.to_string())?;
+            Ok((key, value))
+        });
+        let (key, value) = match record {
+            Ok(record) => record,
+            Err(e) => return jsonl::import_result(imported, Some((lines.line_number, e))),
+        };
+
+        if let Some(resp) = write_through_mode(&mode, &req, &consistency, &key, &value).await {
+            let error = match resp {
+                Ok(resp) if resp.status().is_success() => None,
+                Ok(resp) => Some(format!("write of {} answered {}", key, resp.status())),
+                Err(e) => Some(format!("write of {} failed: {}", key, e)),
+            };
+            if let Some(error) = error {
+                return jsonl::import_result(imported, Some((lines.line_number, error)));
+            }
+        } else {
+            let _write_lock = file_mutex.write();
+            if let Err(e) = write_record(&db, &key, &value) {
+                error!(error = %e, "couldn't write to file");
+                return jsonl::import_result(imported, Some((lines.line_number, e.to_string())));
+            }
+        }
+        imported += 1;
+    }
+    jsonl::import_result(imported, None)
+}
@@ -0,0 +1,279 @@
+use crate::quorum::{Quorum, Versioned};
+use crate::ring::hash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Error;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+// 2^DEPTH leaves, each covering an equal slice of the key hash space.
+pub const DEPTH: usize = 10;
+
+// A peer walks our tree one level per request, so keep it around for a bit
+// rather than rescanning every segment for each level.
+const TREE_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize)]
+pub struct HashesRequest {
+    // whoever is asking, the tree only covers keys both of us replicate
+    pub peer: String,
+    pub level: usize,
+    pub nodes: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordsRequest {
+    pub peer: String,
+    pub buckets: Vec<usize>,
+}
+
+pub struct MerkleTree {
+    // levels[0] is the root, levels[DEPTH] the leaves
+    levels: Vec<Vec<u64>>,
+    buckets: Vec<BTreeMap<String, String>>,
+}
+
+impl MerkleTree {
+    pub fn build(records: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut buckets = vec![BTreeMap::new(); 1 << DEPTH];
+        for (key, value) in records {
+            buckets[bucket_of(&key)].insert(key, value);
+        }
+
+        let leaves = buckets
+            .iter()
+            .map(|bucket| {
+                // sorted, so both sides hash the same records the same way
+                let mut h = 0u64;
+                for (key, value) in bucket {
+                    h = combine(h, hash(&format!("{}:{}", key, value)));
+                }
+                h
+            })
+            .collect::<Vec<u64>>();
+
+        let mut levels = vec![leaves];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(2)
+                .map(|pair| combine(pair[0], pair[1]))
+                .collect();
+            levels.insert(0, parents);
+        }
+
+        MerkleTree { levels, buckets }
+    }
+
+    pub fn hashes(&self, level: usize, nodes: &[usize]) -> Vec<u64> {
+        nodes
+            .iter()
+            .map(|n| self.levels.get(level).and_then(|l| l.get(*n)).copied().unwrap_or(0))
+            .collect()
+    }
+
+    pub fn records(&self, buckets: &[usize]) -> Vec<(String, String)> {
+        buckets
+            .iter()
+            .filter_map(|b| self.buckets.get(*b))
+            .flat_map(|bucket| bucket.iter().map(|(k, v)| (k.clone(), v.clone())))
+            .collect()
+    }
+}
+
+fn bucket_of(key: &str) -> usize {
+    (hash(key) >> (64 - DEPTH)) as usize
+}
+
+fn combine(a: u64, b: u64) -> u64 {
+    hash(&format!("{:016x}{:016x}", a, b))
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct SyncReport {
+    pub peer: String,
+    pub finished_unix_secs: u64,
+    pub differing_buckets: usize,
+    pub keys_pulled: usize,
+    pub keys_pushed: usize,
+    pub error: Option<String>,
+}
+
+pub type Scan = Box<dyn Fn() -> Result<HashMap<String, String>, Error> + Send + Sync>;
+
+// Keeps replicas converging even when writes were missed and nobody reads the
+// key to trigger read repair. Each round compares trees with every other
+// member and only exchanges the keys in buckets whose hashes differ.
+pub struct AntiEntropy {
+    quorum: Arc<Quorum>,
+    scan: Scan,
+    trees: Mutex<HashMap<String, (Instant, Arc<MerkleTree>)>>,
+    reports: RwLock<BTreeMap<String, SyncReport>>,
+    client: reqwest::blocking::Client,
+}
+
+impl AntiEntropy {
+    pub fn new(quorum: Arc<Quorum>, scan: Scan) -> Arc<Self> {
+        Arc::new(AntiEntropy {
+            quorum,
+            scan,
+            trees: Mutex::new(HashMap::new()),
+            reports: RwLock::new(BTreeMap::new()),
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap(),
+        })
+    }
+
+    pub fn reports(&self) -> Vec<SyncReport> {
+        self.reports.read().unwrap().values().cloned().collect()
+    }
+
+    // Tree over the keys that both we and peer are replicas for.
+    pub fn tree_for(&self, peer: &str) -> Result<Arc<MerkleTree>, Error> {
+        if let Some((built, tree)) = self.trees.lock().unwrap().get(peer) {
+            if built.elapsed() < TREE_TTL {
+                return Ok(tree.clone());
+            }
+        }
+
+        let me = self.quorum.ring().id().to_string();
+        let shared = (self.scan)()?.into_iter().filter(|(key, _)| {
+            let replicas = self.quorum.replicas_for(key);
+            replicas.contains(&me) && replicas.iter().any(|r| r == peer)
+        });
+        let tree = Arc::new(MerkleTree::build(shared));
+        self.trees
+            .lock()
+            .unwrap()
+            .insert(peer.to_string(), (Instant::now(), tree.clone()));
+        Ok(tree)
+    }
+
+    pub fn start(self: &Arc<Self>, every: Duration) {
+        let ae = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(every);
+            let me = ae.quorum.ring().id().to_string();
+            for peer in ae.quorum.ring().members().into_iter().filter(|m| *m != me) {
+                let mut report = match ae.sync(&peer) {
+                    Ok(report) => report,
+                    Err(e) => SyncReport {
+                        peer: peer.clone(),
+                        error: Some(e),
+                        ..SyncReport::default()
+                    },
+                };
+                report.finished_unix_secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs();
+                if report.keys_pulled + report.keys_pushed > 0 {
+                    info!(
+                        peer = %peer,
+                        pulled = report.keys_pulled,
+                        pushed = report.keys_pushed,
+                        "anti-entropy repaired keys"
+                    );
+                }
+                ae.reports.write().unwrap().insert(peer, report);
+            }
+        });
+    }
+
+    fn sync(&self, peer: &str) -> Result<SyncReport, String> {
+        let me = self.quorum.ring().id().to_string();
+        // always start from a fresh view of our own data
+        self.trees.lock().unwrap().remove(peer);
+        let ours = self.tree_for(peer).map_err(|e| e.to_string())?;
+
+        // walk down from the root, only following subtrees that differ
+        let mut differing = vec![0usize];
+        for level in 0..=DEPTH {
+            if differing.is_empty() {
+                break;
+            }
+            let theirs: Vec<u64> = self.post(peer, "hashes", &HashesRequest {
+                peer: me.clone(),
+                level,
+                nodes: differing.clone(),
+            })?;
+            let mine = ours.hashes(level, &differing);
+            let next = differing
+                .iter()
+                .zip(mine.iter().zip(theirs.iter()))
+                .filter(|(_, (a, b))| a != b)
+                .map(|(n, _)| *n)
+                .collect::<Vec<usize>>();
+            differing = if level == DEPTH {
+                next
+            } else {
+                next.iter().flat_map(|n| vec![n * 2, n * 2 + 1]).collect()
+            };
+        }
+
+        let mut report = SyncReport {
+            peer: peer.to_string(),
+            differing_buckets: differing.len(),
+            ..SyncReport::default()
+        };
+        if differing.is_empty() {
+            return Ok(report);
+        }
+
+        let theirs: HashMap<String, String> = self
+            .post::<_, Vec<(String, String)>>(peer, "records", &RecordsRequest {
+                peer: me.clone(),
+                buckets: differing.clone(),
+            })?
+            .into_iter()
+            .collect();
+        let mine: HashMap<String, String> = ours.records(&differing).into_iter().collect();
+
+        // last write wins, whichever side has the older copy gets the newer one
+        for (key, raw) in &theirs {
+            let their_version = match Versioned::decode(raw) {
+                Some(v) => v,
+                None => continue,
+            };
+            let our_ts = mine.get(key).and_then(|r| Versioned::decode(r)).map(|v| v.ts);
+            if our_ts.map(|ts| ts < their_version.ts).unwrap_or(true) {
+                self.quorum.put_on(&me, key, &their_version)?;
+                report.keys_pulled += 1;
+            }
+        }
+        for (key, raw) in &mine {
+            let our_version = match Versioned::decode(raw) {
+                Some(v) => v,
+                None => continue,
+            };
+            let their_ts = theirs.get(key).and_then(|r| Versioned::decode(r)).map(|v| v.ts);
+            if their_ts.map(|ts| ts < our_version.ts).unwrap_or(true) {
+                self.quorum.put_on(peer, key, &our_version)?;
+                report.keys_pushed += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn post<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
+        &self,
+        peer: &str,
+        rpc: &str,
+        req: &Req,
+    ) -> Result<Resp, String> {
+        let resp = self
+            .client
+            .post(format!("http://{}/_merkle/{}", peer, rpc))
+            .json(req)
+            .send()
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("{} answered {}", peer, resp.status()));
+        }
+        resp.json().map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,293 @@
+use null_common::error::DbError;
+use crate::ring::{self, Ring};
+use serde::Serialize;
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// Stored in place of the value for deleted keys. Replicas keep it like any
+// other version so an old write arriving late can't bring the key back.
+// Clients can't write it as a value.
+pub const DELETED: &str = "-deleted-";
+
+const REPLICA_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Conflicts are settled last-write-wins on the coordinator's clock, so nodes
+// with skewed clocks can lose writes. Vector clocks would keep both siblings
+// around for the client to merge instead.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Versioned {
+    pub ts: u64,
+    pub value: Option<String>,
+}
+
+impl Versioned {
+    pub fn now(value: Option<String>) -> Self {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        Versioned { ts, value }
+    }
+
+    // "ts|value" so it still fits in one key:value record
+    pub fn encode(&self) -> String {
+        format!("{}|{}", self.ts, self.value.as_deref().unwrap_or(DELETED))
+    }
+
+    pub fn decode(raw: &str) -> Option<Self> {
+        let (ts, value) = raw.split_once('|')?;
+        Some(Versioned {
+            ts: ts.parse().ok()?,
+            value: if value == DELETED { None } else { Some(value.to_string()) },
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum QuorumError {
+    // r or w asked for more replicas than a key has
+    Invalid(String),
+    // fewer replicas answered than the request needed
+    Unavailable { needed: usize, answered: usize },
+}
+
+impl From<QuorumError> for DbError {
+    fn from(e: QuorumError) -> Self {
+        match e {
+            QuorumError::Invalid(msg) => DbError::PreconditionFailed(msg),
+            QuorumError::Unavailable { needed, answered } => DbError::Unavailable(format!(
+                "needed {} replicas, {} answered",
+                needed, answered
+            )),
+        }
+    }
+}
+
+pub type LocalGet = Box<dyn Fn(&str) -> Result<Option<Versioned>, Error> + Send + Sync>;
+pub type LocalPut = Box<dyn Fn(&str, &Versioned) -> Result<(), Error> + Send + Sync>;
+
+#[derive(Serialize)]
+pub struct QuorumStatus {
+    pub n: usize,
+    pub r: usize,
+    pub w: usize,
+}
+
+// The default quorums have to be ones every request can meet. Ones that don't
+// overlap are allowed, as some want faster writes over reading them back.
+fn check_settings(n: usize, r: usize, w: usize) -> Result<(), Error> {
+    if n == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "--replicas must be at least 1"));
+    }
+    for (flag, quorum) in [("--read-quorum", r), ("--write-quorum", w)] {
+        if quorum == 0 || quorum > n {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is {}, it must be between 1 and --replicas ({})", flag, quorum, n),
+            ));
+        }
+    }
+    if r + w <= n {
+        warn!(n, r, w, "read and write quorums don't overlap, so reads can miss acknowledged writes");
+    }
+    Ok(())
+}
+
+// Coordinates reads and writes for whichever key a client asks this node
+// about, fanning out to the key's N replicas on the ring.
+pub struct Quorum {
+    ring: Arc<Ring>,
+    n: usize,
+    r: usize,
+    w: usize,
+    local_get: LocalGet,
+    local_put: LocalPut,
+    client: reqwest::blocking::Client,
+}
+
+impl Quorum {
+    pub fn new(
+        ring: Arc<Ring>,
+        n: usize,
+        r: usize,
+        w: usize,
+        local_get: LocalGet,
+        local_put: LocalPut,
+    ) -> Result<Arc<Self>, Error> {
+        check_settings(n, r, w)?;
+        Ok(Arc::new(Quorum {
+            ring,
+            n,
+            r,
+            w,
+            local_get,
+            local_put,
+            client: reqwest::blocking::Client::builder()
+                .timeout(REPLICA_TIMEOUT)
+                .build()
+                .unwrap(),
+        }))
+    }
+
+    pub fn ring(&self) -> &Arc<Ring> {
+        &self.ring
+    }
+
+    pub fn replicas_for(&self, key: &str) -> Vec<String> {
+        self.ring.preference_list(key, self.n)
+    }
+
+    pub fn status(&self) -> QuorumStatus {
+        QuorumStatus {
+            n: self.n,
+            r: self.r,
+            w: self.w,
+        }
+    }
+
+    fn check(&self, asked: Option<usize>, default: usize) -> Result<usize, QuorumError> {
+        let wanted = asked.unwrap_or(default);
+        if wanted == 0 || wanted > self.n {
+            return Err(QuorumError::Invalid(format!("must be between 1 and {}", self.n)));
+        }
+        Ok(wanted)
+    }
+
+    // Sends the write to every replica, returning once w of them have it.
+    // Replicas that answer late still get the write.
+    pub fn write(
+        self: &Arc<Self>,
+        key: &str,
+        value: Option<String>,
+        w: Option<usize>,
+    ) -> Result<usize, QuorumError> {
+        let w = self.check(w, self.w)?;
+        let version = Versioned::now(value);
+        let replicas = self.replicas_for(key);
+
+        let (tx, rx) = mpsc::channel();
+        for node in replicas.clone() {
+            let (quorum, tx, key, version) = (self.clone(), tx.clone(), key.to_string(), version.clone());
+            thread::spawn(move || {
+                let _ = tx.send(quorum.put_on(&node, &key, &version).is_ok());
+            });
+        }
+        drop(tx);
+
+        let mut acks = 0;
+        for ok in rx.iter() {
+            if ok {
+                acks += 1;
+            }
+            if acks >= w {
+                return Ok(acks);
+            }
+        }
+        Err(QuorumError::Unavailable { needed: w, answered: acks })
+    }
+
+    // Returns the newest version once r replicas have answered. Whatever the
+    // rest say is checked in the background and stale replicas are repaired.
+    pub fn read(self: &Arc<Self>, key: &str, r: Option<usize>) -> Result<Option<Versioned>, QuorumError> {
+        let r = self.check(r, self.r)?;
+        let replicas = self.replicas_for(key);
+
+        let (tx, rx) = mpsc::channel();
+        for node in replicas {
+            let (quorum, tx, key) = (self.clone(), tx.clone(), key.to_string());
+            thread::spawn(move || {
+                let _ = tx.send((node.clone(), quorum.get_from(&node, &key)));
+            });
+        }
+        drop(tx);
+
+        let mut answers: Vec<(String, Option<Versioned>)> = Vec::new();
+        while answers.len() < r {
+            match rx.recv() {
+                Ok((node, Ok(version))) => answers.push((node, version)),
+                Ok((_, Err(_))) => continue,
+                Err(_) => {
+                    return Err(QuorumError::Unavailable { needed: r, answered: answers.len() })
+                }
+            }
+        }
+        let newest = newest(&answers);
+
+        let (quorum, key) = (self.clone(), key.to_string());
+        thread::spawn(move || {
+            for (node, answer) in rx.iter() {
+                if let Ok(version) = answer {
+                    answers.push((node, version));
+                }
+            }
+            quorum.repair(&key, &answers);
+        });
+
+        Ok(newest)
+    }
+
+    fn repair(&self, key: &str, answers: &[(String, Option<Versioned>)]) {
+        let newest = match newest(answers) {
+            Some(newest) => newest,
+            None => return,
+        };
+        for (node, version) in answers {
+            let stale = match version {
+                Some(v) => v.ts < newest.ts,
+                None => true,
+            };
+            if stale {
+                match self.put_on(node, key, &newest) {
+                    Ok(()) => info!(key, node = %node, "read repaired"),
+                    Err(e) => warn!(key, node = %node, error = %e, "read repair failed"),
+                }
+            }
+        }
+    }
+
+    pub fn put_on(&self, node: &str, key: &str, version: &Versioned) -> Result<(), String> {
+        if node == self.ring.id() {
+            return (self.local_put)(key, version).map_err(|e| e.to_string());
+        }
+        let resp = self
+            .client
+            .post(format!("http://{}/_replica/{}", node, ring::escape_key(key)))
+            .body(version.encode())
+            .send()
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("replica answered {}", resp.status()));
+        }
+        Ok(())
+    }
+
+    fn get_from(&self, node: &str, key: &str) -> Result<Option<Versioned>, String> {
+        if node == self.ring.id() {
+            return (self.local_get)(key).map_err(|e| e.to_string());
+        }
+        let resp = self
+            .client
+            .get(format!("http://{}/_replica/{}", node, ring::escape_key(key)))
+            .send()
+            .map_err(|e| e.to_string())?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.status().is_success() {
+            return Err(format!("replica answered {}", resp.status()));
+        }
+        let body = resp.text().map_err(|e| e.to_string())?;
+        Ok(Versioned::decode(&body))
+    }
+}
+
+fn newest(answers: &[(String, Option<Versioned>)]) -> Option<Versioned> {
+    answers
+        .iter()
+        .filter_map(|(_, v)| v.clone())
+        .max_by_key(|v| v.ts)
+}
@@ -0,0 +1,775 @@
+use null_common::error::DbError;
+use null_common::fsync;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::prelude::*;
+use std::io::{BufReader, Error};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+// Term and vote, rewritten whenever either changes.
+const STATE_FILE: &str = "raft.state";
+// One JSON entry per line, appended to and rewritten when truncated.
+const LOG_FILE: &str = "raft.log";
+// Everything the state machine held at the last compacted index.
+const SNAPSHOT_FILE: &str = "raft.snapshot";
+
+const HEARTBEAT: Duration = Duration::from_millis(50);
+const RPC_TIMEOUT: Duration = Duration::from_millis(200);
+const PROPOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// Applied entries to keep in the log before folding them into a snapshot.
+const SNAPSHOT_EVERY: u64 = 1000;
+// Most entries shipped in a single AppendEntries call.
+const MAX_APPEND: usize = 500;
+
+// An empty key is the no-op a new leader appends to commit earlier terms.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    pub term: u64,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate: String,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VoteResponse {
+    pub term: u64,
+    pub granted: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AppendRequest {
+    pub term: u64,
+    pub leader: String,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<Entry>,
+    pub leader_commit: u64,
+}
+
+// On failure match_index is a hint for where the leader should back up to.
+#[derive(Serialize, Deserialize)]
+pub struct AppendResponse {
+    pub term: u64,
+    pub success: bool,
+    pub match_index: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+    pub data: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotRequest {
+    pub term: u64,
+    pub leader: String,
+    pub snapshot: Snapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotResponse {
+    pub term: u64,
+}
+
+#[derive(Debug)]
+pub enum ProposeError {
+    NotLeader(Option<String>),
+    Timeout,
+    Io(Error),
+}
+
+// NotLeader with a known leader is answered with a redirect before we get here.
+impl From<ProposeError> for DbError {
+    fn from(e: ProposeError) -> Self {
+        match e {
+            ProposeError::NotLeader(_) => DbError::Unavailable("no raft leader elected yet".to_string()),
+            ProposeError::Timeout => DbError::Unavailable("write not committed in time".to_string()),
+            ProposeError::Io(e) => e.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Serialize)]
+pub struct RaftStatus {
+    pub id: String,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<String>,
+    pub commit_index: u64,
+    pub last_applied: u64,
+    pub last_log_index: u64,
+    pub snapshot_index: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct HardState {
+    current_term: u64,
+    voted_for: Option<String>,
+}
+
+struct State {
+    hard: HardState,
+    // entries after snapshot_index, so log[0] is index snapshot_index + 1
+    log: Vec<Entry>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    commit_index: u64,
+    last_applied: u64,
+    role: Role,
+    leader: Option<String>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    election_deadline: Instant,
+    last_heartbeat: Instant,
+    // installed by a leader, waiting for the applier to load it
+    pending_snapshot: Option<Snapshot>,
+}
+
+impl State {
+    fn last_index(&self) -> u64 {
+        self.snapshot_index + self.log.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log.last().map(|e| e.term).unwrap_or(self.snapshot_term)
+    }
+
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        if index < self.snapshot_index {
+            return None;
+        }
+        self.log.get((index - self.snapshot_index - 1) as usize).map(|e| e.term)
+    }
+
+    fn entries_from(&self, index: u64, max: usize) -> Vec<Entry> {
+        let start = (index - self.snapshot_index - 1) as usize;
+        self.log.iter().skip(start).take(max).cloned().collect()
+    }
+
+    fn become_follower(&mut self, term: u64) -> Result<(), Error> {
+        if term > self.hard.current_term {
+            self.hard.current_term = term;
+            self.hard.voted_for = None;
+            save_hard_state(&self.hard)?;
+        }
+        self.role = Role::Follower;
+        Ok(())
+    }
+}
+
+pub struct Raft {
+    id: String,
+    peers: Vec<String>,
+    state: Mutex<State>,
+    applied: Condvar,
+    client: reqwest::blocking::Client,
+}
+
+impl Raft {
+    pub fn new(id: String, peers: Vec<String>) -> Result<Arc<Self>, Error> {
+        let hard = load_hard_state()?;
+        let log = load_log()?;
+        let snapshot = load_snapshot()?;
+        let (snapshot_index, snapshot_term) = snapshot
+            .as_ref()
+            .map(|s| (s.last_included_index, s.last_included_term))
+            .unwrap_or((0, 0));
+
+        // The state machine is the segment files, which survived the restart
+        // with everything up to some unknown index applied. Committed entries
+        // are re-applied from the snapshot onwards, which is safe because
+        // replaying puts and deletes in order lands on the same values.
+        let state = State {
+            hard,
+            log,
+            snapshot_index,
+            snapshot_term,
+            commit_index: snapshot_index,
+            last_applied: snapshot_index,
+            role: Role::Follower,
+            leader: None,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            election_deadline: Instant::now() + election_timeout(),
+            last_heartbeat: Instant::now(),
+            pending_snapshot: None,
+        };
+
+        Ok(Arc::new(Raft {
+            id,
+            peers,
+            state: Mutex::new(state),
+            applied: Condvar::new(),
+            client: reqwest::blocking::Client::builder()
+                .timeout(RPC_TIMEOUT)
+                .build()
+                .unwrap(),
+        }))
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        let s = self.state.lock().unwrap();
+        RaftStatus {
+            id: self.id.clone(),
+            role: s.role,
+            term: s.hard.current_term,
+            leader: s.leader.clone(),
+            commit_index: s.commit_index,
+            last_applied: s.last_applied,
+            last_log_index: s.last_index(),
+            snapshot_index: s.snapshot_index,
+        }
+    }
+
+    fn majority(&self) -> usize {
+        let cluster = self.peers.len() + 1;
+        cluster / 2 + 1
+    }
+
+    // Runs elections, heartbeats and the state machine on a background thread.
+    //   apply: write one committed key/value to the engine
+    //   snapshot: dump every live key/value the engine holds
+    //   install: replace the engine's contents with a snapshot
+    pub fn start<A, S, I>(self: &Arc<Self>, apply: A, snapshot: S, install: I)
+    where
+        A: Fn(&str, &str) -> Result<(), Error> + Send + 'static,
+        S: Fn() -> Result<HashMap<String, String>, Error> + Send + 'static,
+        I: Fn(&HashMap<String, String>) -> Result<(), Error> + Send + 'static,
+    {
+        let raft = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_millis(10));
+
+            let (role, deadline, heartbeat) = {
+                let s = raft.state.lock().unwrap();
+                (s.role, s.election_deadline, s.last_heartbeat)
+            };
+            match role {
+                Role::Leader => {
+                    if heartbeat.elapsed() >= HEARTBEAT {
+                        raft.replicate();
+                    }
+                }
+                _ => {
+                    if Instant::now() >= deadline {
+                        raft.campaign();
+                    }
+                }
+            }
+
+            if let Err(e) = raft.apply_committed(&apply, &install) {
+                error!(error = %e, "raft couldn't apply entries");
+            }
+            if let Err(e) = raft.maybe_snapshot(&snapshot) {
+                error!(error = %e, "raft couldn't take a snapshot");
+            }
+        });
+    }
+
+    // Appends a write to the leader's log and blocks until a majority has it
+    // and it has been applied locally.
+    pub fn propose(&self, key: &str, value: &str) -> Result<(), ProposeError> {
+        let (index, term) = {
+            let mut s = self.state.lock().unwrap();
+            if s.role != Role::Leader {
+                return Err(ProposeError::NotLeader(s.leader.clone()));
+            }
+            let entry = Entry {
+                term: s.hard.current_term,
+                key: key.to_string(),
+                value: value.to_string(),
+            };
+            append_log(std::slice::from_ref(&entry)).map_err(ProposeError::Io)?;
+            s.log.push(entry);
+            (s.last_index(), s.hard.current_term)
+        };
+
+        let started = Instant::now();
+        let mut s = self.state.lock().unwrap();
+        while s.last_applied < index {
+            let waited = started.elapsed();
+            if waited >= PROPOSE_TIMEOUT {
+                return Err(ProposeError::Timeout);
+            }
+            s = self.applied.wait_timeout(s, PROPOSE_TIMEOUT - waited).unwrap().0;
+        }
+
+        // a new leader may have overwritten the entry before it committed
+        match s.term_at(index) {
+            Some(t) if t != term => Err(ProposeError::NotLeader(s.leader.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn handle_vote(&self, req: VoteRequest) -> Result<VoteResponse, Error> {
+        let mut s = self.state.lock().unwrap();
+        if req.term > s.hard.current_term {
+            s.become_follower(req.term)?;
+        }
+
+        let up_to_date = req.last_log_term > s.last_term()
+            || (req.last_log_term == s.last_term() && req.last_log_index >= s.last_index());
+        let free = match &s.hard.voted_for {
+            None => true,
+            Some(candidate) => *candidate == req.candidate,
+        };
+        let granted = req.term == s.hard.current_term && free && up_to_date;
+        if granted {
+            s.hard.voted_for = Some(req.candidate);
+            save_hard_state(&s.hard)?;
+            s.election_deadline = Instant::now() + election_timeout();
+        }
+
+        Ok(VoteResponse {
+            term: s.hard.current_term,
+            granted,
+        })
+    }
+
+    pub fn handle_append(&self, req: AppendRequest) -> Result<AppendResponse, Error> {
+        let mut s = self.state.lock().unwrap();
+        if req.term < s.hard.current_term {
+            return Ok(AppendResponse {
+                term: s.hard.current_term,
+                success: false,
+                match_index: 0,
+            });
+        }
+        s.become_follower(req.term)?;
+        s.leader = Some(req.leader);
+        s.election_deadline = Instant::now() + election_timeout();
+
+        let consistent = req.prev_log_index <= s.last_index()
+            && match s.term_at(req.prev_log_index) {
+                Some(term) => term == req.prev_log_term,
+                // already folded into our snapshot, so it was committed
+                None => true,
+            };
+        if !consistent {
+            return Ok(AppendResponse {
+                term: s.hard.current_term,
+                success: false,
+                match_index: s.commit_index.min(s.last_index()),
+            });
+        }
+
+        let mut truncated = false;
+        let mut appended = Vec::new();
+        for (i, entry) in req.entries.iter().enumerate() {
+            let index = req.prev_log_index + 1 + i as u64;
+            if index <= s.snapshot_index {
+                continue;
+            }
+            match s.term_at(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    let keep = (index - s.snapshot_index - 1) as usize;
+                    s.log.truncate(keep);
+                    truncated = true;
+                }
+                None => {}
+            }
+            s.log.push(entry.clone());
+            appended.push(entry.clone());
+        }
+        if truncated {
+            rewrite_log(&s.log)?;
+        } else if !appended.is_empty() {
+            append_log(&appended)?;
+        }
+
+        let match_index = req.prev_log_index + req.entries.len() as u64;
+        if req.leader_commit > s.commit_index {
+            s.commit_index = req.leader_commit.min(match_index);
+        }
+
+        Ok(AppendResponse {
+            term: s.hard.current_term,
+            success: true,
+            match_index,
+        })
+    }
+
+    pub fn handle_snapshot(&self, req: SnapshotRequest) -> Result<SnapshotResponse, Error> {
+        let mut s = self.state.lock().unwrap();
+        if req.term < s.hard.current_term {
+            return Ok(SnapshotResponse { term: s.hard.current_term });
+        }
+        s.become_follower(req.term)?;
+        s.leader = Some(req.leader);
+        s.election_deadline = Instant::now() + election_timeout();
+
+        let snapshot = req.snapshot;
+        if snapshot.last_included_index <= s.snapshot_index {
+            return Ok(SnapshotResponse { term: s.hard.current_term });
+        }
+
+        // keep any log that follows on from the snapshot, otherwise drop it all
+        if s.term_at(snapshot.last_included_index) == Some(snapshot.last_included_term) {
+            let drop = (snapshot.last_included_index - s.snapshot_index) as usize;
+            s.log.drain(..drop);
+        } else {
+            s.log.clear();
+        }
+        s.snapshot_index = snapshot.last_included_index;
+        s.snapshot_term = snapshot.last_included_term;
+        s.commit_index = s.commit_index.max(s.snapshot_index);
+        save_snapshot(&snapshot)?;
+        rewrite_log(&s.log)?;
+        s.pending_snapshot = Some(snapshot);
+
+        Ok(SnapshotResponse { term: s.hard.current_term })
+    }
+
+    fn campaign(&self) {
+        let req = {
+            let mut s = self.state.lock().unwrap();
+            s.hard.current_term += 1;
+            s.hard.voted_for = Some(self.id.clone());
+            if let Err(e) = save_hard_state(&s.hard) {
+                error!(error = %e, "raft couldn't persist vote");
+                return;
+            }
+            s.role = Role::Candidate;
+            s.leader = None;
+            s.election_deadline = Instant::now() + election_timeout();
+            VoteRequest {
+                term: s.hard.current_term,
+                candidate: self.id.clone(),
+                last_log_index: s.last_index(),
+                last_log_term: s.last_term(),
+            }
+        };
+
+        let responses: Vec<VoteResponse> = thread::scope(|scope| {
+            let req = &req;
+            let calls = self
+                .peers
+                .iter()
+                .map(|peer| scope.spawn(move || self.call::<_, VoteResponse>(peer, "vote", &req)))
+                .collect::<Vec<_>>();
+            calls.into_iter().filter_map(|c| c.join().ok().flatten()).collect()
+        });
+
+        let mut s = self.state.lock().unwrap();
+        if s.role != Role::Candidate || s.hard.current_term != req.term {
+            return;
+        }
+        let mut votes = 1;
+        for resp in responses {
+            if resp.term > s.hard.current_term {
+                let _ = s.become_follower(resp.term);
+                return;
+            }
+            if resp.granted {
+                votes += 1;
+            }
+        }
+        if votes < self.majority() {
+            return;
+        }
+
+        s.role = Role::Leader;
+        s.leader = Some(self.id.clone());
+        let next = s.last_index() + 1;
+        for peer in &self.peers {
+            s.next_index.insert(peer.clone(), next);
+            s.match_index.insert(peer.clone(), 0);
+        }
+        // entries from earlier terms only commit once one from ours does
+        let noop = Entry {
+            term: s.hard.current_term,
+            key: String::new(),
+            value: String::new(),
+        };
+        if let Err(e) = append_log(std::slice::from_ref(&noop)) {
+            error!(error = %e, "raft couldn't append no-op");
+            s.role = Role::Follower;
+            return;
+        }
+        s.log.push(noop);
+        info!(id = %self.id, term = s.hard.current_term, "became raft leader");
+        drop(s);
+        self.replicate();
+    }
+
+    fn replicate(&self) {
+        self.state.lock().unwrap().last_heartbeat = Instant::now();
+        thread::scope(|scope| {
+            for peer in &self.peers {
+                scope.spawn(move || self.replicate_to(peer));
+            }
+        });
+        self.advance_commit();
+    }
+
+    fn replicate_to(&self, peer: &str) {
+        enum Call {
+            Append(AppendRequest),
+            Snapshot(SnapshotRequest),
+        }
+
+        let (call, term) = {
+            let s = self.state.lock().unwrap();
+            if s.role != Role::Leader {
+                return;
+            }
+            let next = *s.next_index.get(peer).unwrap_or(&1);
+            let call = if next <= s.snapshot_index {
+                match load_snapshot() {
+                    Ok(Some(snapshot)) => Call::Snapshot(SnapshotRequest {
+                        term: s.hard.current_term,
+                        leader: self.id.clone(),
+                        snapshot,
+                    }),
+                    _ => return,
+                }
+            } else {
+                let prev = next - 1;
+                Call::Append(AppendRequest {
+                    term: s.hard.current_term,
+                    leader: self.id.clone(),
+                    prev_log_index: prev,
+                    prev_log_term: s.term_at(prev).unwrap_or(0),
+                    entries: s.entries_from(next, MAX_APPEND),
+                    leader_commit: s.commit_index,
+                })
+            };
+            (call, s.hard.current_term)
+        };
+
+        match call {
+            Call::Append(req) => {
+                let prev = req.prev_log_index;
+                if let Some(resp) = self.call::<_, AppendResponse>(peer, "append", &req) {
+                    let mut s = self.state.lock().unwrap();
+                    if resp.term > s.hard.current_term {
+                        let _ = s.become_follower(resp.term);
+                    } else if s.role == Role::Leader && s.hard.current_term == term {
+                        if resp.success {
+                            s.match_index.insert(peer.to_string(), resp.match_index);
+                            s.next_index.insert(peer.to_string(), resp.match_index + 1);
+                        } else {
+                            let next = (resp.match_index + 1).min(prev).max(1);
+                            s.next_index.insert(peer.to_string(), next);
+                        }
+                    }
+                }
+            }
+            Call::Snapshot(req) => {
+                let installed = req.snapshot.last_included_index;
+                if let Some(resp) = self.call::<_, SnapshotResponse>(peer, "snapshot", &req) {
+                    let mut s = self.state.lock().unwrap();
+                    if resp.term > s.hard.current_term {
+                        let _ = s.become_follower(resp.term);
+                    } else if s.role == Role::Leader && s.hard.current_term == term {
+                        s.match_index.insert(peer.to_string(), installed);
+                        s.next_index.insert(peer.to_string(), installed + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    // Commits the highest index from this term that a majority has stored.
+    fn advance_commit(&self) {
+        let mut s = self.state.lock().unwrap();
+        if s.role != Role::Leader {
+            return;
+        }
+        let mut index = s.last_index();
+        while index > s.commit_index {
+            if s.term_at(index) == Some(s.hard.current_term) {
+                let replicas = 1 + s.match_index.values().filter(|m| **m >= index).count();
+                if replicas >= self.majority() {
+                    s.commit_index = index;
+                    break;
+                }
+            }
+            index -= 1;
+        }
+    }
+
+    // Only the background thread applies, so entries reach the engine in order.
+    fn apply_committed<A, I>(&self, apply: &A, install: &I) -> Result<(), Error>
+    where
+        A: Fn(&str, &str) -> Result<(), Error>,
+        I: Fn(&HashMap<String, String>) -> Result<(), Error>,
+    {
+        let pending = self.state.lock().unwrap().pending_snapshot.take();
+        if let Some(snapshot) = pending {
+            install(&snapshot.data)?;
+            let mut s = self.state.lock().unwrap();
+            s.last_applied = s.last_applied.max(snapshot.last_included_index);
+            self.applied.notify_all();
+        }
+
+        let (from, entries) = {
+            let s = self.state.lock().unwrap();
+            // a snapshot arrived since we looked, load it first next time round
+            if s.commit_index <= s.last_applied || s.last_applied < s.snapshot_index {
+                return Ok(());
+            }
+            let count = (s.commit_index - s.last_applied) as usize;
+            (s.last_applied, s.entries_from(s.last_applied + 1, count))
+        };
+
+        for entry in &entries {
+            if !entry.key.is_empty() {
+                apply(&entry.key, &entry.value)?;
+            }
+        }
+
+        let mut s = self.state.lock().unwrap();
+        s.last_applied = s.last_applied.max(from + entries.len() as u64);
+        self.applied.notify_all();
+        Ok(())
+    }
+
+    fn maybe_snapshot<S>(&self, snapshot: &S) -> Result<(), Error>
+    where
+        S: Fn() -> Result<HashMap<String, String>, Error>,
+    {
+        let last_applied = {
+            let s = self.state.lock().unwrap();
+            if s.last_applied < s.snapshot_index + SNAPSHOT_EVERY {
+                return Ok(());
+            }
+            s.last_applied
+        };
+
+        // nothing else applies while we are here, so the dump matches last_applied
+        let data = snapshot()?;
+
+        let mut s = self.state.lock().unwrap();
+        if last_applied <= s.snapshot_index {
+            return Ok(());
+        }
+        let snapshot = Snapshot {
+            last_included_index: last_applied,
+            last_included_term: s.term_at(last_applied).unwrap_or(s.snapshot_term),
+            data,
+        };
+        save_snapshot(&snapshot)?;
+        let drop = (last_applied - s.snapshot_index) as usize;
+        s.log.drain(..drop);
+        s.snapshot_index = snapshot.last_included_index;
+        s.snapshot_term = snapshot.last_included_term;
+        rewrite_log(&s.log)
+    }
+
+    fn call<Req: Serialize, Resp: for<'de> Deserialize<'de>>(
+        &self,
+        peer: &str,
+        rpc: &str,
+        req: &Req,
+    ) -> Option<Resp> {
+        self.client
+            .post(format!("http://{}/_raft/{}", peer, rpc))
+            .json(req)
+            .send()
+            .ok()?
+            .json()
+            .ok()
+    }
+}
+
+// 300-600ms, randomised so nodes rarely stand for election at the same time.
+fn election_timeout() -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(300 + random % 300)
+}
+
+fn save_hard_state(hard: &HardState) -> Result<(), Error> {
+    replace_file(STATE_FILE, &serde_json::to_vec(hard)?)
+}
+
+fn load_hard_state() -> Result<HardState, Error> {
+    match std::fs::read(STATE_FILE) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(_) => Ok(HardState::default()),
+    }
+}
+
+fn append_log(entries: &[Entry]) -> Result<(), Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(LOG_FILE)?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    fsync::timed(|| file.sync_data())
+}
+
+fn rewrite_log(entries: &[Entry]) -> Result<(), Error> {
+    let tmp = format!("{}.tmp", LOG_FILE);
+    {
+        let mut file = File::create(&tmp)?;
+        for entry in entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        fsync::timed(|| file.sync_data())?;
+    }
+    std::fs::rename(tmp, LOG_FILE)?;
+    fsync::sync_dir(Path::new("."))
+}
+
+fn load_log() -> Result<Vec<Entry>, Error> {
+    let file = match File::open(LOG_FILE) {
+        Ok(file) => file,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        entries.push(serde_json::from_str(&line?)?);
+    }
+    Ok(entries)
+}
+
+fn save_snapshot(snapshot: &Snapshot) -> Result<(), Error> {
+    replace_file(SNAPSHOT_FILE, &serde_json::to_vec(snapshot)?)
+}
+
+fn load_snapshot() -> Result<Option<Snapshot>, Error> {
+    match std::fs::read(SNAPSHOT_FILE) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(_) => Ok(None),
+    }
+}
+
+// Writes bytes under a temporary name and renames it over path, so a crash
+// leaves either the old file or the new one, never half of either.
+fn replace_file(path: &str, bytes: &[u8]) -> Result<(), Error> {
+    let tmp = format!("{}.tmp", path);
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(bytes)?;
+        fsync::timed(|| file.sync_all())?;
+    }
+    std::fs::rename(tmp, path)?;
+    fsync::sync_dir(Path::new("."))
+}
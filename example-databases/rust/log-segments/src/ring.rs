@@ -0,0 +1,262 @@
+use fnv::FnvHasher;
+use null_common::logging;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::hash::Hasher;
+use std::io::Error;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+// Remembers members that joined after startup so a restart keeps routing the
+// same way even if the command line was never updated.
+const RING_FILE: &str = "null.ring";
+
+// Set on requests one node passes to another, so they are never passed on again.
+pub const FORWARDED_HEADER: &str = "x-null-forwarded";
+
+// Records moved to a new owner per handoff call.
+const HANDOFF_BATCH: usize = 500;
+
+// What a key is escaped from when it goes into a URL. actix decodes every
+// escape but %2F and %2B, so '+' is sent as is and everything else the path
+// could take another way, '.' included, is escaped.
+const KEY_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'~').remove(b'+');
+
+#[derive(Serialize)]
+pub struct RingStatus {
+    pub id: String,
+    pub vnodes: usize,
+    pub members: Vec<String>,
+}
+
+pub struct Ring {
+    id: String,
+    vnodes: usize,
+    members: RwLock<BTreeSet<String>>,
+    // hash of each virtual node -> the member that owns it
+    tokens: RwLock<BTreeMap<u64, String>>,
+    client: reqwest::blocking::Client,
+}
+
+impl Ring {
+    pub fn new(id: String, members: Vec<String>, vnodes: usize) -> Result<Arc<Self>, Error> {
+        let mut all = load_members()?;
+        all.insert(id.clone());
+        all.extend(members);
+
+        let ring = Ring {
+            id,
+            vnodes,
+            members: RwLock::new(BTreeSet::new()),
+            tokens: RwLock::new(BTreeMap::new()),
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap(),
+        };
+        ring.set_members(all)?;
+        Ok(Arc::new(ring))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn status(&self) -> RingStatus {
+        RingStatus {
+            id: self.id.clone(),
+            vnodes: self.vnodes,
+            members: self.members(),
+        }
+    }
+
+    pub fn members(&self) -> Vec<String> {
+        self.members.read().unwrap().iter().cloned().collect()
+    }
+
+    // The first virtual node clockwise from the key's hash owns it.
+    pub fn owner(&self, key: &str) -> String {
+        let tokens = self.tokens.read().unwrap();
+        let h = hash(key);
+        tokens
+            .range(h..)
+            .next()
+            .or_else(|| tokens.iter().next())
+            .map(|(_, node)| node.clone())
+            .unwrap_or_else(|| self.id.clone())
+    }
+
+    // The owner followed by the next distinct members clockwise, n at most.
+    pub fn preference_list(&self, key: &str, n: usize) -> Vec<String> {
+        let tokens = self.tokens.read().unwrap();
+        let h = hash(key);
+        let mut nodes: Vec<String> = Vec::new();
+        for (_, node) in tokens.range(h..).chain(tokens.range(..h)) {
+            if nodes.len() == n {
+                break;
+            }
+            if !nodes.contains(node) {
+                nodes.push(node.clone());
+            }
+        }
+        nodes
+    }
+
+    pub fn is_local(&self, key: &str) -> bool {
+        self.owner(key) == self.id
+    }
+
+    // Adds any members we didn't know about. Returns true if the ring changed.
+    pub fn merge_members(&self, members: Vec<String>) -> Result<bool, Error> {
+        let mut all = self.members.read().unwrap().clone();
+        let before = all.len();
+        all.extend(members);
+        if all.len() == before {
+            return Ok(false);
+        }
+        self.set_members(all)?;
+        Ok(true)
+    }
+
+    fn set_members(&self, members: BTreeSet<String>) -> Result<(), Error> {
+        let mut tokens = BTreeMap::new();
+        for node in &members {
+            for v in 0..self.vnodes {
+                tokens.insert(hash(&format!("{}#{}", node, v)), node.clone());
+            }
+        }
+        save_members(&members)?;
+        *self.tokens.write().unwrap() = tokens;
+        *self.members.write().unwrap() = members;
+        Ok(())
+    }
+
+    // Tells every other member about the whole ring, so a new node only needs
+    // to be started with the existing members listed to join.
+    pub fn announce(self: &Arc<Self>) {
+        let ring = self.clone();
+        thread::spawn(move || {
+            let members = ring.members();
+            for node in members.iter().filter(|n| **n != ring.id) {
+                let sent = ring
+                    .client
+                    .post(format!("http://{}/_ring/members", node))
+                    .json(&members)
+                    .send();
+                if let Err(e) = sent {
+                    warn!(node = %node, error = %e, "couldn't announce ring");
+                }
+            }
+        });
+    }
+
+    // Passes a client request to the node that owns the key, under the same
+    // request id so its logs line up with ours.
+    pub fn forward(
+        &self,
+        method: reqwest::Method,
+        node: &str,
+        key: &str,
+        body: Option<String>,
+        request_id: Option<String>,
+    ) -> Result<(u16, String), String> {
+        let mut req = self
+            .client
+            .request(method, format!("http://{}/{}", node, escape_key(key)))
+            .header(FORWARDED_HEADER, self.id.as_str());
+        if let Some(id) = request_id {
+            req = req.header(logging::REQUEST_ID_HEADER, id);
+        }
+        if let Some(body) = body {
+            req = req.body(body);
+        }
+        let resp = req.send().map_err(|e| e.to_string())?;
+        let status = resp.status().as_u16();
+        Ok((status, resp.text().map_err(|e| e.to_string())?))
+    }
+
+    // Streams every local record that no longer belongs here to its new owner,
+    // then deletes it locally. Reads of moved keys miss until this finishes.
+    //   scan: every live key/value on this node
+    //   delete: drop a key from this node once its owner has it
+    pub fn hand_off<S, D>(self: &Arc<Self>, scan: S, delete: D)
+    where
+        S: Fn() -> Result<HashMap<String, String>, Error> + Send + 'static,
+        D: Fn(&str) -> Result<(), Error> + Send + 'static,
+    {
+        let ring = self.clone();
+        thread::spawn(move || {
+            let records = match scan() {
+                Ok(records) => records,
+                Err(e) => {
+                    error!(error = %e, "couldn't scan records for handoff");
+                    return;
+                }
+            };
+
+            let mut moving: HashMap<String, Vec<(String, String)>> = HashMap::new();
+            for (key, value) in records {
+                let owner = ring.owner(&key);
+                if owner != ring.id {
+                    moving.entry(owner).or_default().push((key, value));
+                }
+            }
+
+            for (node, records) in moving {
+                for batch in records.chunks(HANDOFF_BATCH) {
+                    let sent = ring
+                        .client
+                        .post(format!("http://{}/_ring/handoff", node))
+                        .json(&batch)
+                        .send();
+                    match sent {
+                        Ok(resp) if resp.status().is_success() => {
+                            for (key, _) in batch {
+                                if let Err(e) = delete(key) {
+                                    error!(key = %key, error = %e, "couldn't drop handed off key");
+                                }
+                            }
+                        }
+                        Ok(resp) => warn!(node = %node, status = %resp.status(), "handoff refused"),
+                        Err(e) => warn!(node = %node, error = %e, "handoff failed"),
+                    }
+                }
+                info!(keys = records.len(), node = %node, "handed off keys");
+            }
+        });
+    }
+}
+
+// FNV-1a is stable across processes and builds, unlike the std hasher. Its
+// high bits barely move for keys that differ only at the end ("k1", "k2"), so
+// they get a murmur3 finaliser mixed in before landing on the ring.
+pub fn hash(s: &str) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(s.as_bytes());
+    let mut h = hasher.finish();
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+fn load_members() -> Result<BTreeSet<String>, Error> {
+    match std::fs::read_to_string(RING_FILE) {
+        Ok(text) => Ok(text.lines().map(|l| l.to_string()).collect()),
+        Err(_) => Ok(BTreeSet::new()),
+    }
+}
+
+fn save_members(members: &BTreeSet<String>) -> Result<(), Error> {
+    let text = members.iter().map(|m| format!("{}\n", m)).collect::<String>();
+    std::fs::write(RING_FILE, text)
+}
+
+// key as a single path segment another member reads back as the same key.
+pub fn escape_key(key: &str) -> String {
+    utf8_percent_encode(key, KEY_ESCAPES).to_string()
+}
@@ -0,0 +1,252 @@
+// Runs several disk-log servers, each in a directory and on a port of its
+// own, and checks what they do together.
+mod node;
+
+use node::{wait_for, Node};
+
+// flag followed by the address of every node but skip
+fn peers(flag: &str, nodes: &[&Node], skip: usize) -> Vec<String> {
+    let mut args = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if i != skip {
+            args.push(flag.to_string());
+            args.push(node.addr());
+        }
+    }
+    args
+}
+
+#[test]
+fn follower_catches_up_with_the_leader() {
+    let mut leader = Node::new("follower", "leader");
+    leader.start(&[]);
+    for i in 0..100 {
+        assert!(leader.put(&format!("k{}", i), &format!("v{}", i)).is_success());
+    }
+
+    let mut follower = Node::new("follower", "follower");
+    follower.start(&["--follow".to_string(), leader.addr()]);
+    wait_for("follower to catch up", || follower.get("k99").as_deref() == Some("v99"));
+    assert_eq!(follower.get("k0").as_deref(), Some("v0"));
+    assert_eq!(follower.put("k0", "nope").as_u16(), 405);
+
+    // and keeps up with what comes after
+    assert!(leader.put("k0", "changed").is_success());
+    wait_for("follower to apply the change", || follower.get("k0").as_deref() == Some("changed"));
+
+    // the leader knows how far it has read
+    wait_for("leader to see the follower", || {
+        let stats = leader.json("_admin/stats");
+        let end = stats["replication_log_end"].as_u64().unwrap();
+        let followers = stats["followers"].as_object().unwrap().clone();
+        followers.len() == 1 && followers.values().all(|offset| offset.as_u64() == Some(end))
+    });
+}
+
+#[test]
+fn raft_elects_a_leader_and_catches_up_a_restarted_follower() {
+    let mut nodes = [Node::new("raft", "a"), Node::new("raft", "b"), Node::new("raft", "c")];
+    let args: Vec<Vec<String>> = (0..3).map(|i| peers("--raft-peer", &nodes.iter().collect::<Vec<_>>(), i)).collect();
+    for (node, args) in nodes.iter_mut().zip(&args) {
+        node.start(args);
+    }
+
+    let leader_of = |nodes: &[Node], skip: Option<usize>| -> Option<usize> {
+        nodes.iter().enumerate().filter(|(i, _)| Some(*i) != skip).find_map(|(i, node)| {
+            let stats = node.json("_admin/stats");
+            (stats["raft"]["role"] == "Leader").then_some(i)
+        })
+    };
+    let mut leader = None;
+    wait_for("a leader", || {
+        leader = leader_of(&nodes, None);
+        leader.is_some()
+    });
+    let leader = leader.unwrap();
+    assert!(nodes[leader].put("a", "1").is_success());
+
+    // followers send writes to the leader
+    let follower = (leader + 1) % 3;
+    let resp = nodes[follower].client.post(nodes[follower].url("a")).body("2").send().unwrap();
+    assert_eq!(resp.status().as_u16(), 307);
+    assert_eq!(resp.headers()["location"], format!("http://{}/a", nodes[leader].addr()).as_str());
+
+    // a majority is still there with one follower down
+    nodes[follower].kill();
+    for i in 0..50 {
+        assert!(nodes[leader].put(&format!("k{}", i), "after").is_success());
+    }
+    nodes[follower].restart();
+    wait_for("restarted follower to catch up", || nodes[follower].get("k49").as_deref() == Some("after"));
+    assert_eq!(nodes[follower].get("a").as_deref(), Some("1"));
+
+    // losing the leader brings on an election among the rest
+    nodes[leader].kill();
+    let mut next = None;
+    wait_for("a new leader", || {
+        next = leader_of(&nodes, Some(leader));
+        next.is_some()
+    });
+    let next = next.unwrap();
+    assert!(nodes[next].put("a", "3").is_success());
+    nodes[leader].restart();
+    wait_for("old leader to follow the new one", || nodes[leader].get("a").as_deref() == Some("3"));
+}
+
+#[test]
+fn ring_hands_keys_to_a_member_that_joins() {
+    let mut a = Node::new("ring", "a");
+    let mut b = Node::new("ring", "b");
+    let mut c = Node::new("ring", "c");
+    a.start(&["--ring-node".to_string(), b.addr()]);
+    b.start(&["--ring-node".to_string(), a.addr()]);
+    for i in 0..200 {
+        assert!(a.put(&format!("k{}", i), &format!("v{}", i)).is_success());
+    }
+    // each key lives on its owner alone
+    let (on_a, on_b) = (a.exported_keys().len(), b.exported_keys().len());
+    assert_eq!(on_a + on_b, 200);
+    assert!(on_a > 0 && on_b > 0);
+
+    c.start(&["--ring-node".to_string(), a.addr(), "--ring-node".to_string(), b.addr()]);
+    wait_for("everyone to know the new member", || {
+        [&a, &b, &c].iter().all(|node| node.json("_admin/stats")["ring"]["members"].as_array().unwrap().len() == 3)
+    });
+    // writes racing the handoff reach the new owner first, and win
+    for i in 0..200 {
+        if i % 10 == 0 {
+            let resp = a.client.delete(a.url(&format!("k{}", i))).send().unwrap();
+            assert!(resp.status().is_success());
+        } else {
+            assert!(a.put(&format!("k{}", i), &format!("new{}", i)).is_success());
+        }
+    }
+    wait_for("handoff to the new member", || {
+        let counts: Vec<usize> = [&a, &b, &c].iter().map(|node| node.exported_keys().len()).collect();
+        counts[2] > 0 && counts.iter().sum::<usize>() == 180
+    });
+    // and a handoff that arrives late, whatever the timing above
+    let moved = c.exported_keys()[0].clone();
+    let stale = [(moved.clone(), "stale".to_string())];
+    assert!(c.client.post(c.url("_ring/handoff")).json(&stale).send().unwrap().status().is_success());
+    assert_ne!(c.get(&moved).as_deref(), Some("stale"));
+    for i in 0..200 {
+        let key = format!("k{}", i);
+        let want = if i % 10 == 0 { None } else { Some(format!("new{}", i)) };
+        for node in [&a, &b, &c].iter() {
+            assert_eq!(node.get(&key), want);
+        }
+    }
+}
+
+#[test]
+fn ring_passes_the_request_id_to_the_owner() {
+    let mut a = Node::new("request-id", "a");
+    let mut b = Node::new("request-id", "b");
+    a.start(&["--ring-node".to_string(), b.addr()]);
+    b.start(&["--ring-node".to_string(), a.addr()]);
+
+    let resp = a.client.get(a.url("_admin/stats")).send().unwrap();
+    assert!(!resp.headers()["x-request-id"].is_empty());
+
+    // a key b owns, written through a
+    for i in 0.. {
+        let (key, id) = (format!("k{}", i), format!("trace-{}", i));
+        let resp = a.client.post(a.url(&key)).header("x-request-id", id.as_str()).body("v").send().unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers()["x-request-id"], id.as_str());
+        if b.exported_keys().contains(&key) {
+            assert!(a.log().contains(&id));
+            assert!(b.log().contains(&id));
+            break;
+        }
+    }
+}
+
+#[test]
+fn quorums_the_replicas_cant_meet_are_refused() {
+    let node = Node::new("quorum-settings", "a");
+    let other = Node::new("quorum-settings", "b").addr();
+    let ring = ["--ring-node", other.as_str()];
+    let refused = |args: &[&str]| node.refuse(&[&ring[..], args].concat());
+    assert!(refused(&["--replicas", "1"]).contains("--read-quorum is 2"));
+    assert!(refused(&["--replicas", "3", "--write-quorum", "4"]).contains("--write-quorum is 4"));
+    assert!(refused(&["--replicas", "3", "--read-quorum", "0"]).contains("--read-quorum is 0"));
+    assert!(refused(&["--replicas", "0"]).contains("--replicas must be at least 1"));
+}
+
+#[test]
+fn anti_entropy_repairs_a_replica_that_missed_writes() {
+    let mut nodes = [Node::new("quorum", "a"), Node::new("quorum", "b"), Node::new("quorum", "c")];
+    let refs: Vec<&Node> = nodes.iter().collect();
+    let args: Vec<Vec<String>> = (0..3)
+        .map(|i| {
+            let mut args = peers("--ring-node", &refs, i);
+            args.extend(["--replicas", "3", "--anti-entropy-secs", "1"].iter().map(|s| s.to_string()));
+            args
+        })
+        .collect();
+    for (node, args) in nodes.iter_mut().zip(&args) {
+        node.start(args);
+    }
+
+    // c is down for these, so only a and b have them
+    nodes[2].kill();
+    for i in 0..50 {
+        assert!(nodes[0].put(&format!("k{}", i), &format!("v{}", i)).is_success());
+    }
+    nodes[2].restart();
+
+    wait_for("c to be repaired", || {
+        (0..50).all(|i| {
+            let resp = nodes[2].client.get(nodes[2].url(&format!("_replica/k{}", i))).send().unwrap();
+            resp.status().is_success() && resp.text().unwrap().ends_with(&format!("|v{}", i))
+        })
+    });
+    let synced: u64 = nodes
+        .iter()
+        .flat_map(|node| node.json("_admin/stats")["anti_entropy"].as_array().unwrap().clone())
+        .map(|report| report["keys_pulled"].as_u64().unwrap() + report["keys_pushed"].as_u64().unwrap())
+        .sum();
+    assert!(synced >= 50, "{}", synced);
+}
+
+#[test]
+fn gossip_fails_a_silent_member_and_takes_it_back() {
+    let mut nodes = [Node::new("gossip", "a"), Node::new("gossip", "b"), Node::new("gossip", "c")];
+    let refs: Vec<&Node> = nodes.iter().collect();
+    let args: Vec<Vec<String>> = (0..3)
+        .map(|i| {
+            let mut args = peers("--gossip-seed", &refs, i);
+            args.push("--gossip".to_string());
+            args
+        })
+        .collect();
+    for (node, args) in nodes.iter_mut().zip(&args) {
+        node.start(args);
+    }
+
+    let c = nodes[2].addr();
+    let state_of_c = |node: &Node| -> String {
+        let members = node.json("_admin/members");
+        let member = members.as_array().unwrap().iter().find(|m| m["id"] == c.as_str()).unwrap().clone();
+        member["state"].as_str().unwrap().to_string()
+    };
+    assert_eq!(state_of_c(&nodes[0]), "Alive");
+
+    nodes[2].kill();
+    let mut seen = Vec::new();
+    wait_for("c to be declared failed", || {
+        let state = state_of_c(&nodes[0]);
+        if seen.last() != Some(&state) {
+            seen.push(state.clone());
+        }
+        state == "Failed"
+    });
+    assert_eq!(seen, vec!["Alive", "Suspect", "Failed"]);
+    wait_for("b to hear c failed", || state_of_c(&nodes[1]) == "Failed");
+
+    // a restarted member refutes its failure with a higher incarnation
+    nodes[2].restart();
+    wait_for("c to rejoin", || state_of_c(&nodes[0]) == "Alive" && state_of_c(&nodes[1]) == "Alive");
+}
@@ -0,0 +1,157 @@
+use null_log_segments::{open, Error, Options};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+// A fresh directory per test so they can run in parallel, removed once the
+// test is done with it.
+fn temp_dir(name: &str) -> TempDir {
+    tempfile::Builder::new().prefix(&format!("null-log-segments-{}-", name)).tempdir().unwrap()
+}
+
+#[test]
+fn put_get_delete() {
+    let dir = temp_dir("put_get_delete");
+    let db = open(&dir, Options::default()).unwrap();
+
+    assert_eq!(db.get("foo").unwrap(), None);
+    db.put("foo", "bar").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("bar".to_string()));
+    db.put("foo", "baz").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("baz".to_string()));
+    db.delete("foo").unwrap();
+    assert_eq!(db.get("foo").unwrap(), None);
+}
+
+#[test]
+fn reopen_keeps_records() {
+    let dir = temp_dir("reopen_keeps_records");
+    let db = open(&dir, Options::default()).unwrap();
+    for i in 0..200 {
+        db.put(&format!("k{}", i % 50), &format!("v{}", i)).unwrap();
+    }
+    db.delete("k7").unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, Options::default()).unwrap();
+    assert_eq!(db.get("k49").unwrap(), Some("v199".to_string()));
+    assert_eq!(db.get("k7").unwrap(), None);
+    assert_eq!(db.scan().unwrap().len(), 49);
+}
+
+#[test]
+fn compaction_keeps_newest_values() {
+    let dir = temp_dir("compaction_keeps_newest_values");
+    let compactions = Arc::new(AtomicUsize::new(0));
+    let seen = compactions.clone();
+    let db = open(&dir, Options {
+        max_segment_lines: 8,
+        compact_after: 2,
+        on_compaction: Some(Arc::new(move |_| {
+            seen.fetch_add(1, Ordering::SeqCst);
+        })),
+        ..Options::default()
+    })
+    .unwrap();
+
+    for i in 0..100 {
+        db.put(&format!("k{}", i % 10), &format!("v{}", i)).unwrap();
+    }
+    db.delete("k0").unwrap();
+
+    assert!(compactions.load(Ordering::SeqCst) > 0);
+    assert_eq!(db.get("k9").unwrap(), Some("v99".to_string()));
+    assert_eq!(db.get("k0").unwrap(), None);
+    let mut records = db.scan().unwrap();
+    records.sort();
+    assert_eq!(records.len(), 9);
+    assert_eq!(records[0], ("k1".to_string(), "v91".to_string()));
+    assert!(db.stats().unwrap().segments <= 3);
+}
+
+#[test]
+fn rejects_records_it_cannot_store() {
+    let dir = temp_dir("rejects_records_it_cannot_store");
+    let db = open(&dir, Options::default()).unwrap();
+
+    assert!(matches!(db.put("a:b", "c"), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("a", "b\nc"), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("a", null_log_segments::TOMBSTONE), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("", "b"), Err(Error::Invalid(_))));
+    assert_eq!(db.stats().unwrap().keys, 0);
+}
+
+#[test]
+fn write_batch_is_all_or_nothing() {
+    let dir = temp_dir("write_batch_is_all_or_nothing");
+    let db = open(&dir, Options::default()).unwrap();
+    db.put("gone", "soon").unwrap();
+
+    db.write_batch(&[
+        ("a".to_string(), Some("1".to_string())),
+        ("b".to_string(), Some("2".to_string())),
+        ("gone".to_string(), None),
+    ])
+    .unwrap();
+    assert_eq!(db.get("a").unwrap(), Some("1".to_string()));
+    assert_eq!(db.get("b").unwrap(), Some("2".to_string()));
+    assert_eq!(db.get("gone").unwrap(), None);
+
+    let bad = db.write_batch(&[
+        ("a".to_string(), Some("changed".to_string())),
+        ("b:c".to_string(), Some("3".to_string())),
+    ]);
+    assert!(matches!(bad, Err(Error::Invalid(_))));
+    assert_eq!(db.get("a").unwrap(), Some("1".to_string()));
+}
+
+#[test]
+fn lookups_reach_rolled_segments() {
+    let dir = temp_dir("lookups_reach_rolled_segments");
+    let db = open(&dir, Options {
+        max_segment_lines: 8,
+        compact_after: 100,
+        ..Options::default()
+    })
+    .unwrap();
+
+    for i in 0..50 {
+        db.put(&format!("k{}", i), &format!("v{}", i)).unwrap();
+    }
+    db.put("k3", "newer").unwrap();
+    db.delete("k4").unwrap();
+
+    assert!(db.stats().unwrap().segments > 5);
+    assert_eq!(db.get("k0").unwrap(), Some("v0".to_string()));
+    assert_eq!(db.get("k3").unwrap(), Some("newer".to_string()));
+    assert_eq!(db.get("k4").unwrap(), None);
+    assert_eq!(db.get("k49").unwrap(), Some("v49".to_string()));
+}
+
+#[test]
+fn cached_reads_follow_rollovers() {
+    let dir = temp_dir("cached_reads_follow_rollovers");
+    let db = open(&dir, Options {
+        max_segment_lines: 50,
+        compact_after: 3,
+        block_cache_bytes: 64 * 1024,
+        ..Options::default()
+    })
+    .unwrap();
+
+    // records long enough to straddle the cache's 4 KiB chunks, and enough
+    // rounds that the active segment is renamed and compacted away under it
+    for round in 0..6 {
+        for i in 0..40 {
+            let value = format!("{}-{}", round, "x".repeat(100 + i * 7));
+            db.put(&format!("k{}", i), &value).unwrap();
+        }
+        for i in 0..40 {
+            let value = format!("{}-{}", round, "x".repeat(100 + i * 7));
+            assert_eq!(db.get(&format!("k{}", i)).unwrap(), Some(value));
+        }
+    }
+    let cache = db.stats().unwrap().cache;
+    assert!(cache.hits > 0, "{:?}", cache);
+    assert!(cache.bytes <= 64 * 1024);
+}
@@ -0,0 +1,147 @@
+// A disk-log server run by a test, in a directory and on a port of its own.
+// Each test file uses a different part of this.
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::fs::OpenOptions;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long a node or cluster gets to settle before a test gives up on it.
+const SETTLE: Duration = Duration::from_secs(30);
+
+pub struct Node {
+    pub dir: PathBuf,
+    port: u16,
+    args: Vec<String>,
+    child: Option<Child>,
+    pub client: reqwest::blocking::Client,
+}
+
+impl Node {
+    // A node that hasn't been started yet, so its address can be handed to
+    // the others first.
+    pub fn new(test: &str, name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("null-node-{}-{}-{}", test, name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Node {
+            dir,
+            port: free_port(),
+            args: Vec::new(),
+            child: None,
+            client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap(),
+        }
+    }
+
+    pub fn addr(&self) -> String {
+        format!("127.0.0.1:{}", self.port)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{}", self.addr(), path)
+    }
+
+    // Starts the server with args and waits until it answers.
+    pub fn start(&mut self, args: &[String]) {
+        self.args = args.to_vec();
+        self.restart();
+    }
+
+    pub fn restart(&mut self) {
+        let child = Command::new(env!("CARGO_BIN_EXE_disk-log"))
+            .arg("--port")
+            .arg(self.port.to_string())
+            .args(&self.args)
+            .current_dir(&self.dir)
+            .stdout(Stdio::null())
+            .stderr(self.log_file())
+            .spawn()
+            .unwrap();
+        self.child = Some(child);
+        wait_for("node to start", || self.client.get(self.url("_admin/stats")).send().is_ok());
+    }
+
+    // Runs the server with args, which it should refuse, and returns what it
+    // said about them.
+    pub fn refuse(&self, args: &[&str]) -> String {
+        let output = Command::new(env!("CARGO_BIN_EXE_disk-log"))
+            .arg("--port")
+            .arg(self.port.to_string())
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "started with {:?}", args);
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    }
+
+    // Logs of every run, kept across restarts.
+    fn log_file(&self) -> std::fs::File {
+        OpenOptions::new().create(true).append(true).open(self.dir.join("node.log")).unwrap()
+    }
+
+    pub fn log(&self) -> String {
+        std::fs::read_to_string(self.dir.join("node.log")).unwrap_or_default()
+    }
+
+    pub fn kill(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let resp = self.client.get(self.url(key)).send().ok()?;
+        if !resp.status().is_success() {
+            return None;
+        }
+        resp.text().ok()
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> reqwest::StatusCode {
+        self.client.post(self.url(key)).body(value.to_string()).send().unwrap().status()
+    }
+
+    pub fn json(&self, path: &str) -> Value {
+        self.client.get(self.url(path)).send().unwrap().json().unwrap()
+    }
+
+    // The keys this node holds itself.
+    pub fn exported_keys(&self) -> Vec<String> {
+        let text = self.client.get(self.url("_export")).send().unwrap().text().unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["key"].as_str().unwrap().to_string())
+            .collect()
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.kill();
+        // a failed test's directory is kept, node.log and all
+        if !thread::panicking() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+pub fn wait_for<F: FnMut() -> bool>(what: &str, mut done: F) {
+    let started = Instant::now();
+    while !done() {
+        assert!(started.elapsed() < SETTLE, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,86 @@
+// Backups taken from a running disk-log server, and restores from them.
+mod node;
+
+use node::Node;
+use null_common::archive::Archive;
+use null_common::replication;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// A directory for a backup or archive, removed once the test is done with it.
+fn temp_dir(name: &str) -> TempDir {
+    tempfile::Builder::new().prefix(&format!("null-node-{}-", name)).tempdir().unwrap()
+}
+
+fn take_backup(node: &Node, dir: &std::path::Path) -> reqwest::StatusCode {
+    node.client
+        .post(node.url("_admin/backup"))
+        .query(&[("dir", dir.to_str().unwrap())])
+        .send()
+        .unwrap()
+        .status()
+}
+
+#[test]
+fn backup_restores_what_was_there_when_it_was_taken() {
+    let mut source = Node::new("backup", "source");
+    source.start(&[]);
+    for i in 0..100 {
+        assert!(source.put(&format!("k{}", i), &format!("v{}", i)).is_success());
+    }
+    let backup = temp_dir("backup");
+    assert!(take_backup(&source, backup.path()).is_success());
+    // a directory holds one backup
+    assert_eq!(take_backup(&source, backup.path()).as_u16(), 409);
+
+    assert!(source.put("k0", "after").is_success());
+    assert!(source.put("later", "after").is_success());
+
+    let mut restored = Node::new("backup", "restored");
+    restored.start(&["--restore-from".to_string(), backup.path().to_str().unwrap().to_string()]);
+    for i in 0..100 {
+        assert_eq!(restored.get(&format!("k{}", i)), Some(format!("v{}", i)));
+    }
+    assert_eq!(restored.get("later"), None);
+    assert_eq!(restored.exported_keys().len(), 100);
+    // the log carries on from where the backup was taken
+    let stats = restored.json("_admin/stats");
+    assert_eq!(stats["replication_log_start"], stats["replication_log_end"]);
+    assert!(stats["replication_log_start"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn restore_rolls_forward_to_a_timestamp() {
+    let mut source = Node::new("pitr", "source");
+    let archive = temp_dir("archive");
+    source.start(&["--archive-dir".to_string(), archive.path().to_str().unwrap().to_string()]);
+    assert!(source.put("a", "1").is_success());
+    let backup = temp_dir("backup");
+    assert!(take_backup(&source, backup.path()).is_success());
+
+    assert!(source.put("b", "2").is_success());
+    thread::sleep(Duration::from_millis(20));
+    let until = replication::now_millis();
+    thread::sleep(Duration::from_millis(20));
+    assert!(source.put("a", "3").is_success());
+    assert!(source.put("c", "4").is_success());
+
+    // the server archives every 30s; stopped, its log can be archived here
+    source.kill();
+    std::env::set_current_dir(&source.dir).unwrap();
+    Archive::new(archive.path().to_path_buf(), Duration::from_secs(3600)).roll().unwrap();
+
+    let mut restored = Node::new("pitr", "restored");
+    restored.start(&[
+        "--restore-from".to_string(),
+        backup.path().to_str().unwrap().to_string(),
+        "--archive-dir".to_string(),
+        archive.path().to_str().unwrap().to_string(),
+        "--recover-until-ms".to_string(),
+        until.to_string(),
+    ]);
+    assert_eq!(restored.get("a").as_deref(), Some("1"));
+    assert_eq!(restored.get("b").as_deref(), Some("2"));
+    assert_eq!(restored.get("c"), None);
+}
@@ -0,0 +1,279 @@
+//! The simplest key/value store there is: every write appended to one file as
+//! a key:value line, and reads walking the file backwards until they find the
+//! newest line for their key, with recently read parts of the file kept in a
+//! block cache.
+//!
+//! ```no_run
+//! let db = null_log::open("/tmp/null", null_log::Options::default())?;
+//! db.put("foo", "bar")?;
+//! assert_eq!(db.get("foo")?, Some("bar".to_string()));
+//! db.close()?;
+//! # Ok::<(), null_log::Error>(())
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use null_common::cache::{self, BlockCache, CachedFile};
+pub use null_common::cache::CacheStats;
+use null_common::error::DbError;
+use tracing::trace;
+
+mod file_reader;
+//use easy_reader::EasyReader;
+use file_reader::EasyReader;
+
+pub const TOMBSTONE: &str = "~tombstone~";
+// The one file the database lives in, inside the directory it is opened at.
+pub const FILE: &str = "null.db";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // a record or file on disk we can't make sense of
+    Corrupt(String),
+    // a key or value we can't store as given
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// For callers that only deal in io::Error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Corrupt(msg) => io::Error::new(io::ErrorKind::InvalidData, msg),
+            Error::Invalid(msg) => io::Error::new(io::ErrorKind::InvalidInput, msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => Error::Corrupt(e.to_string()),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+// Lets the servers hand our errors straight back as HTTP responses.
+impl From<Error> for DbError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e.into(),
+            Error::Corrupt(msg) => DbError::Corrupt(msg),
+            Error::Invalid(msg) => DbError::Invalid(msg),
+        }
+    }
+}
+
+pub struct Options {
+    // fsync after every write rather than only on flush
+    pub sync_writes: bool,
+    // how many bytes of file reads to keep in memory; 0 reads every lookup
+    // from disk
+    pub block_cache_bytes: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            sync_writes: false,
+            block_cache_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub keys: usize,
+    pub disk_bytes: u64,
+    pub cache: CacheStats,
+}
+
+pub struct Db {
+    path: PathBuf,
+    options: Options,
+    //it's just protecting the OS's file access
+    lock: RwLock<()>,
+    cache: BlockCache<Vec<u8>>,
+    // what the cache knows the file by, which only ever grows
+    file_id: u64,
+}
+
+// Opens the database kept in dir, creating it if needed.
+pub fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<Db, Error> {
+    std::fs::create_dir_all(path.as_ref())?;
+    let path = path.as_ref().join(FILE);
+    OpenOptions::new().create(true).append(true).open(&path)?;
+    Ok(Db {
+        path,
+        cache: BlockCache::new(options.block_cache_bytes),
+        options,
+        lock: RwLock::new(()),
+        file_id: cache::next_file_id(),
+    })
+}
+
+impl Db {
+    // The file every record is written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // None when the key was never written or has been deleted.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let _reader = self.lock.read().unwrap();
+        let file = CachedFile::open(&self.path, &self.cache, self.file_id)?;
+        if file.is_empty() {
+            return Ok(None);
+        }
+        let mut reader = EasyReader::new(file)?;
+        // Generate index (optional)
+        reader.build_index()?;
+        reader.eof();
+        // callers tracing a request see how far back the key was
+        let span = tracing::Span::current();
+        span.record("segments", 1);
+        let mut scanned = 0u64;
+        while let Some(line) = reader.prev_line()? {
+            scanned += 1;
+            let split = line.split(":").collect::<Vec<&str>>();
+            if split.len() == 2 && split[0] == key {
+                span.record("lines_scanned", scanned);
+                if split[1] == TOMBSTONE {
+                    return Ok(None);
+                }
+                return Ok(Some(split[1].to_string()));
+            }
+            trace!(line = %line, "scanned");
+        }
+        span.record("lines_scanned", scanned);
+        Ok(None)
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        check_record(key, value)?;
+        if value == TOMBSTONE {
+            return Err(Error::Invalid(format!("{} is reserved for deletes", TOMBSTONE)));
+        }
+        self.append(key, value)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        check_record(key, "")?;
+        self.append(key, TOMBSTONE)
+    }
+
+    // Appends every write in batch with a single write call under one lock, so
+    // readers see all of them or none. A value of None deletes the key. Nothing
+    // is written unless every record in the batch can be stored.
+    pub fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), Error> {
+        let mut lines = String::new();
+        for (key, value) in batch {
+            match value {
+                Some(value) => {
+                    check_record(key, value)?;
+                    if value == TOMBSTONE {
+                        return Err(Error::Invalid(format!("{} is reserved for deletes", TOMBSTONE)));
+                    }
+                    lines.push_str(&format!("{}:{}\n", key, value));
+                }
+                None => {
+                    check_record(key, "")?;
+                    lines.push_str(&format!("{}:{}\n", key, TOMBSTONE));
+                }
+            }
+        }
+        let _writer = self.lock.write().unwrap();
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        if self.options.sync_writes {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    // Every live record, in no particular order. Replays the whole file so
+    // later writes win.
+    pub fn scan(&self) -> Result<Vec<(String, String)>, Error> {
+        let _reader = self.lock.read().unwrap();
+        let mut records = HashMap::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            let split = line.split(":").collect::<Vec<&str>>();
+            if split.len() == 2 {
+                if split[1] == TOMBSTONE {
+                    records.remove(split[0]);
+                } else {
+                    records.insert(split[0].to_string(), split[1].to_string());
+                }
+            }
+        }
+        Ok(records.into_iter().collect())
+    }
+
+    // Makes every write so far durable.
+    pub fn flush(&self) -> Result<(), Error> {
+        let _writer = self.lock.write().unwrap();
+        File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let keys = self.scan()?.len();
+        Ok(Stats {
+            keys,
+            disk_bytes: std::fs::metadata(&self.path)?.len(),
+            cache: self.cache.stats(),
+        })
+    }
+
+    // Without stats' scan of the whole file.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    // Flushes and lets go of the database.
+    pub fn close(self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<(), Error> {
+        let _writer = self.lock.write().unwrap();
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}:{}", key, value)?;
+        if self.options.sync_writes {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+// Records are stored as key:value lines, so neither half can hold a ':' or a
+// newline.
+fn check_record(key: &str, value: &str) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::Invalid("key can't be empty".to_string()));
+    }
+    if key.contains([':', '\n']) || value.contains([':', '\n']) {
+        return Err(Error::Invalid("key and value can't hold ':' or newlines".to_string()));
+    }
+    Ok(())
+}
@@ -4,38 +4,35 @@ use actix_web::{
     delete, 
     web::{self, Data}, 
     App, 
-    Responder, 
     HttpResponse,
-    HttpServer, dev::HttpResponseBuilder
+    HttpServer
 };
 #[macro_use]
 extern crate lazy_static;
-mod file_reader;
-//use easy_reader::EasyReader;
-use file_reader::EasyReader;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
-use std::{
-    fs::{
-        File,
-    },
-    io::{
-        self,
-        Error
-    }
-};
-use std::sync::RwLock; // read heavy better for sure -- probably better period.
+mod metrics;
+use null_common::error::{self, DbError};
+use null_common::{jsonl, logging};
+use null_log::{Db, Options, TOMBSTONE};
+use tracing::error;
 
-const TOMBSTONE: &'static str = "~tombstone~";
+// what request logs call this server
+const ENGINE: &str = "log";
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    logging::init();
 
-    let file_mutex = Data::new(RwLock::new("null.db"));
+    let db = Data::new(null_log::open(".", Options::default())?);
 
     HttpServer::new(move || {
         App::new()
-            .app_data(file_mutex.clone())
+            .app_data(db.clone())
+            .app_data(web::PayloadConfig::new(error::MAX_BODY_BYTES))
+            .wrap_fn(|req, srv| logging::trace_request(ENGINE, req, srv))
+            // ahead of /{key}, which would take these for keys
+            .service(metrics::get_metrics)
+            .service(export_records)
+            .service(import_records)
             .service(get_value_for_key)
             .service(put_value_for_key)
             .service(delete_value_for_key)
@@ -48,75 +45,79 @@ async fn main() -> std::io::Result<()> {
 
 #[get("/{key}")]
 pub async fn get_value_for_key(
-    file_mutex: Data<RwLock<&str>>, 
+    db: Data<Db>, 
     web::Path(key): web::Path<String>
-) -> impl Responder {
-    //it's just protecting the OS's file access
-    let reader = *file_mutex.read().unwrap();
-
-    let file = File::open(reader).unwrap();
-    let mut reader = EasyReader::new(file).unwrap();
-    // Generate index (optional)
-    reader.build_index();
-    reader.eof();
-    while let Some(line) = reader.prev_line().unwrap() {
-        let split = line.split(":").collect::<Vec<&str>>();
-        if split.len() == 2 {
-            if split[0] == key {
-                let value = split[1].to_string().clone();
-                if value == TOMBSTONE {
-                    return HttpResponseBuilder::from(HttpResponse::Ok().body("Key not found"));
-                }
-                return HttpResponseBuilder::from(HttpResponse::Ok().body(value));
-            }
-        }
-        println!("{}", line);
+) -> Result<HttpResponse, DbError> {
+    match db.get(&key)? {
+        Some(value) => Ok(HttpResponse::Ok().body(value)),
+        None => Err(DbError::NotFound(key)),
     }
-
-    // Repeat process by seeking back by chunk_size again.;
-    return HttpResponseBuilder::from(HttpResponse::NotFound().body(""));
 }
 
 #[post("/{key}")]
 pub async fn put_value_for_key(
-    file_mutex: Data<RwLock<&str>>,
+    db: Data<Db>,
     web::Path(key): web::Path<String>,
     req_body: String
-) -> impl Responder {
-
-    let writer = file_mutex.write().unwrap();
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(*writer)
-        .unwrap();  
+) -> Result<HttpResponse, DbError> {
+    check_value(&req_body)?;
+    db.put(&key, &req_body)?;
+    metrics::BYTES_WRITTEN.inc_by((key.len() + req_body.len() + 2) as u64);
 
-    if let Err(e) = writeln!(file,"{}:{}",key, req_body) {
-        eprintln!("Couldn't write to file: {}", e);
-        return HttpResponse::InternalServerError();
-    }
-
-    return HttpResponseBuilder::from(HttpResponse::Ok().body("It is saved... to disk!!!"));
+    Ok(HttpResponse::Ok().body("It is saved... to disk!!!"))
 }
 
 #[delete("/{key}")]
 pub async fn delete_value_for_key(
-    file_mutex: Data<RwLock<&str>>,
+    db: Data<Db>,
     web::Path(key): web::Path<String>
-) -> impl Responder {
+) -> Result<HttpResponse, DbError> {
+    db.delete(&key)?;
+    metrics::BYTES_WRITTEN.inc_by((key.len() + TOMBSTONE.len() + 2) as u64);
 
-    let writer = file_mutex.write().unwrap();
-    let mut file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(*writer)
-        .unwrap();
+    Ok(HttpResponse::Ok().body("Record Deleted"))
+}
 
-    if let Err(e) = writeln!(file,"{}:{}",key, TOMBSTONE) {
-        eprintln!("Couldn't write to file: {}", e);
-        return HttpResponse::InternalServerError();
+// The database turns away records it can't store, this only keeps out values
+// too big to take in one request.
+fn check_value(value: &str) -> Result<(), DbError> {
+    if value.len() > error::MAX_VALUE_BYTES {
+        return Err(DbError::TooLarge { size: value.len(), limit: error::MAX_VALUE_BYTES });
     }
+    Ok(())
+}
 
-    return HttpResponseBuilder::from(HttpResponse::Ok().body("Record Deleted"));
+// Every live record as JSON Lines.
+#[get("/_export")]
+pub async fn export_records(
+    db: Data<Db>
+) -> Result<HttpResponse, DbError> {
+    Ok(jsonl::export(db.scan()?.into_iter()))
 }
 
+#[post("/_import")]
+pub async fn import_records(
+    db: Data<Db>,
+    body: web::Payload
+) -> Result<HttpResponse, DbError> {
+    let mut lines = jsonl::Lines::new(body);
+    let mut imported = 0;
+    while let Some(line) = lines.next().await {
+        let record = line.map_err(|e| e.to_string()).and_then(|line| jsonl::decode(&line)).and_then(|(key, value)| {
+            check_value(&value).map_err(|e| e.to_string())?;
+            Ok((key, value))
+        });
+        let (key, value) = match record {
+            Ok(record) => record,
+            Err(e) => return Ok(jsonl::import_result(imported, Some((lines.line_number, e)))),
+        };
+
+        if let Err(e) = db.put(&key, &value) {
+            error!(error = %e, "couldn't write to file");
+            return Ok(jsonl::import_result(imported, Some((lines.line_number, e.to_string()))));
+        }
+        metrics::BYTES_WRITTEN.inc_by((key.len() + value.len() + 2) as u64);
+        imported += 1;
+    }
+    Ok(jsonl::import_result(imported, None))
+}
@@ -0,0 +1,55 @@
+use actix_web::{get, web::Data, Responder};
+use null_common::metrics::{catch_up, render};
+use null_log::Db;
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge_vec, IntCounter,
+    IntCounterVec, IntGaugeVec,
+};
+
+lazy_static! {
+    pub static ref BYTES_WRITTEN: IntCounter = register_int_counter!(
+        "null_bytes_written_total",
+        "Bytes of records appended to the log"
+    )
+    .unwrap();
+    static ref SEGMENTS: IntGaugeVec = register_int_gauge_vec!(
+        "null_segments",
+        "Segment files on disk, by kind",
+        &["kind"]
+    )
+    .unwrap();
+    static ref SEGMENT_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_segment_bytes",
+        "Size of the segment files on disk, by kind",
+        &["kind"]
+    )
+    .unwrap();
+    static ref CACHE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "null_block_cache_requests_total",
+        "Log chunk reads asked of the block cache, by result",
+        &["result"]
+    )
+    .unwrap();
+    static ref CACHE_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_block_cache_bytes",
+        "Bytes of log chunks in the block cache, and what it can hold",
+        &["kind"]
+    )
+    .unwrap();
+}
+
+#[get("/metrics")]
+pub async fn get_metrics(
+    db: Data<Db>
+) -> impl Responder {
+    // the whole database is one log file that never rolls over
+    let size = std::fs::metadata(db.path()).map(|m| m.len()).unwrap_or(0);
+    SEGMENTS.with_label_values(&["log"]).set(1);
+    SEGMENT_BYTES.with_label_values(&["log"]).set(size as i64);
+    let cache = db.cache_stats();
+    catch_up(&CACHE_REQUESTS, &[("hit", cache.hits), ("miss", cache.misses)]);
+    CACHE_BYTES.with_label_values(&["used"]).set(cache.bytes as i64);
+    CACHE_BYTES.with_label_values(&["capacity"]).set(cache.capacity as i64);
+
+    render()
+}
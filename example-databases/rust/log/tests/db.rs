@@ -0,0 +1,105 @@
+use null_log::{open, Error, Options};
+use tempfile::TempDir;
+
+// A fresh directory per test so they can run in parallel, removed once the
+// test is done with it.
+fn temp_dir(name: &str) -> TempDir {
+    tempfile::Builder::new().prefix(&format!("null-log-{}-", name)).tempdir().unwrap()
+}
+
+#[test]
+fn put_get_delete() {
+    let dir = temp_dir("put_get_delete");
+    let db = open(&dir, Options::default()).unwrap();
+
+    assert_eq!(db.get("foo").unwrap(), None);
+    db.put("foo", "bar").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("bar".to_string()));
+    db.put("foo", "baz").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("baz".to_string()));
+    db.delete("foo").unwrap();
+    assert_eq!(db.get("foo").unwrap(), None);
+}
+
+#[test]
+fn reopen_keeps_records() {
+    let dir = temp_dir("reopen_keeps_records");
+    let db = open(&dir, Options { sync_writes: true, ..Options::default() }).unwrap();
+    for i in 0..200 {
+        db.put(&format!("k{}", i % 50), &format!("v{}", i)).unwrap();
+    }
+    db.delete("k7").unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, Options::default()).unwrap();
+    assert_eq!(db.get("k49").unwrap(), Some("v199".to_string()));
+    assert_eq!(db.get("k7").unwrap(), None);
+    let mut records = db.scan().unwrap();
+    records.sort();
+    assert_eq!(records.len(), 49);
+    assert_eq!(records[0], ("k0".to_string(), "v150".to_string()));
+    assert_eq!(db.stats().unwrap().keys, 49);
+}
+
+#[test]
+fn rejects_records_it_cannot_store() {
+    let dir = temp_dir("rejects_records_it_cannot_store");
+    let db = open(&dir, Options::default()).unwrap();
+
+    assert!(matches!(db.put("a:b", "c"), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("a", "b\nc"), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("a", null_log::TOMBSTONE), Err(Error::Invalid(_))));
+    assert!(matches!(db.put("", "b"), Err(Error::Invalid(_))));
+    assert_eq!(db.stats().unwrap().disk_bytes, 0);
+}
+
+#[test]
+fn write_batch_is_all_or_nothing() {
+    let dir = temp_dir("write_batch_is_all_or_nothing");
+    let db = open(&dir, Options::default()).unwrap();
+    db.put("gone", "soon").unwrap();
+
+    db.write_batch(&[
+        ("a".to_string(), Some("1".to_string())),
+        ("b".to_string(), Some("2".to_string())),
+        ("gone".to_string(), None),
+    ])
+    .unwrap();
+    assert_eq!(db.get("a").unwrap(), Some("1".to_string()));
+    assert_eq!(db.get("b").unwrap(), Some("2".to_string()));
+    assert_eq!(db.get("gone").unwrap(), None);
+
+    let bad = db.write_batch(&[
+        ("a".to_string(), Some("changed".to_string())),
+        ("b:c".to_string(), Some("3".to_string())),
+    ]);
+    assert!(matches!(bad, Err(Error::Invalid(_))));
+    assert_eq!(db.get("a").unwrap(), Some("1".to_string()));
+}
+
+#[test]
+fn lookups_read_through_the_block_cache() {
+    let dir = temp_dir("lookups_read_through_the_block_cache");
+    let db = open(&dir, Options::default()).unwrap();
+    // a few chunks' worth, so the early ones are full and can be cached
+    for i in 0..1000 {
+        db.put(&format!("k{:04}", i), &format!("v{:04}", i)).unwrap();
+    }
+
+    assert_eq!(db.get("k0000").unwrap(), Some("v0000".to_string()));
+    let first = db.stats().unwrap().cache;
+    assert_eq!(db.get("k0000").unwrap(), Some("v0000".to_string()));
+    let second = db.stats().unwrap().cache;
+    assert!(second.hits > first.hits);
+    assert!(second.bytes > 0);
+
+    // the last chunk is still growing, and is read afresh
+    db.put("k0999", "changed").unwrap();
+    assert_eq!(db.get("k0999").unwrap(), Some("changed".to_string()));
+
+    let dir = temp_dir("no_block_cache");
+    let db = open(&dir, Options { block_cache_bytes: 0, ..Options::default() }).unwrap();
+    db.put("foo", "bar").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("bar".to_string()));
+    assert_eq!(db.stats().unwrap().cache.bytes, 0);
+}
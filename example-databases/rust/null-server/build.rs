@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // so building doesn't need protoc installed
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        // the server never calls itself, the client is for tests
+        .build_client(true)
+        .compile(&["proto/null.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use crate::engine::{StorageEngine, Stats};
+use disk_btree::{Db, Options};
+use null_common::error::DbError;
+use null_common::metrics::catch_up;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, IntCounterVec, IntGauge,
+    IntGaugeVec,
+};
+use std::path::Path;
+
+lazy_static! {
+    static ref PAGES: IntGaugeVec = register_int_gauge_vec!(
+        "null_btree_pages",
+        "Pages in the B+tree file, by whether they are in use or free",
+        &["kind"]
+    )
+    .unwrap();
+    static ref DEPTH: IntGauge = register_int_gauge!(
+        "null_btree_depth",
+        "Levels from the root of the B+tree to its leaves"
+    )
+    .unwrap();
+    static ref POOL_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "null_buffer_pool_requests_total",
+        "Pages asked of the buffer pool, by whether they were already in a frame",
+        &["result"]
+    )
+    .unwrap();
+    static ref POOL_WRITES: IntCounterVec = register_int_counter_vec!(
+        "null_buffer_pool_pages_total",
+        "Pages evicted from the buffer pool, and dirty pages it wrote back to the file",
+        &["event"]
+    )
+    .unwrap();
+    static ref POOL_FRAMES: IntGaugeVec = register_int_gauge_vec!(
+        "null_buffer_pool_frames",
+        "Frames in the buffer pool, and how many hold dirty pages",
+        &["kind"]
+    )
+    .unwrap();
+}
+
+// A B+tree of pages updated in place in one file, see the disk-btree crate.
+pub struct Btree {
+    db: Db,
+}
+
+impl Btree {
+    pub fn open(dir: &Path) -> Result<Self, DbError> {
+        Ok(Btree {
+            db: disk_btree::open(dir, Options::default())?,
+        })
+    }
+}
+
+impl StorageEngine for Btree {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError> {
+        Ok(self.db.put(key, value)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        Ok(self.db.delete(key)?)
+    }
+
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError> {
+        Ok(self.db.scan()?)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        Ok(self.db.flush()?)
+    }
+
+    fn stats(&self) -> Result<Stats, DbError> {
+        let stats = self.db.stats()?;
+        Ok(Stats {
+            keys: stats.keys as usize,
+            // the whole tree is one file
+            segments: 1,
+            disk_bytes: stats.file_bytes,
+        })
+    }
+
+    fn report_metrics(&self) -> Result<(), DbError> {
+        let stats = self.db.stats()?;
+        PAGES.with_label_values(&["used"]).set((stats.pages - stats.free_pages) as i64);
+        PAGES.with_label_values(&["free"]).set(stats.free_pages as i64);
+        DEPTH.set(stats.depth as i64);
+        catch_up(&POOL_REQUESTS, &[("hit", stats.pool.hits), ("miss", stats.pool.misses)]);
+        catch_up(&POOL_WRITES, &[("eviction", stats.pool.evictions), ("writeback", stats.pool.writebacks)]);
+        POOL_FRAMES.with_label_values(&["total"]).set(stats.pool.frames as i64);
+        POOL_FRAMES.with_label_values(&["dirty"]).set(stats.pool.dirty as i64);
+        Ok(())
+    }
+}
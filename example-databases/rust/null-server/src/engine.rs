@@ -0,0 +1,85 @@
+use null_common::error::DbError;
+use crate::{btree, hash_index, log, log_segments, memory, sstables};
+use serde::Serialize;
+use std::path::Path;
+
+// What every storage engine offers the HTTP front end. Engines do their own
+// locking, so the server can share one between all of its workers.
+pub trait StorageEngine: Send + Sync {
+    // None when the key was never written or has been deleted.
+    fn get(&self, key: &str) -> Result<Option<String>, DbError>;
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError>;
+    fn delete(&self, key: &str) -> Result<(), DbError>;
+    // Applies every write in batch so readers see all of them or none. A value
+    // of None deletes the key. Engines override this where they can do better
+    // than one write at a time.
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        for (key, value) in batch {
+            match value {
+                Some(value) => self.put(key, value)?,
+                None => self.delete(key)?,
+            }
+        }
+        Ok(())
+    }
+    // Every live record, in no particular order.
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError>;
+    // Makes every write so far durable.
+    fn flush(&self) -> Result<(), DbError>;
+    fn stats(&self) -> Result<Stats, DbError>;
+    // Sets the metrics only this engine has, read off it at scrape time. Most
+    // engines have none beyond what stats reports.
+    fn report_metrics(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Default)]
+pub struct Stats {
+    pub keys: usize,
+    // files holding records, 0 for engines that keep nothing on disk
+    pub segments: usize,
+    pub disk_bytes: u64,
+}
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+pub enum EngineKind {
+    // a HashMap, gone when the process stops
+    Memory,
+    // every write appended to one file, read back newest first
+    Log,
+    // the log split into segments that are rolled over and compacted
+    LogSegments,
+    // log segments plus an in-memory map of where each key was last written
+    HashIndex,
+    // a log-structured merge tree: a memtable flushed to sorted tables
+    Sstables,
+    // a B+tree of pages updated in place in one file
+    Btree,
+}
+
+impl EngineKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EngineKind::Memory => "memory",
+            EngineKind::Log => "log",
+            EngineKind::LogSegments => "log-segments",
+            EngineKind::HashIndex => "hash-index",
+            EngineKind::Sstables => "sstables",
+            EngineKind::Btree => "btree",
+        }
+    }
+}
+
+// Opens the engine with its files under dir, creating dir if needed.
+pub fn open(kind: EngineKind, dir: &Path) -> Result<Box<dyn StorageEngine>, DbError> {
+    std::fs::create_dir_all(dir)?;
+    Ok(match kind {
+        EngineKind::Memory => Box::new(memory::Memory::new()),
+        EngineKind::Log => Box::new(log::Log::open(dir)?),
+        EngineKind::LogSegments => Box::new(log_segments::LogSegments::open(dir)?),
+        EngineKind::HashIndex => Box::new(hash_index::HashIndex::open(dir)?),
+        EngineKind::Sstables => Box::new(sstables::Sstables::open(dir)?),
+        EngineKind::Btree => Box::new(btree::Btree::open(dir)?),
+    })
+}
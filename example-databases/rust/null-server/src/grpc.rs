@@ -0,0 +1,204 @@
+// The gRPC API from proto/null.proto, for typed service to service calls. It
+// runs on its own tokio runtime, as actix is still on an older tokio, and
+// hands engine calls to blocking threads like the HTTP handlers do.
+use null_common::error::DbError;
+use crate::watch::Change;
+use crate::{check_record, Engine};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tracing::{error, info};
+
+pub mod proto {
+    tonic::include_proto!("null");
+}
+
+use proto::key_value_server::{KeyValue, KeyValueServer};
+use proto::{
+    BatchRequest, BatchResponse, DeleteRequest, DeleteResponse, GetRequest, GetResponse, PutRequest,
+    PutResponse, Record, ScanRequest, WatchRequest,
+};
+
+// records a scan sends ahead of a slow client
+const SCAN_BUFFER: usize = 64;
+
+// DbError lives in null-common, so this can't be a From impl.
+fn status(e: DbError) -> Status {
+    let message = e.to_string();
+    match e {
+        DbError::NotFound(_) => Status::not_found(message),
+        DbError::Corrupt(_) => Status::data_loss(message),
+        DbError::Io(_) => Status::internal(message),
+        DbError::Conflict(_) => Status::already_exists(message),
+        DbError::PreconditionFailed(_) | DbError::ReadOnly(_) => Status::failed_precondition(message),
+        DbError::TooLarge { .. } | DbError::Invalid(_) => Status::invalid_argument(message),
+        DbError::Unavailable(_) => Status::unavailable(message),
+        DbError::Gone(_) => Status::out_of_range(message),
+    }
+}
+
+// Binds the listener, then serves it from a thread of its own.
+pub fn start(port: u16, engine: Arc<Engine>, changes: broadcast::Sender<Change>) -> io::Result<()> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    info!(port, "gRPC listening");
+
+    let service = KeyValueServer::new(Service { engine, changes });
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let listener = match tokio::net::TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => return error!(error = %e, "couldn't start gRPC listener"),
+            };
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+            {
+                error!(error = %e, "gRPC server stopped");
+            }
+        })
+    });
+    Ok(())
+}
+
+struct Service {
+    engine: Arc<Engine>,
+    changes: broadcast::Sender<Change>,
+}
+
+impl Service {
+    async fn block<T, F>(&self, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Engine) -> Result<T, DbError> + Send + 'static,
+    {
+        let engine = self.engine.clone();
+        match tokio::task::spawn_blocking(move || f(&engine)).await {
+            Ok(result) => result.map_err(status),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+}
+
+type Streamed<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl KeyValue for Service {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        let key = request.into_inner().key;
+        let k = key.clone();
+        match self.block(move |engine| engine.get(&k)).await? {
+            Some(value) => Ok(Response::new(GetResponse { value })),
+            None => Err(status(DbError::NotFound(key))),
+        }
+    }
+
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        let PutRequest { key, value } = request.into_inner();
+        check_record(&key, &value).map_err(status)?;
+        let written = (key.len() + value.len()) as u64;
+        self.block(move |engine| engine.put(&key, &value)).await?;
+        crate::metrics::BYTES_WRITTEN.inc_by(written);
+        Ok(Response::new(PutResponse {}))
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteResponse>, Status> {
+        let key = request.into_inner().key;
+        check_record(&key, "").map_err(status)?;
+        self.block(move |engine| engine.delete(&key)).await?;
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    async fn batch(&self, request: Request<BatchRequest>) -> Result<Response<BatchResponse>, Status> {
+        let mut batch = Vec::new();
+        let mut written = 0;
+        for write in request.into_inner().writes {
+            if write.delete {
+                check_record(&write.key, "").map_err(status)?;
+                batch.push((write.key, None));
+            } else {
+                check_record(&write.key, &write.value).map_err(status)?;
+                written += (write.key.len() + write.value.len()) as u64;
+                batch.push((write.key, Some(write.value)));
+            }
+        }
+        self.block(move |engine| engine.write_batch(&batch)).await?;
+        crate::metrics::BYTES_WRITTEN.inc_by(written);
+        Ok(Response::new(BatchResponse {}))
+    }
+
+    type ScanStream = Streamed<Record>;
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<Self::ScanStream>, Status> {
+        let prefix = request.into_inner().prefix;
+        let (tx, rx) = mpsc::channel(SCAN_BUFFER);
+        let engine = self.engine.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut records = match engine.scan() {
+                Ok(records) => records,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(status(e)));
+                    return;
+                }
+            };
+            records.retain(|(key, _)| key.starts_with(&prefix));
+            records.sort();
+            for (key, value) in records {
+                // the client went away
+                if tx.blocking_send(Ok(Record { key, value })).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    type WatchStream = Streamed<proto::Change>;
+
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let prefix = request.into_inner().prefix;
+        let mut changes = self.changes.subscribe();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let change = tokio::select! {
+                    // the client went away
+                    _ = tx.closed() => return,
+                    change = changes.recv() => change,
+                };
+                let change = match change {
+                    Ok(change) => change,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let _ = tx
+                            .send(Err(Status::resource_exhausted(format!(
+                                "watcher fell {} changes behind",
+                                missed
+                            ))))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if !change.key.starts_with(&prefix) {
+                    continue;
+                }
+                let sent = tx.send(Ok(proto::Change {
+                    key: change.key,
+                    deleted: change.value.is_none(),
+                    value: change.value.unwrap_or_default(),
+                }));
+                if sent.await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+}
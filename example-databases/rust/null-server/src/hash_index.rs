@@ -0,0 +1,53 @@
+use crate::engine::{StorageEngine, Stats};
+use null_common::error::DbError;
+use null_hash_index::{Db, Options};
+use std::path::Path;
+
+// Log segments with an in-memory map of where each key was last written, see
+// the null-hash-index crate.
+pub struct HashIndex {
+    db: Db,
+}
+
+impl HashIndex {
+    pub fn open(dir: &Path) -> Result<Self, DbError> {
+        Ok(HashIndex {
+            db: null_hash_index::open(dir, Options::default())?,
+        })
+    }
+}
+
+impl StorageEngine for HashIndex {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError> {
+        Ok(self.db.put(key, value)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        Ok(self.db.delete(key)?)
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        Ok(self.db.write_batch(batch)?)
+    }
+
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError> {
+        Ok(self.db.scan()?)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        Ok(self.db.flush()?)
+    }
+
+    fn stats(&self) -> Result<Stats, DbError> {
+        let stats = self.db.stats()?;
+        Ok(Stats {
+            keys: stats.keys,
+            segments: stats.segments,
+            disk_bytes: stats.disk_bytes,
+        })
+    }
+}
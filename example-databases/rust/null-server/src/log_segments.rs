@@ -0,0 +1,53 @@
+use crate::engine::{StorageEngine, Stats};
+use null_common::error::DbError;
+use null_log_segments::{Db, Options};
+use std::path::Path;
+
+// The log split into segments that are rolled over and compacted, see the
+// null-log-segments crate.
+pub struct LogSegments {
+    db: Db,
+}
+
+impl LogSegments {
+    pub fn open(dir: &Path) -> Result<Self, DbError> {
+        Ok(LogSegments {
+            db: null_log_segments::open(dir, Options::default())?,
+        })
+    }
+}
+
+impl StorageEngine for LogSegments {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError> {
+        Ok(self.db.put(key, value)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        Ok(self.db.delete(key)?)
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        Ok(self.db.write_batch(batch)?)
+    }
+
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError> {
+        Ok(self.db.scan()?)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        Ok(self.db.flush()?)
+    }
+
+    fn stats(&self) -> Result<Stats, DbError> {
+        let stats = self.db.stats()?;
+        Ok(Stats {
+            keys: stats.keys,
+            segments: stats.segments,
+            disk_bytes: stats.disk_bytes,
+        })
+    }
+}
@@ -0,0 +1,219 @@
+use actix_web::{
+    get,
+    post,
+    delete,
+    web::{self, Data},
+    App,
+    HttpResponse,
+    HttpServer
+};
+use clap::Parser;
+#[macro_use]
+extern crate lazy_static;
+mod btree;
+mod engine;
+mod grpc;
+mod hash_index;
+mod log;
+mod log_segments;
+mod memory;
+mod metrics;
+mod sstables;
+mod watch;
+use engine::{EngineKind, StorageEngine};
+use null_common::error::{self, DbError};
+use null_common::{jsonl, logging, resp};
+use std::path::PathBuf;
+use tracing::{error, info};
+
+// One HTTP front end over whichever engine --engine picks, so every engine
+// answers the same API with the same status codes.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(long, arg_enum)]
+    engine: EngineKind,
+    #[clap(long, default_value_t = 8080)]
+    port: u16,
+    /// Port for the Redis protocol (RESP) listener
+    #[clap(long, default_value_t = 6379)]
+    resp_port: u16,
+    /// Port for the gRPC API in proto/null.proto
+    #[clap(long, default_value_t = 50051)]
+    grpc_port: u16,
+    /// Directory the engine keeps its files in
+    #[clap(long, default_value = ".")]
+    data_dir: PathBuf,
+}
+
+pub type Engine = Box<dyn StorageEngine>;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    logging::init();
+
+    let engine = engine::open(args.engine, &args.data_dir)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    info!(engine = args.engine.name(), dir = %args.data_dir.display(), "opened engine");
+    let watched = watch::Watched::new(engine);
+    let changes = watched.changes();
+    let engine: Engine = Box::new(watched);
+    let engine = Data::new(engine);
+    let kind = Data::new(args.engine);
+    resp::start(args.resp_port, engine.clone().into_inner(), args.engine.name())?;
+    grpc::start(args.grpc_port, engine.clone().into_inner(), changes)?;
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(engine.clone())
+            .app_data(kind.clone())
+            .app_data(web::PayloadConfig::new(error::MAX_BODY_BYTES))
+            .wrap_fn(|req, srv| {
+                let engine = req.app_data::<Data<EngineKind>>().map(|k| k.name()).unwrap_or("");
+                logging::trace_request(engine, req, srv)
+            })
+            // ahead of /{key}, which would take these for keys
+            .service(metrics::get_metrics)
+            .service(export_records)
+            .service(import_records)
+            .service(get_admin_stats)
+            .service(post_admin_flush)
+            .service(get_value_for_key)
+            .service(put_value_for_key)
+            .service(delete_value_for_key)
+    })
+    .bind(("127.0.0.1", args.port))?
+    .run()
+    .await
+}
+
+// The file engines store key:value lines, so the same rule holds for every
+// engine and a record that one engine takes, all of them take.
+fn check_record(key: &str, value: &str) -> Result<(), DbError> {
+    if key.contains([':', '\n']) || value.contains([':', '\n']) {
+        return Err(DbError::Invalid("key and value can't hold ':' or newlines".to_string()));
+    }
+    if value.len() > error::MAX_VALUE_BYTES {
+        return Err(DbError::TooLarge { size: value.len(), limit: error::MAX_VALUE_BYTES });
+    }
+    Ok(())
+}
+
+impl resp::Store for Engine {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        (**self).get(key)
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        (**self).write_batch(batch)?;
+        let written = batch
+            .iter()
+            .map(|(key, value)| (key.len() + value.as_ref().map_or(0, |v| v.len())) as u64)
+            .sum();
+        metrics::BYTES_WRITTEN.inc_by(written);
+        Ok(())
+    }
+
+    fn keys(&self) -> Result<Vec<String>, DbError> {
+        Ok((**self).scan()?.into_iter().map(|(key, _)| key).collect())
+    }
+
+    fn check(&self, key: &str, value: &str) -> Result<(), DbError> {
+        check_record(key, value)
+    }
+}
+
+#[get("/{key}")]
+pub async fn get_value_for_key(
+    engine: Data<Engine>,
+    web::Path(key): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    let k = key.clone();
+    match web::block(move || engine.get(&k)).await? {
+        Some(value) => Ok(HttpResponse::Ok().body(value)),
+        None => Err(DbError::NotFound(key)),
+    }
+}
+
+#[post("/{key}")]
+pub async fn put_value_for_key(
+    engine: Data<Engine>,
+    web::Path(key): web::Path<String>,
+    req_body: String
+) -> Result<HttpResponse, DbError> {
+    check_record(&key, &req_body)?;
+    let written = (key.len() + req_body.len()) as u64;
+    web::block(move || engine.put(&key, &req_body)).await?;
+    metrics::BYTES_WRITTEN.inc_by(written);
+    Ok(HttpResponse::Ok().body("It is saved"))
+}
+
+#[delete("/{key}")]
+pub async fn delete_value_for_key(
+    engine: Data<Engine>,
+    web::Path(key): web::Path<String>
+) -> Result<HttpResponse, DbError> {
+    check_record(&key, "")?;
+    web::block(move || engine.delete(&key)).await?;
+    Ok(HttpResponse::Ok().body("It has been deleted!"))
+}
+
+#[get("/_admin/stats")]
+pub async fn get_admin_stats(
+    engine: Data<Engine>,
+    kind: Data<EngineKind>
+) -> Result<HttpResponse, DbError> {
+    let stats = web::block(move || engine.stats()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "engine": kind.name(),
+        "stats": stats,
+    })))
+}
+
+// Makes every write so far durable, for engines that buffer or skip fsyncs.
+#[post("/_admin/flush")]
+pub async fn post_admin_flush(
+    engine: Data<Engine>
+) -> Result<HttpResponse, DbError> {
+    web::block(move || engine.flush()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/_export")]
+pub async fn export_records(
+    engine: Data<Engine>
+) -> Result<HttpResponse, DbError> {
+    let records = web::block(move || engine.scan()).await?;
+    Ok(jsonl::export(records.into_iter()))
+}
+
+#[post("/_import")]
+pub async fn import_records(
+    engine: Data<Engine>,
+    body: web::Payload
+) -> Result<HttpResponse, DbError> {
+    let mut lines = jsonl::Lines::new(body);
+    let mut imported = 0;
+    while let Some(line) = lines.next().await {
+        let record = line.map_err(|e| e.to_string()).and_then(|line| jsonl::decode(&line)).and_then(|(key, value)| {
+            check_record(&key, &value).map_err(|e| e.to_string())?;
+            Ok((key, value))
+        });
+        let (key, value) = match record {
+            Ok(record) => record,
+            Err(e) => return Ok(jsonl::import_result(imported, Some((lines.line_number, e)))),
+        };
+
+        let written = (key.len() + value.len()) as u64;
+        let engine = engine.clone();
+        if let Err(e) = web::block(move || engine.put(&key, &value)).await {
+            let e = DbError::from(e);
+            error!(error = %e, "couldn't import record");
+            return Ok(jsonl::import_result(imported, Some((lines.line_number, e.to_string()))));
+        }
+        metrics::BYTES_WRITTEN.inc_by(written);
+        imported += 1;
+    }
+    Ok(jsonl::import_result(imported, None))
+}
@@ -0,0 +1,57 @@
+use crate::engine::{StorageEngine, Stats};
+use all_memory_kv::store::{self, Db, Entry};
+use null_common::error::DbError;
+
+// The map behind all-memory-kv, gone when the process stops.
+pub struct Memory {
+    db: Db,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory { db: Db::default() }
+    }
+}
+
+impl StorageEngine for Memory {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(store::get(&self.db.read().unwrap(), key).map(|entry| entry.value))
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError> {
+        self.db.write().unwrap().insert(key.to_string(), Entry::plain(value.to_string()));
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        self.db.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        let mut map = self.db.write().unwrap();
+        for (key, value) in batch {
+            match value {
+                Some(value) => map.insert(key.clone(), Entry::plain(value.clone())),
+                None => map.remove(key),
+            };
+        }
+        Ok(())
+    }
+
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError> {
+        Ok(store::records(&self.db.read().unwrap()))
+    }
+
+    // nothing to make durable
+    fn flush(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<Stats, DbError> {
+        Ok(Stats {
+            keys: store::records(&self.db.read().unwrap()).len(),
+            ..Stats::default()
+        })
+    }
+}
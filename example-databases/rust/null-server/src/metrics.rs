@@ -0,0 +1,51 @@
+use crate::engine::EngineKind;
+use crate::Engine;
+use actix_web::{get, web::Data, Responder};
+use null_common::metrics::render;
+use prometheus::{register_int_counter, register_int_gauge_vec, IntCounter, IntGaugeVec};
+use tracing::error;
+
+lazy_static! {
+    pub static ref BYTES_WRITTEN: IntCounter = register_int_counter!(
+        "null_bytes_written_total",
+        "Bytes of keys and values written"
+    )
+    .unwrap();
+    static ref KEYS: IntGaugeVec = register_int_gauge_vec!(
+        "null_keys",
+        "Live keys, by engine",
+        &["engine"]
+    )
+    .unwrap();
+    static ref SEGMENTS: IntGaugeVec = register_int_gauge_vec!(
+        "null_segments",
+        "Files holding records, by engine",
+        &["engine"]
+    )
+    .unwrap();
+    static ref DISK_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_disk_bytes",
+        "Size of the files holding records, by engine",
+        &["engine"]
+    )
+    .unwrap();
+}
+
+// Engine gauges are read off the engine at scrape time, so they are never stale.
+#[get("/metrics")]
+pub async fn get_metrics(engine: Data<Engine>, kind: Data<EngineKind>) -> impl Responder {
+    match engine.stats() {
+        Ok(stats) => {
+            let name = kind.name();
+            KEYS.with_label_values(&[name]).set(stats.keys as i64);
+            SEGMENTS.with_label_values(&[name]).set(stats.segments as i64);
+            DISK_BYTES.with_label_values(&[name]).set(stats.disk_bytes as i64);
+        }
+        Err(e) => error!(error = %e, "couldn't read engine stats"),
+    }
+    if let Err(e) = engine.report_metrics() {
+        error!(error = %e, "couldn't read engine metrics");
+    }
+
+    render()
+}
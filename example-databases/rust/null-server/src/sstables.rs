@@ -0,0 +1,120 @@
+use crate::engine::{StorageEngine, Stats};
+use disk_sstables::{Db, Options};
+use null_common::error::DbError;
+use null_common::metrics::{catch_up, catch_up_one};
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+};
+use std::path::Path;
+
+lazy_static! {
+    static ref MEMTABLE_BYTES: IntGauge = register_int_gauge!(
+        "null_memtable_bytes",
+        "Bytes of keys and values in the memtable, waiting to be flushed"
+    )
+    .unwrap();
+    static ref LEVEL_TABLES: IntGaugeVec = register_int_gauge_vec!(
+        "null_lsm_level_tables",
+        "Tables in each level of the LSM tree",
+        &["level"]
+    )
+    .unwrap();
+    static ref LEVEL_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_lsm_level_bytes",
+        "Size of the tables in each level of the LSM tree",
+        &["level"]
+    )
+    .unwrap();
+    static ref COMPACTIONS: IntCounter = register_int_counter!(
+        "null_compactions_total",
+        "Compactions run, tables moved down a level without a merge included"
+    )
+    .unwrap();
+    static ref FILTER_CHECKS: IntCounterVec = register_int_counter_vec!(
+        "null_bloom_filter_checks_total",
+        "Table Bloom filters checked by lookups, by result: negative skipped the table, positive found the key, false_positive read the table for nothing",
+        &["result"]
+    )
+    .unwrap();
+    static ref CACHE_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "null_block_cache_requests_total",
+        "Block reads asked of the block cache, by result",
+        &["result"]
+    )
+    .unwrap();
+    static ref CACHE_BYTES: IntGaugeVec = register_int_gauge_vec!(
+        "null_block_cache_bytes",
+        "Bytes of blocks in the block cache, and what it can hold",
+        &["kind"]
+    )
+    .unwrap();
+}
+
+// A log-structured merge tree, see the disk-sstables crate.
+pub struct Sstables {
+    db: Db,
+}
+
+impl Sstables {
+    pub fn open(dir: &Path) -> Result<Self, DbError> {
+        Ok(Sstables {
+            db: disk_sstables::open(dir, Options::default())?,
+        })
+    }
+}
+
+impl StorageEngine for Sstables {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        Ok(self.db.get(key)?)
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError> {
+        Ok(self.db.put(key, value)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        Ok(self.db.delete(key)?)
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        Ok(self.db.write_batch(batch)?)
+    }
+
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError> {
+        Ok(self.db.scan()?)
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        Ok(self.db.flush()?)
+    }
+
+    fn stats(&self) -> Result<Stats, DbError> {
+        let stats = self.db.stats()?;
+        Ok(Stats {
+            keys: stats.keys,
+            segments: stats.tables + stats.value_log.files,
+            disk_bytes: stats.disk_bytes + stats.value_log.bytes,
+        })
+    }
+
+    fn report_metrics(&self) -> Result<(), DbError> {
+        let stats = self.db.stats()?;
+        MEMTABLE_BYTES.set(stats.memtable_bytes as i64);
+        for (level, level_stats) in stats.levels.iter().enumerate() {
+            let level = format!("L{}", level);
+            LEVEL_TABLES.with_label_values(&[&level]).set(level_stats.tables as i64);
+            LEVEL_BYTES.with_label_values(&[&level]).set(level_stats.bytes as i64);
+        }
+        catch_up_one(&COMPACTIONS, stats.compactions);
+        catch_up(&FILTER_CHECKS, &[
+            ("negative", stats.filters.negatives),
+            ("positive", stats.filters.positives),
+            ("false_positive", stats.filters.false_positives),
+        ]);
+        catch_up(&CACHE_REQUESTS, &[("hit", stats.cache.hits), ("miss", stats.cache.misses)]);
+        CACHE_BYTES.with_label_values(&["used"]).set(stats.cache.bytes as i64);
+        CACHE_BYTES.with_label_values(&["capacity"]).set(stats.cache.capacity as i64);
+        Ok(())
+    }
+}
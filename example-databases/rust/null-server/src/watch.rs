@@ -0,0 +1,115 @@
+use crate::engine::{StorageEngine, Stats};
+use null_common::error::DbError;
+use std::sync::RwLock;
+use tokio::sync::broadcast;
+
+// how many changes a watcher can fall behind by before it is cut off
+const BACKLOG: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub key: String,
+    // None when the key was deleted
+    pub value: Option<String>,
+}
+
+// Wraps an engine so every write made through it, whichever API it came in
+// on, is sent on to the watchers.
+pub struct Watched {
+    engine: Box<dyn StorageEngine>,
+    changes: broadcast::Sender<Change>,
+    // Watchers have to see changes in the order the engine took them, or the
+    // last value one sees for a key might not be the one stored. So while
+    // anyone is watching, each write holds this exclusively across the write
+    // and the send. With nobody watching, writes only share it and run
+    // together; a watcher arriving part way through one waits out the rest
+    // behind its first change, so it never sees an older write after a newer
+    // one.
+    order: RwLock<()>,
+}
+
+impl Watched {
+    pub fn new(engine: Box<dyn StorageEngine>) -> Self {
+        let (changes, _) = broadcast::channel(BACKLOG);
+        Watched {
+            engine,
+            changes,
+            order: RwLock::new(()),
+        }
+    }
+
+    // Something to subscribe to changes with, that can outlive a borrow of the
+    // engine.
+    pub fn changes(&self) -> broadcast::Sender<Change> {
+        self.changes.clone()
+    }
+
+    // Makes the write, then sends what it changed if anyone is watching.
+    fn write<F, C>(&self, write: F, changes: C) -> Result<(), DbError>
+    where
+        F: FnOnce() -> Result<(), DbError>,
+        C: FnOnce() -> Vec<Change>,
+    {
+        {
+            let _unwatched = self.order.read().unwrap();
+            if self.changes.receiver_count() == 0 {
+                return write();
+            }
+        }
+        let _watched = self.order.write().unwrap();
+        write()?;
+        for change in changes() {
+            // a watcher gone since is not an error
+            let _ = self.changes.send(change);
+        }
+        Ok(())
+    }
+}
+
+impl StorageEngine for Watched {
+    fn get(&self, key: &str) -> Result<Option<String>, DbError> {
+        self.engine.get(key)
+    }
+
+    fn put(&self, key: &str, value: &str) -> Result<(), DbError> {
+        self.write(
+            || self.engine.put(key, value),
+            || vec![Change { key: key.to_string(), value: Some(value.to_string()) }],
+        )
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        self.write(
+            || self.engine.delete(key),
+            || vec![Change { key: key.to_string(), value: None }],
+        )
+    }
+
+    fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), DbError> {
+        self.write(
+            || self.engine.write_batch(batch),
+            || {
+                batch
+                    .iter()
+                    .map(|(key, value)| Change { key: key.clone(), value: value.clone() })
+                    .collect()
+            },
+        )
+    }
+
+    fn scan(&self) -> Result<Vec<(String, String)>, DbError> {
+        self.engine.scan()
+    }
+
+    fn flush(&self) -> Result<(), DbError> {
+        self.engine.flush()
+    }
+
+    fn stats(&self) -> Result<Stats, DbError> {
+        self.engine.stats()
+    }
+
+    fn report_metrics(&self) -> Result<(), DbError> {
+        self.engine.report_metrics()
+    }
+}
@@ -0,0 +1,78 @@
+// Every engine behind the StorageEngine trait answers the same API the same
+// way, so each test here runs the same checks against one engine.
+mod server;
+
+use serde_json::Value;
+use server::Server;
+
+fn answers_the_same_api(engine: &str, durable: bool) {
+    let mut server = Server::start("api", engine);
+
+    assert_eq!(server.get("foo").0, 404);
+    assert_eq!(server.put("foo", "bar"), 200);
+    assert_eq!(server.get("foo"), (200, "bar".to_string()));
+    assert_eq!(server.put("foo", "baz"), 200);
+    assert_eq!(server.get("foo"), (200, "baz".to_string()));
+    assert_eq!(server.delete("foo"), 200);
+    assert_eq!(server.get("foo").0, 404);
+
+    // the same rules for what a record can hold, with the same errors
+    assert_eq!(server.put("a:b", "c"), 400);
+    assert_eq!(server.put("k", "two\nlines"), 400);
+    let body: Value = server.client.get(server.url("missing")).send().unwrap().json().unwrap();
+    assert_eq!(body["error"], "not_found");
+
+    // export and import carry every record across
+    for i in 0..50 {
+        assert_eq!(server.put(&format!("k{}", i), &format!("v{}", i)), 200);
+    }
+    let exported = server.client.get(server.url("_export")).send().unwrap().text().unwrap();
+    assert_eq!(exported.lines().count(), 50);
+    let other = Server::start("import", engine);
+    let resp = other.client.post(other.url("_import")).body(exported).send().unwrap();
+    assert!(resp.status().is_success());
+    assert_eq!(other.get("k49"), (200, "v49".to_string()));
+    let stats: Value = other.client.get(other.url("_admin/stats")).send().unwrap().json().unwrap();
+    assert_eq!(stats["engine"], engine);
+    assert_eq!(stats["stats"]["keys"], 50);
+
+    assert!(server.client.post(server.url("_admin/flush")).send().unwrap().status().is_success());
+    server.kill();
+    server.restart();
+    if durable {
+        assert_eq!(server.get("k0"), (200, "v0".to_string()));
+        assert_eq!(server.get("foo").0, 404);
+    } else {
+        assert_eq!(server.get("k0").0, 404);
+    }
+}
+
+#[test]
+fn memory() {
+    answers_the_same_api("memory", false);
+}
+
+#[test]
+fn log() {
+    answers_the_same_api("log", true);
+}
+
+#[test]
+fn log_segments() {
+    answers_the_same_api("log-segments", true);
+}
+
+#[test]
+fn hash_index() {
+    answers_the_same_api("hash-index", true);
+}
+
+#[test]
+fn sstables() {
+    answers_the_same_api("sstables", true);
+}
+
+#[test]
+fn btree() {
+    answers_the_same_api("btree", true);
+}
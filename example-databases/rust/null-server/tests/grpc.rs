@@ -0,0 +1,44 @@
+// The gRPC API, through a client built from the same proto/null.proto.
+mod server;
+
+use server::Server;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/null.rs"));
+}
+
+use proto::key_value_client::KeyValueClient;
+use proto::{Change, DeleteRequest, GetRequest, PutRequest, WatchRequest};
+
+#[test]
+fn watch_streams_writes_under_its_prefix() {
+    let server = Server::start("watch", "memory");
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let mut client = KeyValueClient::connect(server.grpc_url()).await.unwrap();
+        let mut changes = client
+            .watch(WatchRequest { prefix: "user/".to_string() })
+            .await
+            .unwrap()
+            .into_inner();
+
+        for key in ["other/1", "user/1"] {
+            client.put(PutRequest { key: key.to_string(), value: "v".to_string() }).await.unwrap();
+        }
+        client.delete(DeleteRequest { key: "user/1".to_string() }).await.unwrap();
+
+        // other/1 never shows up
+        let expected = [
+            Change { key: "user/1".to_string(), value: "v".to_string(), deleted: false },
+            Change { key: "user/1".to_string(), value: String::new(), deleted: true },
+        ];
+        for change in expected {
+            let got = tokio::time::timeout(Duration::from_secs(10), changes.next()).await.unwrap();
+            assert_eq!(got.unwrap().unwrap(), change);
+        }
+
+        let missing = client.get(GetRequest { key: "user/1".to_string() }).await.unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+    });
+}
@@ -0,0 +1,101 @@
+// A null-server run by a test over one engine, with its files in a directory
+// and its HTTP, RESP and gRPC listeners on ports of its own.
+// Each test file uses a different part of this.
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct Server {
+    pub engine: String,
+    pub dir: PathBuf,
+    pub port: u16,
+    pub grpc_port: u16,
+    resp_port: u16,
+    child: Option<Child>,
+    pub client: reqwest::blocking::Client,
+}
+
+impl Server {
+    pub fn start(test: &str, engine: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("null-server-{}-{}-{}", test, engine, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut server = Server {
+            engine: engine.to_string(),
+            dir,
+            port: free_port(),
+            grpc_port: free_port(),
+            resp_port: free_port(),
+            child: None,
+            client: reqwest::blocking::Client::builder().timeout(Duration::from_secs(10)).build().unwrap(),
+        };
+        server.restart();
+        server
+    }
+
+    pub fn restart(&mut self) {
+        let child = Command::new(env!("CARGO_BIN_EXE_null-server"))
+            .args(["--engine", &self.engine])
+            .args(["--port", &self.port.to_string()])
+            .args(["--resp-port", &self.resp_port.to_string()])
+            .args(["--grpc-port", &self.grpc_port.to_string()])
+            .arg("--data-dir")
+            .arg(&self.dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        self.child = Some(child);
+        let started = Instant::now();
+        while self.client.get(self.url("_admin/stats")).send().is_err() {
+            assert!(started.elapsed() < Duration::from_secs(30), "{} didn't start", self.engine);
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    pub fn kill(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}/{}", self.port, path)
+    }
+
+    pub fn grpc_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.grpc_port)
+    }
+
+    // The status and body of a GET.
+    pub fn get(&self, key: &str) -> (u16, String) {
+        let resp = self.client.get(self.url(key)).send().unwrap();
+        (resp.status().as_u16(), resp.text().unwrap())
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> u16 {
+        self.client.post(self.url(key)).body(value.to_string()).send().unwrap().status().as_u16()
+    }
+
+    pub fn delete(&self, key: &str) -> u16 {
+        self.client.delete(self.url(key)).send().unwrap().status().as_u16()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.kill();
+        // a failed test's directory is kept to look at
+        if !thread::panicking() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
@@ -0,0 +1,174 @@
+// A block of entries sorted by key, the unit a table is read in. Each key is
+// stored as the length it shares with the key before it plus the bytes that
+// differ, so sorted keys with long common prefixes take little room. Every
+// RESTART_INTERVAL entries a key is stored whole, a restart point, and the
+// offsets of the restart points close the block so a lookup can binary search
+// them and only decode the run of entries after one.
+//
+// entry:   shared u32 | unshared u32 | value length u32 | kind u8 | key suffix | value
+// trailer: restart offset u32, one per restart point | restart count u32
+use crate::encoding::{invalid, put_u32, u32_at};
+use std::cmp::Ordering;
+use std::io;
+
+pub const RESTART_INTERVAL: usize = 16;
+
+// a key, its kind and its value, as stored
+pub type RawEntry = (Vec<u8>, u8, Vec<u8>);
+
+// shared, unshared, value length and kind
+const ENTRY_HEADER_BYTES: usize = 13;
+
+#[derive(Default)]
+pub struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    last_key: Vec<u8>,
+    since_restart: usize,
+}
+
+impl BlockBuilder {
+    // Keys must be added in order.
+    pub fn add(&mut self, key: &[u8], kind: u8, value: &[u8]) {
+        let shared = if self.restarts.is_empty() || self.since_restart == RESTART_INTERVAL {
+            self.restarts.push(self.buf.len() as u32);
+            self.since_restart = 0;
+            0
+        } else {
+            self.last_key.iter().zip(key).take_while(|(a, b)| a == b).count()
+        };
+        put_u32(&mut self.buf, shared as u32);
+        put_u32(&mut self.buf, (key.len() - shared) as u32);
+        put_u32(&mut self.buf, value.len() as u32);
+        self.buf.push(kind);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.since_restart += 1;
+    }
+
+    // What finish would return, give or take nothing.
+    pub fn len(&self) -> usize {
+        self.buf.len() + 4 * self.restarts.len() + 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.restarts.is_empty()
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    // The finished block. The builder is left empty for the next one.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buf);
+        for restart in &self.restarts {
+            put_u32(&mut block, *restart);
+        }
+        put_u32(&mut block, self.restarts.len() as u32);
+        *self = BlockBuilder::default();
+        block
+    }
+}
+
+pub struct Block {
+    data: Vec<u8>,
+    // where the restart offsets start, which is also where the entries end
+    restarts: usize,
+    count: usize,
+}
+
+// One decoded entry, borrowing its value from the block.
+struct Decoded<'a> {
+    kind: u8,
+    value: &'a [u8],
+    // where the next entry starts
+    next: usize,
+}
+
+impl Block {
+    pub fn new(data: Vec<u8>) -> io::Result<Self> {
+        if data.len() < 4 {
+            return Err(invalid("block is too short".to_string()));
+        }
+        let count = u32_at(&data, data.len() - 4)? as usize;
+        let restarts = match data.len().checked_sub(4 + 4 * count) {
+            // only an empty block has no restart points
+            Some(restarts) if count > 0 || restarts == 0 => restarts,
+            _ => return Err(invalid(format!("block can't hold {} restart points", count))),
+        };
+        Ok(Block { data, restarts, count })
+    }
+
+    // The kind and value stored for key, if the block holds it.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<(u8, Vec<u8>)>> {
+        // the last restart point whose key is at or before key; keys before
+        // the first restart point aren't in this block
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let mut restart_key = Vec::new();
+            self.decode(self.restart(mid)?, &mut restart_key)?;
+            match restart_key.as_slice().cmp(key) {
+                Ordering::Greater => high = mid,
+                _ => low = mid + 1,
+            }
+        }
+        if low == 0 {
+            return Ok(None);
+        }
+
+        let mut at = self.restart(low - 1)?;
+        let mut current = Vec::new();
+        while at < self.restarts {
+            let entry = self.decode(at, &mut current)?;
+            match current.as_slice().cmp(key) {
+                Ordering::Less => at = entry.next,
+                Ordering::Equal => return Ok(Some((entry.kind, entry.value.to_vec()))),
+                Ordering::Greater => break,
+            }
+        }
+        Ok(None)
+    }
+
+    // Every key, kind and value, in key order.
+    pub fn entries(&self) -> io::Result<Vec<RawEntry>> {
+        let mut entries = Vec::new();
+        let mut key = Vec::new();
+        let mut at = 0;
+        while at < self.restarts {
+            let entry = self.decode(at, &mut key)?;
+            entries.push((key.clone(), entry.kind, entry.value.to_vec()));
+            at = entry.next;
+        }
+        Ok(entries)
+    }
+
+    fn restart(&self, i: usize) -> io::Result<usize> {
+        Ok(u32_at(&self.data, self.restarts + 4 * i)? as usize)
+    }
+
+    // Decodes the entry at offset at, turning key, which must hold the key
+    // before it, into its key.
+    fn decode(&self, at: usize, key: &mut Vec<u8>) -> io::Result<Decoded<'_>> {
+        let shared = u32_at(&self.data, at)? as usize;
+        let unshared = u32_at(&self.data, at + 4)? as usize;
+        let value_len = u32_at(&self.data, at + 8)? as usize;
+        let kind = *self.data.get(at + 12).ok_or_else(|| invalid(format!("entry at {} is cut short", at)))?;
+        let start = at + ENTRY_HEADER_BYTES;
+        let end = start + unshared + value_len;
+        if shared > key.len() || end > self.restarts {
+            return Err(invalid(format!("entry at {} runs past its block", at)));
+        }
+        key.truncate(shared);
+        key.extend_from_slice(&self.data[start..start + unshared]);
+        Ok(Decoded {
+            kind,
+            value: &self.data[start + unshared..end],
+            next: end,
+        })
+    }
+}
@@ -0,0 +1,62 @@
+// A Bloom filter over the keys of one table, so a lookup for a key the table
+// doesn't hold can usually skip reading a block. Each key sets k bits picked
+// by double hashing one 64-bit hash; a key whose bits aren't all set was
+// never added. With 10 bits per key about 1% of absent keys get through.
+//
+// filter: bit array | k u8
+use crate::encoding::invalid;
+use std::io;
+
+pub struct Bloom {
+    bits: Vec<u8>,
+    k: u32,
+}
+
+// Builds a filter from the hash of every key in a table.
+pub fn build(hashes: &[u64], bits_per_key: usize) -> Vec<u8> {
+    // ln 2 of the bits per key gives the fewest false positives
+    let k = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+    let nbits = (hashes.len() * bits_per_key).max(64);
+    let mut filter = vec![0; nbits.div_ceil(8)];
+    let nbits = filter.len() as u64 * 8;
+    for hash in hashes {
+        for bit in probes(*hash, k, nbits) {
+            filter[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+    filter.push(k as u8);
+    filter
+}
+
+impl Bloom {
+    pub fn new(mut data: Vec<u8>) -> io::Result<Self> {
+        match data.pop() {
+            Some(k) if k > 0 && !data.is_empty() => Ok(Bloom { bits: data, k: k as u32 }),
+            _ => Err(invalid("bloom filter is malformed".to_string())),
+        }
+    }
+
+    // False means key is certainly not in the table.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        let nbits = self.bits.len() as u64 * 8;
+        probes(hash, self.k, nbits).all(|bit| self.bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0)
+    }
+}
+
+// FNV-1a, with murmur3's finalizer so the low bits mix as well as the high.
+pub fn hash(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^ (hash >> 33)
+}
+
+fn probes(hash: u64, k: u32, nbits: u64) -> impl Iterator<Item = u64> {
+    let h1 = hash as u32 as u64;
+    let h2 = (hash >> 32) | 1;
+    (0..k as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % nbits)
+}
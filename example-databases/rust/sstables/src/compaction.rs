@@ -0,0 +1,219 @@
+// Leveled compaction. L0 holds tables as the memtable was flushed, newest
+// first, and their keys overlap. Every level after it holds tables in key
+// order whose keys don't, so a lookup reads at most one table there, and each
+// level may hold 10 times the bytes of the one before. The level furthest
+// over its limit is compacted first: all of L0, or one table of a deeper
+// level, taken round-robin through its keys, is merged with the tables it
+// overlaps in the next level, and the merged tables replace them there.
+//
+// The merge reads and writes tables without holding the state lock, so reads
+// and writes carry on, and only swapping the tables in takes it.
+use crate::sstable::{self, Table, TableEntry, Value};
+use crate::vlog::POINTER_BYTES;
+use crate::{manifest, table_path, Error, Options, Shared};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+pub const LEVELS: usize = 7;
+const LEVEL_MULTIPLIER: u64 = 10;
+// how long to wait before trying again after a compaction fails
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+// The tables one compaction merges.
+struct Pick {
+    level: usize,
+    inputs: Vec<Arc<Table>>,
+    // the tables in the next level whose keys overlap the inputs
+    overlapping: Vec<Arc<Table>>,
+}
+
+// Runs one compaction, if one is due. False when none was.
+pub fn run(shared: &Shared) -> Result<bool, Error> {
+    let mut pointers = shared.compacting.lock().unwrap();
+    let pick = {
+        let state = shared.state.read().unwrap();
+        match pick(&state.levels, &shared.options, &pointers) {
+            Some(pick) => pick,
+            None => return Ok(false),
+        }
+    };
+    let start = Instant::now();
+    let output_level = pick.level + 1;
+    if pick.level > 0 {
+        pointers[pick.level] = pick.inputs[0].largest.clone();
+    }
+
+    // a table that overlaps nothing in the next level moves down as it is
+    if pick.level > 0 && pick.overlapping.is_empty() {
+        let table = pick.inputs[0].clone();
+        let mut state = shared.state.write().unwrap();
+        state.levels[pick.level].retain(|t| t.id != table.id);
+        insert(&mut state.levels[output_level], vec![table.clone()]);
+        manifest::write(&shared.dir, &ids(&state.levels))?;
+        shared.compactions.fetch_add(1, Ordering::Relaxed);
+        info!(id = table.id, from = pick.level, to = output_level, "moved table");
+        return Ok(true);
+    }
+
+    // oldest first, so newer entries win: the next level, then the inputs
+    let mut merged = BTreeMap::new();
+    for table in pick.overlapping.iter().chain(pick.inputs.iter().rev()) {
+        merged.extend(table.entries()?);
+    }
+    // a delete only has to be kept while an older value may be below it
+    {
+        let state = shared.state.read().unwrap();
+        let deeper = &state.levels[output_level + 1..];
+        merged.retain(|key, value| value.is_some() || deeper.iter().any(|level| find(level, key.as_bytes()).is_some()));
+    }
+
+    let mut outputs = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+    for (key, value) in merged {
+        let value_bytes = match &value {
+            Some(Value::Inline(value)) => value.len(),
+            Some(Value::Pointer(_)) => POINTER_BYTES,
+            None => 0,
+        };
+        batch_bytes += key.len() + value_bytes;
+        batch.push((key, value));
+        if batch_bytes >= shared.options.table_bytes {
+            outputs.push(write_table(shared, &batch)?);
+            batch.clear();
+            batch_bytes = 0;
+        }
+    }
+    if !batch.is_empty() {
+        outputs.push(write_table(shared, &batch)?);
+    }
+
+    let inputs = pick.inputs.iter().chain(&pick.overlapping).collect::<Vec<&Arc<Table>>>();
+    let gone = inputs.iter().map(|t| t.id).collect::<HashSet<u64>>();
+    {
+        let mut state = shared.state.write().unwrap();
+        state.levels[pick.level].retain(|t| !gone.contains(&t.id));
+        state.levels[output_level].retain(|t| !gone.contains(&t.id));
+        insert(&mut state.levels[output_level], outputs.clone());
+        manifest::write(&shared.dir, &ids(&state.levels))?;
+    }
+    // reads that started before the swap still have the files open
+    for table in &inputs {
+        std::fs::remove_file(&table.path)?;
+    }
+    shared.compactions.fetch_add(1, Ordering::Relaxed);
+    info!(
+        from = pick.level,
+        to = output_level,
+        inputs = inputs.len(),
+        outputs = outputs.len(),
+        bytes_in = inputs.iter().map(|t| t.size).sum::<u64>(),
+        bytes_out = outputs.iter().map(|t| t.size).sum::<u64>(),
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "compacted"
+    );
+    Ok(true)
+}
+
+// The body of the background compaction thread: runs compactions until none
+// are due, then waits for a flush to make one due, until the database closes.
+pub fn background(shared: &Shared) {
+    loop {
+        {
+            let mut wake = shared.wake.lock().unwrap();
+            while !wake.pending && !wake.closing {
+                wake = shared.woken.wait(wake).unwrap();
+            }
+            if wake.closing {
+                return;
+            }
+            wake.pending = false;
+        }
+        loop {
+            if shared.wake.lock().unwrap().closing {
+                return;
+            }
+            match run(shared) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    error!(error = %e, "compaction failed");
+                    let wake = shared.wake.lock().unwrap();
+                    let (mut wake, _) = shared.woken.wait_timeout(wake, RETRY_AFTER).unwrap();
+                    wake.pending = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The table in level, which must be in key order, whose keys span key.
+pub fn find<'a>(level: &'a [Arc<Table>], key: &[u8]) -> Option<&'a Arc<Table>> {
+    let i = level.partition_point(|t| t.largest.as_slice() < key);
+    level.get(i).filter(|t| t.smallest.as_slice() <= key)
+}
+
+// The table ids in each level, as the manifest lists them.
+pub fn ids(levels: &[Vec<Arc<Table>>]) -> Vec<Vec<u64>> {
+    levels.iter().map(|level| level.iter().map(|t| t.id).collect()).collect()
+}
+
+// Picks the level furthest over its limit, if any is, and what to merge from
+// it. The last level has nowhere to go, so it is never picked.
+fn pick(levels: &[Vec<Arc<Table>>], options: &Options, pointers: &[Vec<u8>]) -> Option<Pick> {
+    let mut best = None;
+    let mut best_score = 1.0;
+    for (level, tables) in levels.iter().enumerate().take(LEVELS - 1) {
+        let score = if level == 0 {
+            tables.len() as f64 / options.l0_compaction_trigger.max(1) as f64
+        } else {
+            let bytes = tables.iter().map(|t| t.size).sum::<u64>();
+            bytes as f64 / level_limit(options, level) as f64
+        };
+        if score >= best_score {
+            best = Some(level);
+            best_score = score;
+        }
+    }
+    let level = best?;
+
+    let inputs = if level == 0 {
+        levels[0].clone()
+    } else {
+        // the first table after where the last compaction of this level
+        // stopped, starting over at the front once it reaches the end
+        let tables = &levels[level];
+        let next = tables.iter().find(|t| t.smallest > pointers[level]).unwrap_or(&tables[0]);
+        vec![next.clone()]
+    };
+    let smallest = inputs.iter().map(|t| &t.smallest).min()?;
+    let largest = inputs.iter().map(|t| &t.largest).max()?;
+    let overlapping = levels[level + 1]
+        .iter()
+        .filter(|t| &t.largest >= smallest && &t.smallest <= largest)
+        .cloned()
+        .collect();
+    Some(Pick { level, inputs, overlapping })
+}
+
+// How many bytes level, which isn't L0, may hold.
+fn level_limit(options: &Options, level: usize) -> u64 {
+    options.level1_bytes.saturating_mul(LEVEL_MULTIPLIER.saturating_pow(level as u32 - 1))
+}
+
+fn write_table(shared: &Shared, entries: &[TableEntry]) -> Result<Arc<Table>, Error> {
+    let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+    let path = table_path(&shared.dir, id);
+    sstable::write(&path, entries.iter().map(|(k, v)| (k, v)), shared.options.bloom_bits_per_key)?;
+    Ok(Arc::new(Table::open(id, path, shared.cache.clone())?))
+}
+
+// Adds tables to level, keeping it in key order.
+fn insert(level: &mut Vec<Arc<Table>>, tables: Vec<Arc<Table>>) {
+    level.extend(tables);
+    level.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+}
@@ -0,0 +1,77 @@
+// How a key and its value, or the tombstone that deleted it, are laid out in
+// the write-ahead log: a kind byte, then the key and the value each as a
+// little-endian u32 length followed by that many bytes. Tables use the same
+// kinds and integers but lay entries out in blocks, see block.rs.
+use std::convert::TryInto;
+use std::io::{self, Read};
+
+// a key and its value, None once the key is deleted
+pub type Entry = (String, Option<String>);
+
+pub const PUT: u8 = 0;
+pub const DELETE: u8 = 1;
+// only in tables, whose value is where the value log keeps it
+pub const POINTER: u8 = 2;
+
+pub fn encode_entry(buf: &mut Vec<u8>, key: &str, value: Option<&str>) {
+    buf.push(if value.is_some() { PUT } else { DELETE });
+    put_str(buf, key);
+    put_str(buf, value.unwrap_or(""));
+}
+
+pub fn decode_entry(r: &mut impl Read) -> io::Result<Entry> {
+    let mut kind = [0; 1];
+    r.read_exact(&mut kind)?;
+    let key = read_str(r)?;
+    let value = read_str(r)?;
+    match kind[0] {
+        PUT => Ok((key, Some(value))),
+        DELETE => Ok((key, None)),
+        other => Err(invalid(format!("unknown entry kind {}", other))),
+    }
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, n: u64) {
+    buf.extend_from_slice(&n.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+pub fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// The u32 at offset at in data, for readers working on a whole block in memory.
+pub fn u32_at(data: &[u8], at: usize) -> io::Result<u32> {
+    match data.get(at..at + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(invalid(format!("offset {} is past the end", at))),
+    }
+}
+
+pub fn u64_at(data: &[u8], at: usize) -> io::Result<u64> {
+    match data.get(at..at + 8) {
+        Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap())),
+        None => Err(invalid(format!("offset {} is past the end", at))),
+    }
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(r)? as usize;
+    let mut bytes = vec![0; len];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid("string is not utf-8".to_string()))
+}
+
+pub fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,579 @@
+//! A log-structured merge tree. Writes go to a write-ahead log and a sorted
+//! in-memory memtable, which is flushed to an immutable sorted table on disk
+//! once it grows past a size threshold. Tables are merged into levels of
+//! non-overlapping tables by a background thread (see compaction.rs). Reads
+//! look in the memtable, then the tables newest first, and stop at the first
+//! that knows the key. Each table has a Bloom filter, so most tables that
+//! don't hold a key are skipped without reading them, and recently read
+//! blocks are kept in a cache. Large values can be kept out of the tables, in
+//! a value log (see vlog.rs).
+//!
+//! ```no_run
+//! let db = disk_sstables::open("/tmp/null", disk_sstables::Options::default())?;
+//! db.put("foo", "bar")?;
+//! assert_eq!(db.get("foo")?, Some("bar".to_string()));
+//! db.close()?;
+//! # Ok::<(), disk_sstables::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use null_common::cache::BlockCache;
+use null_common::error::DbError;
+use tracing::{info, warn};
+
+mod block;
+mod bloom;
+mod compaction;
+mod encoding;
+mod manifest;
+mod memtable;
+mod sstable;
+mod vlog;
+mod wal;
+
+use block::Block;
+pub use null_common::cache::CacheStats;
+use compaction::LEVELS;
+use memtable::Memtable;
+use sstable::{Table, Value};
+use vlog::ValueLog;
+pub use vlog::ValueLogStats;
+use wal::Wal;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // a record or file on disk we can't make sense of
+    Corrupt(String),
+    // a key or value we can't store as given
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// For callers that only deal in io::Error.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Corrupt(msg) => io::Error::new(io::ErrorKind::InvalidData, msg),
+            Error::Invalid(msg) => io::Error::new(io::ErrorKind::InvalidInput, msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => Error::Corrupt(e.to_string()),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+// Lets the servers hand our errors straight back as HTTP responses.
+impl From<Error> for DbError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e.into(),
+            Error::Corrupt(msg) => DbError::Corrupt(msg),
+            Error::Invalid(msg) => DbError::Invalid(msg),
+        }
+    }
+}
+
+pub struct Options {
+    // the memtable is flushed to a table once its keys and values add up to
+    // this many bytes
+    pub memtable_bytes: usize,
+    // fsync the write-ahead log after every write rather than only on flush
+    pub sync_writes: bool,
+    // the size of each table's Bloom filter; 0 leaves filters out of new
+    // tables
+    pub bloom_bits_per_key: usize,
+    // how many bytes of table blocks to keep in memory, across every table;
+    // 0 reads every block from disk
+    pub block_cache_bytes: usize,
+    // L0 is compacted into L1 once it holds this many tables
+    pub l0_compaction_trigger: usize,
+    // L1 is compacted into L2 once its tables add up to this many bytes, and
+    // every level after that holds 10 times the one before
+    pub level1_bytes: u64,
+    // compaction cuts what it writes into tables of about this many bytes
+    pub table_bytes: usize,
+    // values of at least this many bytes are kept in the value log, and only
+    // a pointer to them in the tables; 0 keeps every value in the tables
+    pub value_threshold: usize,
+    // a value log file is closed, and a new one started, once it grows past
+    // this many bytes
+    pub value_log_file_bytes: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            memtable_bytes: 4 * 1024 * 1024,
+            sync_writes: false,
+            bloom_bits_per_key: 10,
+            block_cache_bytes: 8 * 1024 * 1024,
+            l0_compaction_trigger: 4,
+            level1_bytes: 10 * 1024 * 1024,
+            table_bytes: 2 * 1024 * 1024,
+            value_threshold: 0,
+            value_log_file_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub keys: usize,
+    pub tables: usize,
+    pub memtable_bytes: usize,
+    // tables and write-ahead logs
+    pub disk_bytes: u64,
+    // L0 first
+    pub levels: Vec<LevelStats>,
+    pub compactions: u64,
+    pub filters: FilterStats,
+    pub cache: CacheStats,
+    pub value_log: ValueLogStats,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LevelStats {
+    pub tables: usize,
+    pub bytes: u64,
+}
+
+// What table filters did for lookups since the database was opened.
+#[derive(Debug, Default)]
+pub struct FilterStats {
+    // tables skipped because their filter ruled the key out
+    pub negatives: u64,
+    // tables whose filter let the key through, and that held it
+    pub positives: u64,
+    // tables whose filter let the key through, but that didn't hold it
+    pub false_positives: u64,
+}
+
+pub struct Db {
+    shared: Arc<Shared>,
+    // the background compaction thread, stopped when the database is dropped
+    compactor: Option<JoinHandle<()>>,
+}
+
+// Everything the background compaction thread needs as well.
+struct Shared {
+    dir: PathBuf,
+    options: Options,
+    state: RwLock<State>,
+    cache: Arc<BlockCache<Block>>,
+    // ids for new logs and tables
+    next_id: AtomicU64,
+    // held while compacting, with the largest key each level's last
+    // compaction took, so the next one carries on after it
+    compacting: Mutex<Vec<Vec<u8>>>,
+    compactions: AtomicU64,
+    // held while collecting value log garbage
+    collecting: Mutex<()>,
+    wake: Mutex<Wake>,
+    woken: Condvar,
+    filter_negatives: AtomicU64,
+    filter_positives: AtomicU64,
+    filter_false_positives: AtomicU64,
+}
+
+// What the compaction thread is woken for.
+#[derive(Default)]
+struct Wake {
+    // a flush may have made a compaction due
+    pending: bool,
+    closing: bool,
+}
+
+struct State {
+    memtable: Memtable,
+    wal: Wal,
+    // the id the memtable's table will get, which its log is named after
+    wal_id: u64,
+    // older logs whose writes are in the memtable too, left by a crash
+    // part way through a flush
+    replayed: Vec<PathBuf>,
+    // L0 newest first, its tables' keys overlapping; every deeper level in
+    // key order, with no two tables holding the same key
+    levels: Vec<Vec<Arc<Table>>>,
+    vlog: ValueLog,
+}
+
+// Opens the database kept in dir, creating it if needed. Writes that were
+// only in the write-ahead log are replayed into the memtable.
+pub fn open<P: AsRef<Path>>(path: P, options: Options) -> Result<Db, Error> {
+    let dir = path.as_ref().to_path_buf();
+    std::fs::create_dir_all(&dir)?;
+
+    let cache = Arc::new(BlockCache::new(options.block_cache_bytes));
+    let mut tables = HashMap::new();
+    let mut logs = Vec::new();
+    let mut values = Vec::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let id = match path.file_stem().and_then(OsStr::to_str).and_then(|s| s.parse::<u64>().ok()) {
+            Some(id) => id,
+            None => continue,
+        };
+        match path.extension().and_then(OsStr::to_str) {
+            Some("sst") => {
+                tables.insert(id, path);
+            }
+            Some("wal") => logs.push((id, path)),
+            Some("vlog") => values.push(id),
+            // a table that never got renamed into place
+            Some("tmp") => std::fs::remove_file(&path)?,
+            _ => {}
+        }
+    }
+    let newest = tables.keys().chain(logs.iter().map(|(id, _)| id)).chain(&values).copied().max();
+    let next_id = newest.map_or(1, |id| id + 1);
+
+    let listed = match manifest::read(&dir)? {
+        Some(listed) => listed,
+        // a database from before there were levels, all of it in L0
+        None => {
+            let mut ids = tables.keys().copied().collect::<Vec<u64>>();
+            ids.sort_by_key(|id| std::cmp::Reverse(*id));
+            vec![ids]
+        }
+    };
+    if listed.len() > LEVELS {
+        return Err(Error::Corrupt(format!("manifest lists {} levels", listed.len())));
+    }
+    let mut levels = vec![Vec::new(); LEVELS];
+    for (level, ids) in listed.into_iter().enumerate() {
+        for id in ids {
+            let path = match tables.remove(&id) {
+                Some(path) => path,
+                None => return Err(Error::Corrupt(format!("table {} is in the manifest but not on disk", id))),
+            };
+            levels[level].push(Arc::new(Table::open(id, path, cache.clone())?));
+        }
+    }
+    for level in &mut levels[1..] {
+        level.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+    }
+    // written by a flush or compaction that crashed before recording it
+    for path in tables.values() {
+        warn!(table = %path.display(), "removing table missing from the manifest");
+        std::fs::remove_file(path)?;
+    }
+    manifest::write(&dir, &compaction::ids(&levels))?;
+
+    logs.sort();
+    let mut memtable = Memtable::default();
+    for (_, log) in &logs {
+        wal::replay(log, &mut memtable)?;
+    }
+    let wal = Wal::open(&wal_path(&dir, next_id))?;
+    let vlog = ValueLog::open(&dir, &values, options.value_log_file_bytes)?;
+    info!(tables = levels.iter().map(Vec::len).sum::<usize>(), logs = logs.len(), "opened sstables");
+
+    let shared = Arc::new(Shared {
+        dir,
+        options,
+        state: RwLock::new(State {
+            memtable,
+            wal,
+            wal_id: next_id,
+            replayed: logs.into_iter().map(|(_, path)| path).collect(),
+            levels,
+            vlog,
+        }),
+        cache,
+        next_id: AtomicU64::new(next_id + 1),
+        compacting: Mutex::new(vec![Vec::new(); LEVELS]),
+        compactions: AtomicU64::new(0),
+        collecting: Mutex::new(()),
+        // whatever was already due when the database was closed
+        wake: Mutex::new(Wake { pending: true, closing: false }),
+        woken: Condvar::new(),
+        filter_negatives: AtomicU64::new(0),
+        filter_positives: AtomicU64::new(0),
+        filter_false_positives: AtomicU64::new(0),
+    });
+    let background = shared.clone();
+    let compactor = thread::Builder::new()
+        .name("sstables-compaction".to_string())
+        .spawn(move || compaction::background(&background))?;
+    Ok(Db {
+        shared,
+        compactor: Some(compactor),
+    })
+}
+
+impl Db {
+    pub fn dir(&self) -> &Path {
+        &self.shared.dir
+    }
+
+    // None when the key was never written or has been deleted.
+    pub fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let state = self.shared.state.read().unwrap();
+        match self.shared.lookup(&state, key)? {
+            Some(value) => Ok(Some(resolve(&state, key, value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put(&self, key: &str, value: &str) -> Result<(), Error> {
+        check_record(key, value)?;
+        self.shared.write(&[(key, Some(value))])
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        check_record(key, "")?;
+        self.shared.write(&[(key, None)])
+    }
+
+    // Applies every put (Some) and delete (None) in batch, or none of them if
+    // any record is turned away. The batch is one log record, so a crash
+    // can't leave half of it behind either.
+    pub fn write_batch(&self, batch: &[(String, Option<String>)]) -> Result<(), Error> {
+        let mut entries = Vec::with_capacity(batch.len());
+        for (key, value) in batch {
+            check_record(key, value.as_deref().unwrap_or(""))?;
+            entries.push((key.as_str(), value.as_deref()));
+        }
+        self.shared.write(&entries)
+    }
+
+    // Every live record, in key order. Replays every table, oldest first, so
+    // newer writes win.
+    pub fn scan(&self) -> Result<Vec<(String, String)>, Error> {
+        let state = self.shared.state.read().unwrap();
+        let mut records = BTreeMap::new();
+        for level in state.levels[1..].iter().rev() {
+            for table in level {
+                records.extend(table.entries()?);
+            }
+        }
+        for table in state.levels[0].iter().rev() {
+            records.extend(table.entries()?);
+        }
+        records.extend(state.memtable.iter().map(|(k, v)| (k.clone(), v.clone().map(Value::Inline))));
+        // only the newest value for each key is looked up in the value log,
+        // older ones may be in files since collected
+        let mut live = Vec::new();
+        for (key, value) in records {
+            if let Some(value) = value {
+                let value = resolve(&state, &key, value)?;
+                live.push((key, value));
+            }
+        }
+        Ok(live)
+    }
+
+    // Makes every write so far durable.
+    pub fn flush(&self) -> Result<(), Error> {
+        let state = self.shared.state.read().unwrap();
+        Ok(state.wal.sync()?)
+    }
+
+    // Runs every compaction that is due now rather than waiting for the
+    // background thread to get to them.
+    pub fn compact(&self) -> Result<(), Error> {
+        while compaction::run(&self.shared)? {}
+        Ok(())
+    }
+
+    // Collects the oldest value log file, moving the values in it that are
+    // still live to the newest. Returns the bytes freed, 0 when there was no
+    // file but the one being appended to.
+    pub fn collect_value_log_garbage(&self) -> Result<u64, Error> {
+        vlog::collect_garbage(&self.shared)
+    }
+
+    pub fn stats(&self) -> Result<Stats, Error> {
+        let keys = self.scan()?.len();
+        let shared = &self.shared;
+        let state = shared.state.read().unwrap();
+        let levels = state
+            .levels
+            .iter()
+            .map(|level| LevelStats {
+                tables: level.len(),
+                bytes: level.iter().map(|t| t.size).sum(),
+            })
+            .collect::<Vec<LevelStats>>();
+        let mut disk_bytes = levels.iter().map(|l| l.bytes).sum::<u64>();
+        for log in state.replayed.iter().map(PathBuf::as_path).chain(Some(state.wal.path())) {
+            disk_bytes += std::fs::metadata(log).map(|m| m.len()).unwrap_or(0);
+        }
+        Ok(Stats {
+            keys,
+            tables: levels.iter().map(|l| l.tables).sum(),
+            memtable_bytes: state.memtable.bytes(),
+            disk_bytes,
+            levels,
+            compactions: shared.compactions.load(Ordering::Relaxed),
+            filters: FilterStats {
+                negatives: shared.filter_negatives.load(Ordering::Relaxed),
+                positives: shared.filter_positives.load(Ordering::Relaxed),
+                false_positives: shared.filter_false_positives.load(Ordering::Relaxed),
+            },
+            cache: shared.cache.stats(),
+            value_log: state.vlog.stats(),
+        })
+    }
+
+    // Flushes and lets go of the database.
+    pub fn close(self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+// Waits for a compaction in progress to finish, then stops the thread.
+impl Drop for Db {
+    fn drop(&mut self) {
+        self.shared.wake.lock().unwrap().closing = true;
+        self.shared.woken.notify_all();
+        if let Some(compactor) = self.compactor.take() {
+            let _ = compactor.join();
+        }
+    }
+}
+
+impl Shared {
+    // What the newest write to key left, with values in the value log not
+    // yet read from it. None when the key was never written or has been
+    // deleted.
+    fn lookup(&self, state: &State, key: &str) -> Result<Option<Value>, Error> {
+        if let Some(value) = state.memtable.get(key) {
+            return Ok(value.map(Value::Inline));
+        }
+        // every L0 table may hold the key, but only one table in each level
+        // after that can
+        let deeper = state.levels[1..].iter().filter_map(|level| compaction::find(level, key.as_bytes()));
+        for table in state.levels[0].iter().chain(deeper) {
+            if let Some(value) = self.table_get(table, key)? {
+                return Ok(value);
+            }
+        }
+        Ok(None)
+    }
+
+    // What table holds for key, counting what its filter did.
+    fn table_get(&self, table: &Table, key: &str) -> Result<Option<Option<Value>>, Error> {
+        if !table.may_contain(key) {
+            self.filter_negatives.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        }
+        let found = table.get(key)?;
+        if table.has_filter() {
+            let counter = if found.is_some() { &self.filter_positives } else { &self.filter_false_positives };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(found)
+    }
+
+    fn write(&self, entries: &[(&str, Option<&str>)]) -> Result<(), Error> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut state = self.state.write().unwrap();
+        self.write_locked(&mut state, entries)
+    }
+
+    fn write_locked(&self, state: &mut State, entries: &[(&str, Option<&str>)]) -> Result<(), Error> {
+        state.wal.append(entries, self.options.sync_writes)?;
+        for (key, value) in entries {
+            state.memtable.insert(key, *value);
+        }
+        if state.memtable.bytes() >= self.options.memtable_bytes {
+            self.flush_memtable(state)?;
+        }
+        Ok(())
+    }
+
+    // Writes the memtable out as the newest L0 table, then starts a new one
+    // with a log of its own. Values big enough go to the value log first. The
+    // old logs go once the table is recorded in the manifest.
+    fn flush_memtable(&self, state: &mut State) -> Result<(), Error> {
+        if state.memtable.is_empty() {
+            return Ok(());
+        }
+        let id = state.wal_id;
+        let path = table_path(&self.dir, id);
+        let threshold = self.options.value_threshold;
+        let mut entries = Vec::new();
+        for (key, value) in state.memtable.iter() {
+            let value = match value {
+                Some(value) if threshold > 0 && value.len() >= threshold => {
+                    if state.vlog.needs_head() {
+                        state.vlog.start_head(self.next_id.fetch_add(1, Ordering::Relaxed))?;
+                    }
+                    Some(Value::Pointer(state.vlog.append(key, value)?))
+                }
+                value => value.clone().map(Value::Inline),
+            };
+            entries.push((key.clone(), value));
+        }
+        state.vlog.sync()?;
+        sstable::write(&path, entries.iter().map(|(k, v)| (k, v)), self.options.bloom_bits_per_key)?;
+        state.levels[0].insert(0, Arc::new(Table::open(id, path, self.cache.clone())?));
+        manifest::write(&self.dir, &compaction::ids(&state.levels))?;
+        info!(id, bytes = state.memtable.bytes(), "flushed memtable");
+
+        state.wal_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let old = std::mem::replace(&mut state.wal, Wal::open(&wal_path(&self.dir, state.wal_id))?);
+        state.memtable = Memtable::default();
+        for log in state.replayed.drain(..).chain(Some(old.path().to_path_buf())) {
+            std::fs::remove_file(log)?;
+        }
+
+        self.wake.lock().unwrap().pending = true;
+        self.woken.notify_all();
+        Ok(())
+    }
+}
+
+// The value itself, read from the value log if that is where it is.
+fn resolve(state: &State, key: &str, value: Value) -> Result<String, Error> {
+    match value {
+        Value::Inline(value) => Ok(value),
+        Value::Pointer(pointer) => Ok(state.vlog.read(key, pointer)?),
+    }
+}
+
+fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.sst", id))
+}
+
+fn wal_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.wal", id))
+}
+
+// Keys are what tables are sorted and searched by, so an empty one is turned
+// away. Anything else can be stored as given.
+fn check_record(key: &str, _value: &str) -> Result<(), Error> {
+    if key.is_empty() {
+        return Err(Error::Invalid("key can't be empty".to_string()));
+    }
+    Ok(())
+}
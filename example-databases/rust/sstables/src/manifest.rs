@@ -0,0 +1,58 @@
+// Which tables make up the database and the level each is in, rewritten
+// whole after every flush and compaction. A table on disk that the manifest
+// doesn't list was left by a flush or compaction that crashed before it was
+// recorded, and is removed on open.
+//
+// MANIFEST: "null manifest 1" line, then one "<level> <table id>" line per
+// table, L0 newest first
+use crate::encoding::invalid;
+use crate::sstable::sync_dir;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter, ErrorKind};
+use std::path::Path;
+
+const NAME: &str = "MANIFEST";
+const HEADER: &str = "null manifest 1";
+
+// The table ids in each level, or None if the database has no manifest yet.
+pub fn read(dir: &Path) -> io::Result<Option<Vec<Vec<u64>>>> {
+    let text = match std::fs::read_to_string(dir.join(NAME)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(invalid("manifest has an unknown header".to_string()));
+    }
+    let mut levels: Vec<Vec<u64>> = Vec::new();
+    for line in lines {
+        let parsed = line.split_once(' ').and_then(|(level, id)| Some((level.parse::<usize>().ok()?, id.parse::<u64>().ok()?)));
+        let (level, id) = parsed.ok_or_else(|| invalid(format!("manifest line {:?} is malformed", line)))?;
+        if levels.len() <= level {
+            levels.resize(level + 1, Vec::new());
+        }
+        levels[level].push(id);
+    }
+    Ok(Some(levels))
+}
+
+// Replaces the manifest with one listing levels. The new one is written under
+// a temporary name and renamed over the old, so there is always a whole one.
+pub fn write(dir: &Path, levels: &[Vec<u64>]) -> io::Result<()> {
+    let path = dir.join(NAME);
+    let tmp = path.with_extension("tmp");
+    {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        writeln!(out, "{}", HEADER)?;
+        for (level, ids) in levels.iter().enumerate() {
+            for id in ids {
+                writeln!(out, "{} {}", level, id)?;
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    std::fs::rename(&tmp, &path)?;
+    sync_dir(&path)
+}
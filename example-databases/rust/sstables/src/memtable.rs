@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+
+// The newest writes, kept sorted in memory until there are enough of them to
+// flush to a table. A value of None is a delete, which has to be kept so it
+// hides the key in older tables.
+#[derive(Default)]
+pub struct Memtable {
+    map: BTreeMap<String, Option<String>>,
+    // roughly what the entries would take on disk
+    bytes: usize,
+}
+
+impl Memtable {
+    pub fn insert(&mut self, key: &str, value: Option<&str>) {
+        self.bytes += key.len() + value.map_or(0, |v| v.len());
+        self.map.insert(key.to_string(), value.map(String::from));
+    }
+
+    // None when the memtable knows nothing of key, Some(None) when it holds
+    // the delete.
+    pub fn get(&self, key: &str) -> Option<Option<String>> {
+        self.map.get(key).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Option<String>)> {
+        self.map.iter()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
@@ -0,0 +1,280 @@
+// An immutable file of entries sorted by key, written once when a memtable is
+// flushed and only ever read after that. An entry holds a value, a pointer to
+// a value in the value log (see vlog.rs), or a delete.
+//
+// file:   data block | data block | ... | index block | filter block | first key block
+//         | footer
+// footer: index offset u64 | index length u32 | filter offset u64 | filter length u32
+//         | first key offset u64 | first key length u32 | crc32 of the footer so far u32
+//         | magic u64
+//
+// Data blocks hold the entries, cut at about BLOCK_BYTES each (see block.rs).
+// The index block holds one entry per data block, keyed by the block's last
+// key, whose value is the block's offset u64 and length u32. The filter block
+// is a Bloom filter over every key (see bloom.rs), empty when filters are
+// turned off. The first key block is the table's smallest key, which with the
+// last key in the index gives the keys the table spans. Every block is followed
+// by the crc32 of its bytes, so a lookup reads the footer, index, filter and
+// first key once, at open, then at most one block.
+use crate::block::{Block, BlockBuilder};
+use crate::bloom::{self, Bloom};
+use crate::encoding::{invalid, put_u32, put_u64, u32_at, u64_at, DELETE, POINTER, PUT};
+use crate::vlog::Pointer;
+use null_common::cache::{self, BlockCache};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufWriter};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// "nullsst" and a format version
+const MAGIC: u64 = 0x6e75_6c6c_7373_7403;
+const FOOTER_BYTES: usize = 48;
+// data blocks are cut once they grow past this
+pub const BLOCK_BYTES: usize = 4 * 1024;
+
+// What a table holds for a key that wasn't deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Inline(String),
+    // kept in the value log
+    Pointer(Pointer),
+}
+
+// a key and what the table holds for it, None once the key is deleted
+pub type TableEntry = (String, Option<Value>);
+
+// Where a block is in the file, not counting its crc32.
+#[derive(Clone, Copy)]
+struct Handle {
+    offset: u64,
+    len: u32,
+}
+
+pub struct Table {
+    pub id: u64,
+    pub path: PathBuf,
+    pub size: u64,
+    // the first and last keys in the table
+    pub smallest: Vec<u8>,
+    pub largest: Vec<u8>,
+    file: File,
+    // what the block cache knows this file by
+    file_id: u64,
+    cache: Arc<BlockCache<Block>>,
+    // the last key of each data block and where the block is, in key order
+    index: Vec<(Vec<u8>, Handle)>,
+    filter: Option<Bloom>,
+}
+
+// Writes entries, which must come in key order, to a new table at path. The
+// table is written under a temporary name and renamed into place once it is
+// on disk, so a crash never leaves half a table behind. A bits_per_key of 0
+// leaves out the filter.
+pub fn write<'a, I>(path: &Path, entries: I, bits_per_key: usize) -> io::Result<()>
+where
+    I: Iterator<Item = (&'a String, &'a Option<Value>)>,
+{
+    let tmp = path.with_extension("tmp");
+    {
+        let mut out = Writer { out: BufWriter::new(File::create(&tmp)?), offset: 0 };
+        let mut block = BlockBuilder::default();
+        let mut index = BlockBuilder::default();
+        let mut hashes = Vec::new();
+        let mut first_key = None;
+        for (key, value) in entries {
+            first_key.get_or_insert_with(|| key.clone());
+            if bits_per_key > 0 {
+                hashes.push(bloom::hash(key.as_bytes()));
+            }
+            match value {
+                Some(Value::Inline(value)) => block.add(key.as_bytes(), PUT, value.as_bytes()),
+                Some(Value::Pointer(pointer)) => block.add(key.as_bytes(), POINTER, &pointer.encode()),
+                None => block.add(key.as_bytes(), DELETE, b""),
+            }
+            if block.len() >= BLOCK_BYTES {
+                out.finish_block(&mut block, &mut index)?;
+            }
+        }
+        if !block.is_empty() {
+            out.finish_block(&mut block, &mut index)?;
+        }
+
+        let index = out.write_block(&index.finish())?;
+        let filter = if bits_per_key > 0 { bloom::build(&hashes, bits_per_key) } else { Vec::new() };
+        let filter = out.write_block(&filter)?;
+        let first_key = out.write_block(first_key.unwrap_or_default().as_bytes())?;
+        let mut footer = Vec::with_capacity(FOOTER_BYTES);
+        put_u64(&mut footer, index.offset);
+        put_u32(&mut footer, index.len);
+        put_u64(&mut footer, filter.offset);
+        put_u32(&mut footer, filter.len);
+        put_u64(&mut footer, first_key.offset);
+        put_u32(&mut footer, first_key.len);
+        let crc = crc32fast::hash(&footer);
+        put_u32(&mut footer, crc);
+        put_u64(&mut footer, MAGIC);
+        out.out.write_all(&footer)?;
+        out.out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    std::fs::rename(&tmp, path)?;
+    sync_dir(path)
+}
+
+struct Writer {
+    out: BufWriter<File>,
+    offset: u64,
+}
+
+impl Writer {
+    fn write_block(&mut self, block: &[u8]) -> io::Result<Handle> {
+        let handle = Handle { offset: self.offset, len: block.len() as u32 };
+        self.out.write_all(block)?;
+        self.out.write_all(&crc32fast::hash(block).to_le_bytes())?;
+        self.offset += block.len() as u64 + 4;
+        Ok(handle)
+    }
+
+    // Writes out a data block and adds it to the index.
+    fn finish_block(&mut self, block: &mut BlockBuilder, index: &mut BlockBuilder) -> io::Result<()> {
+        let last_key = block.last_key().to_vec();
+        let handle = self.write_block(&block.finish())?;
+        let mut value = Vec::with_capacity(12);
+        put_u64(&mut value, handle.offset);
+        put_u32(&mut value, handle.len);
+        index.add(&last_key, PUT, &value);
+        Ok(())
+    }
+}
+
+impl Table {
+    // Checks the footer and reads the index, filter and first key into memory.
+    // Data blocks are read through cache.
+    pub fn open(id: u64, path: PathBuf, cache: Arc<BlockCache<Block>>) -> io::Result<Self> {
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        if size < FOOTER_BYTES as u64 {
+            return Err(invalid(format!("{} is too short to be a table", path.display())));
+        }
+        let mut footer = [0; FOOTER_BYTES];
+        file.read_exact_at(&mut footer, size - FOOTER_BYTES as u64)?;
+        if u64_at(&footer, 40)? != MAGIC {
+            return Err(invalid(format!("{} is not a table", path.display())));
+        }
+        if u32_at(&footer, 36)? != crc32fast::hash(&footer[..36]) {
+            return Err(invalid(format!("{} has a corrupt footer", path.display())));
+        }
+        let index = Handle { offset: u64_at(&footer, 0)?, len: u32_at(&footer, 8)? };
+        let filter = Handle { offset: u64_at(&footer, 12)?, len: u32_at(&footer, 20)? };
+        let first_key = Handle { offset: u64_at(&footer, 24)?, len: u32_at(&footer, 32)? };
+
+        let mut table = Table {
+            id,
+            path,
+            size,
+            smallest: Vec::new(),
+            largest: Vec::new(),
+            file,
+            file_id: cache::next_file_id(),
+            cache,
+            index: Vec::new(),
+            filter: None,
+        };
+        for (last_key, _, value) in Block::new(table.read_raw(index)?)?.entries()? {
+            let handle = Handle { offset: u64_at(&value, 0)?, len: u32_at(&value, 8)? };
+            table.index.push((last_key, handle));
+        }
+        if filter.len > 0 {
+            table.filter = Some(Bloom::new(table.read_raw(filter)?)?);
+        }
+        table.largest = match table.index.last() {
+            Some((last_key, _)) => last_key.clone(),
+            None => return Err(invalid(format!("{} holds no entries", table.path.display()))),
+        };
+        table.smallest = table.read_raw(first_key)?;
+        Ok(table)
+    }
+
+    pub fn has_filter(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    // False when the filter rules key out, so there is no need to get it.
+    pub fn may_contain(&self, key: &str) -> bool {
+        match &self.filter {
+            Some(filter) => filter.may_contain(bloom::hash(key.as_bytes())),
+            None => true,
+        }
+    }
+
+    // None when the table holds nothing for key, Some(None) when it holds
+    // the delete.
+    pub fn get(&self, key: &str) -> io::Result<Option<Option<Value>>> {
+        // the first block whose last key isn't before key is the only one
+        // that can hold it
+        let i = self.index.partition_point(|(last_key, _)| last_key.as_slice() < key.as_bytes());
+        let handle = match self.index.get(i) {
+            Some((_, handle)) => *handle,
+            None => return Ok(None),
+        };
+        match self.read_block(handle)?.get(key.as_bytes())? {
+            Some((kind, value)) => Ok(Some(decode_value(kind, value)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Every entry, deletes included, in key order. Blocks are read around the
+    // cache, so a scan doesn't push out the blocks lookups keep coming back to.
+    pub fn entries(&self) -> io::Result<Vec<TableEntry>> {
+        let mut entries = Vec::new();
+        for (_, handle) in &self.index {
+            for (key, kind, value) in Block::new(self.read_raw(*handle)?)?.entries()? {
+                let key = String::from_utf8(key).map_err(|_| invalid("key is not utf-8".to_string()))?;
+                entries.push((key, decode_value(kind, value)?));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_block(&self, handle: Handle) -> io::Result<Arc<Block>> {
+        if let Some(block) = self.cache.get(self.file_id, handle.offset) {
+            return Ok(block);
+        }
+        let data = self.read_raw(handle)?;
+        let charge = data.len();
+        Ok(self.cache.insert(self.file_id, handle.offset, Block::new(data)?, charge))
+    }
+
+    // The bytes of the block at handle, once they pass their checksum.
+    fn read_raw(&self, handle: Handle) -> io::Result<Vec<u8>> {
+        if handle.offset.saturating_add(handle.len as u64 + 4) > self.size {
+            return Err(invalid(format!("block at {} runs past the end of {}", handle.offset, self.path.display())));
+        }
+        let mut data = vec![0; handle.len as usize + 4];
+        self.file.read_exact_at(&mut data, handle.offset)?;
+        let crc = u32_at(&data, handle.len as usize)?;
+        data.truncate(handle.len as usize);
+        if crc32fast::hash(&data) != crc {
+            return Err(invalid(format!("block at {} in {} fails its checksum", handle.offset, self.path.display())));
+        }
+        Ok(data)
+    }
+}
+
+fn decode_value(kind: u8, value: Vec<u8>) -> io::Result<Option<Value>> {
+    match kind {
+        PUT => String::from_utf8(value)
+            .map(|value| Some(Value::Inline(value)))
+            .map_err(|_| invalid("value is not utf-8".to_string())),
+        POINTER => Ok(Some(Value::Pointer(Pointer::decode(&value)?))),
+        DELETE => Ok(None),
+        other => Err(invalid(format!("unknown entry kind {}", other))),
+    }
+}
+
+// Makes a rename into the directory holding path durable.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
@@ -0,0 +1,272 @@
+// The value log, for key-value separation as in WiscKey. Values of at least
+// Options::value_threshold bytes are appended here when the memtable is
+// flushed, and the table gets a pointer to the value instead, so compaction
+// moves a 20 byte pointer around rather than rewriting the value every time.
+//
+// Overwriting or deleting a key leaves its old value behind as garbage. The
+// garbage collector takes the oldest file, puts every value in it that a
+// pointer still leads to back through the write path, which appends it to the
+// newest file at the next flush, then deletes the file.
+//
+// file:    record | record | ...
+// record:  crc32 of the rest u32 | key length u32 | value length u32 | key | value
+// pointer: file id u64 | record offset u64 | record length u32
+use crate::encoding::{invalid, put_u32, put_u64, u32_at, u64_at};
+use crate::sstable::{sync_dir, Value};
+use crate::{Error, Shared};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufReader, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+
+const RECORD_HEADER_BYTES: usize = 12;
+pub const POINTER_BYTES: usize = 20;
+
+// Where a value is in the value log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pointer {
+    pub file: u64,
+    pub offset: u64,
+    pub len: u32,
+}
+
+impl Pointer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(POINTER_BYTES);
+        put_u64(&mut buf, self.file);
+        put_u64(&mut buf, self.offset);
+        put_u32(&mut buf, self.len);
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        if data.len() != POINTER_BYTES {
+            return Err(invalid(format!("value pointer is {} bytes", data.len())));
+        }
+        Ok(Pointer { file: u64_at(data, 0)?, offset: u64_at(data, 8)?, len: u32_at(data, 16)? })
+    }
+}
+
+pub struct ValueLog {
+    dir: PathBuf,
+    // every file by id, oldest first, the head included
+    files: BTreeMap<u64, Arc<File>>,
+    // the file being appended to, which a run of the program starts afresh,
+    // so nothing is ever appended after a record a crash tore
+    head: Option<Head>,
+    // the head is closed once it grows past this
+    file_bytes: u64,
+}
+
+struct Head {
+    id: u64,
+    file: Arc<File>,
+    // where the next record goes, counting those not yet written
+    offset: u64,
+    // records appended since the last sync
+    pending: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct ValueLogStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+pub fn path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.vlog", id))
+}
+
+impl ValueLog {
+    // Opens the files with the given ids for reading.
+    pub fn open(dir: &Path, ids: &[u64], file_bytes: u64) -> io::Result<Self> {
+        let mut files = BTreeMap::new();
+        for id in ids {
+            files.insert(*id, Arc::new(File::open(path(dir, *id))?));
+        }
+        Ok(ValueLog {
+            dir: dir.to_path_buf(),
+            files,
+            head: None,
+            file_bytes,
+        })
+    }
+
+    // Whether the head has to be started, or closed and a new one started,
+    // before the next append.
+    pub fn needs_head(&self) -> bool {
+        self.head.as_ref().is_none_or(|head| head.offset >= self.file_bytes)
+    }
+
+    // Closes the head, if there is one, and starts a new one as file id.
+    pub fn start_head(&mut self, id: u64) -> io::Result<()> {
+        self.sync()?;
+        let path = path(&self.dir, id);
+        let file = Arc::new(OpenOptions::new().read(true).append(true).create_new(true).open(&path)?);
+        sync_dir(&path)?;
+        self.files.insert(id, file.clone());
+        self.head = Some(Head { id, file, offset: 0, pending: Vec::new() });
+        Ok(())
+    }
+
+    // Appends key and value to the head, which there must be. The record
+    // only reaches the file on the next sync.
+    pub fn append(&mut self, key: &str, value: &str) -> io::Result<Pointer> {
+        let head = match &mut self.head {
+            Some(head) => head,
+            None => return Err(io::Error::other("the value log has no head")),
+        };
+        let mut rest = Vec::with_capacity(RECORD_HEADER_BYTES - 4 + key.len() + value.len());
+        put_u32(&mut rest, key.len() as u32);
+        put_u32(&mut rest, value.len() as u32);
+        rest.extend_from_slice(key.as_bytes());
+        rest.extend_from_slice(value.as_bytes());
+        put_u32(&mut head.pending, crc32fast::hash(&rest));
+        head.pending.extend_from_slice(&rest);
+
+        let pointer = Pointer { file: head.id, offset: head.offset, len: rest.len() as u32 + 4 };
+        head.offset += pointer.len as u64;
+        Ok(pointer)
+    }
+
+    // Writes out and fsyncs what has been appended, which has to be done
+    // before any table points at it.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(head) = &mut self.head {
+            if !head.pending.is_empty() {
+                (&*head.file).write_all(&head.pending)?;
+                head.file.sync_data()?;
+                head.pending.clear();
+            }
+        }
+        Ok(())
+    }
+
+    // The value pointer leads to, checking it is key's.
+    pub fn read(&self, key: &str, pointer: Pointer) -> io::Result<String> {
+        let file = match self.files.get(&pointer.file) {
+            Some(file) => file,
+            None => return Err(invalid(format!("value log file {} is missing", pointer.file))),
+        };
+        let mut record = vec![0; pointer.len as usize];
+        file.read_exact_at(&mut record, pointer.offset)?;
+        match decode(&record)? {
+            (found, value) if found == key => Ok(value),
+            _ => Err(invalid(format!("value log record at {} in file {} isn't for {}", pointer.offset, pointer.file, key))),
+        }
+    }
+
+    // The oldest file, unless that is the head.
+    fn oldest(&self) -> Option<u64> {
+        let head = self.head.as_ref().map(|head| head.id);
+        self.files.keys().next().copied().filter(|id| Some(*id) != head)
+    }
+
+    fn remove(&mut self, id: u64) -> io::Result<()> {
+        self.files.remove(&id);
+        std::fs::remove_file(path(&self.dir, id))?;
+        sync_dir(&path(&self.dir, id))
+    }
+
+    pub fn stats(&self) -> ValueLogStats {
+        let bytes = self.files.values().map(|f| f.metadata().map(|m| m.len()).unwrap_or(0)).sum::<u64>();
+        ValueLogStats {
+            files: self.files.len(),
+            bytes: bytes + self.head.as_ref().map_or(0, |h| h.pending.len() as u64),
+        }
+    }
+}
+
+// A key and value from a whole record.
+fn decode(record: &[u8]) -> io::Result<(String, String)> {
+    let crc = u32_at(record, 0)?;
+    if record.len() < RECORD_HEADER_BYTES || crc32fast::hash(&record[4..]) != crc {
+        return Err(invalid("value log record fails its checksum".to_string()));
+    }
+    let key_len = u32_at(record, 4)? as usize;
+    let value_len = u32_at(record, 8)? as usize;
+    if RECORD_HEADER_BYTES + key_len + value_len != record.len() {
+        return Err(invalid("value log record has the wrong length".to_string()));
+    }
+    let key = &record[RECORD_HEADER_BYTES..RECORD_HEADER_BYTES + key_len];
+    let value = &record[RECORD_HEADER_BYTES + key_len..];
+    match (String::from_utf8(key.to_vec()), String::from_utf8(value.to_vec())) {
+        (Ok(key), Ok(value)) => Ok((key, value)),
+        _ => Err(invalid("value log record is not utf-8".to_string())),
+    }
+}
+
+// Every record in a file that isn't the head, with where it is. A record cut
+// short at the end was being written by a flush that crashed, and no table
+// points at it; any other bad record is an error.
+fn records(path: &Path) -> io::Result<Vec<(Pointer, String, String)>> {
+    let id = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok()).unwrap_or(0);
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut records = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut header = [0; RECORD_HEADER_BYTES];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let len = RECORD_HEADER_BYTES + u32_at(&header, 4)? as usize + u32_at(&header, 8)? as usize;
+        if offset + len as u64 > size {
+            break;
+        }
+        let mut record = header.to_vec();
+        record.resize(len, 0);
+        match reader.read_exact(&mut record[RECORD_HEADER_BYTES..]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let (key, value) = decode(&record)?;
+        records.push((Pointer { file: id, offset, len: len as u32 }, key, value));
+        offset += len as u64;
+    }
+    Ok(records)
+}
+
+// Collects the oldest file of the value log, unless it is the head: every
+// value in it that is still the newest for its key is written again, which
+// moves it to the head at the next flush, and the file is deleted. Returns
+// the bytes freed.
+pub fn collect_garbage(shared: &Shared) -> Result<u64, Error> {
+    let _collecting = shared.collecting.lock().unwrap();
+    let id = match shared.state.read().unwrap().vlog.oldest() {
+        Some(id) => id,
+        None => return Ok(0),
+    };
+    let path = path(&shared.dir, id);
+    let size = std::fs::metadata(&path)?.len();
+
+    let mut live = 0;
+    let mut live_bytes = 0;
+    let records = records(&path)?;
+    for (pointer, key, value) in &records {
+        let mut state = shared.state.write().unwrap();
+        // a write since the pointer was read would have replaced it, and
+        // checking under the write lock keeps one from slipping in between
+        if shared.lookup(&state, key)? == Some(Value::Pointer(*pointer)) {
+            shared.write_locked(&mut state, &[(key, Some(value))])?;
+            live += 1;
+            live_bytes += pointer.len as u64;
+        }
+    }
+
+    // the values moved are only safe to lose from here once the
+    // write-ahead log has them on disk
+    let mut state = shared.state.write().unwrap();
+    state.wal.sync()?;
+    state.vlog.remove(id)?;
+    info!(file = id, records = records.len(), live, live_bytes, "collected value log garbage");
+    Ok(size.saturating_sub(live_bytes))
+}
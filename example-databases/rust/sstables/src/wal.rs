@@ -0,0 +1,109 @@
+// Every write lands here before the memtable, so the memtable can be rebuilt
+// after a crash. Each record is a crc32 and a length, then the entries of one
+// write, so a batch is replayed whole or not at all.
+use crate::encoding::{decode_entry, encode_entry, put_u32, read_u32, Entry};
+use crate::memtable::Memtable;
+use crate::Error;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// the biggest record append takes, so a length past it means corruption
+const MAX_RECORD_BYTES: usize = 64 * 1024 * 1024;
+
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Wal {
+            path: path.to_path_buf(),
+            file: OpenOptions::new().create(true).append(true).open(path)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Turns away a write bigger than replay would take back, rather than
+    // losing it and everything after it on the next open.
+    pub fn append(&mut self, entries: &[(&str, Option<&str>)], sync: bool) -> Result<(), Error> {
+        let mut entry = Vec::new();
+        for (key, value) in entries {
+            encode_entry(&mut entry, key, *value);
+        }
+        if entry.len() > MAX_RECORD_BYTES {
+            return Err(Error::Invalid(format!(
+                "write of {} bytes is over the write-ahead log's limit of {}",
+                entry.len(),
+                MAX_RECORD_BYTES
+            )));
+        }
+        let mut record = Vec::with_capacity(entry.len() + 8);
+        put_u32(&mut record, crc32fast::hash(&entry));
+        put_u32(&mut record, entry.len() as u32);
+        record.extend_from_slice(&entry);
+        // one write call, so a crash leaves at most one torn record at the end
+        self.file.write_all(&record)?;
+        if sync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+// Replays the log at path into memtable. A torn or corrupt record can only be
+// the last one written before a crash, so the log is cut off there rather
+// than failing the open.
+pub fn replay(path: &Path, memtable: &mut Memtable) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut good = 0;
+    loop {
+        match read_record(&mut reader) {
+            Ok(Some(entries)) => {
+                for (key, value) in entries {
+                    memtable.insert(&key, value.as_deref());
+                }
+                good = reader.stream_position()?;
+            }
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::InvalidData || e.kind() == ErrorKind::UnexpectedEof => {
+                warn!(wal = %path.display(), offset = good, error = %e, "cutting off torn write-ahead log");
+                OpenOptions::new().write(true).open(path)?.set_len(good)?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn read_record(reader: &mut impl BufRead) -> io::Result<Option<Vec<Entry>>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let crc = read_u32(reader)?;
+    let len = read_u32(reader)? as usize;
+    if len > MAX_RECORD_BYTES {
+        return Err(io::Error::new(ErrorKind::InvalidData, "record length out of range"));
+    }
+    let mut entry = vec![0; len];
+    reader.read_exact(&mut entry)?;
+    if crc32fast::hash(&entry) != crc {
+        return Err(io::Error::new(ErrorKind::InvalidData, "checksum mismatch"));
+    }
+    let mut rest = &entry[..];
+    let mut entries = Vec::new();
+    while !rest.is_empty() {
+        entries.push(decode_entry(&mut rest)?);
+    }
+    Ok(Some(entries))
+}
@@ -0,0 +1,471 @@
+use disk_sstables::{open, Error, Options};
+use std::io::Write;
+use tempfile::TempDir;
+
+// A fresh directory per test so they can run in parallel, removed once the
+// test is done with it.
+fn temp_dir(name: &str) -> TempDir {
+    tempfile::Builder::new().prefix(&format!("null-sstables-{}-", name)).tempdir().unwrap()
+}
+
+// Small enough that a few hundred writes flush several tables.
+fn small() -> Options {
+    Options { memtable_bytes: 256, ..Options::default() }
+}
+
+#[test]
+fn put_get_delete() {
+    let dir = temp_dir("put_get_delete");
+    let db = open(&dir, Options::default()).unwrap();
+
+    assert_eq!(db.get("foo").unwrap(), None);
+    db.put("foo", "bar").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("bar".to_string()));
+    db.put("foo", "baz").unwrap();
+    assert_eq!(db.get("foo").unwrap(), Some("baz".to_string()));
+    db.delete("foo").unwrap();
+    assert_eq!(db.get("foo").unwrap(), None);
+    assert!(matches!(db.put("", "b"), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn flushes_memtable_to_tables() {
+    let dir = temp_dir("flushes_memtable_to_tables");
+    let db = open(&dir, small()).unwrap();
+    for i in 0..300 {
+        db.put(&format!("k{:03}", i % 100), &format!("v{}", i)).unwrap();
+    }
+    // the delete has to hide the key in the tables under it
+    db.delete("k007").unwrap();
+
+    let stats = db.stats().unwrap();
+    assert!(stats.tables > 1);
+    assert!(stats.memtable_bytes < 256);
+    assert_eq!(stats.keys, 99);
+    assert_eq!(db.get("k042").unwrap(), Some("v242".to_string()));
+    assert_eq!(db.get("k007").unwrap(), None);
+    let records = db.scan().unwrap();
+    assert_eq!(records[0], ("k000".to_string(), "v200".to_string()));
+    assert_eq!(records.len(), 99);
+}
+
+#[test]
+fn reopen_replays_write_ahead_log() {
+    let dir = temp_dir("reopen_replays_write_ahead_log");
+    let db = open(&dir, small()).unwrap();
+    for i in 0..50 {
+        db.put(&format!("k{}", i), &format!("v{}", i)).unwrap();
+    }
+    db.delete("k3").unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, small()).unwrap();
+    assert!(db.stats().unwrap().memtable_bytes > 0);
+    assert_eq!(db.get("k49").unwrap(), Some("v49".to_string()));
+    assert_eq!(db.get("k0").unwrap(), Some("v0".to_string()));
+    assert_eq!(db.get("k3").unwrap(), None);
+    assert_eq!(db.scan().unwrap().len(), 49);
+}
+
+#[test]
+fn torn_log_tail_is_cut_off() {
+    let dir = temp_dir("torn_log_tail_is_cut_off");
+    let db = open(&dir, Options::default()).unwrap();
+    db.put("a", "1").unwrap();
+    db.put("b", "2").unwrap();
+    db.close().unwrap();
+
+    // half a record, as a crash part way through a write would leave
+    let log = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|e| e == "wal"))
+        .unwrap();
+    let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+    file.write_all(&[1, 2, 3, 4, 40, 0]).unwrap();
+
+    let db = open(&dir, Options::default()).unwrap();
+    assert_eq!(db.get("b").unwrap(), Some("2".to_string()));
+    db.put("c", "3").unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, Options::default()).unwrap();
+    assert_eq!(db.scan().unwrap().len(), 3);
+}
+
+#[test]
+fn writes_the_log_cant_replay_are_turned_away() {
+    let dir = temp_dir("writes_the_log_cant_replay_are_turned_away");
+    // nothing is flushed, so every write has to come back from the log
+    let options = || Options { memtable_bytes: 1 << 30, ..Options::default() };
+    let db = open(&dir, options()).unwrap();
+    let near = "x".repeat((64 << 20) - 1024);
+    db.put("near", &near).unwrap();
+    assert!(matches!(db.put("over", &"x".repeat(65 << 20)), Err(Error::Invalid(_))));
+    db.put("after", "y").unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, options()).unwrap();
+    assert_eq!(db.get("near").unwrap().map(|v| v.len()), Some(near.len()));
+    assert_eq!(db.get("over").unwrap(), None);
+    assert_eq!(db.get("after").unwrap(), Some("y".to_string()));
+}
+
+#[test]
+fn write_batch_is_all_or_nothing() {
+    let dir = temp_dir("write_batch_is_all_or_nothing");
+    let db = open(&dir, Options::default()).unwrap();
+    db.put("gone", "soon").unwrap();
+
+    let bad = vec![("a".to_string(), Some("1".to_string())), ("".to_string(), Some("2".to_string()))];
+    assert!(matches!(db.write_batch(&bad), Err(Error::Invalid(_))));
+    assert_eq!(db.get("a").unwrap(), None);
+
+    let good = vec![
+        ("a".to_string(), Some("1".to_string())),
+        ("b".to_string(), Some("2".to_string())),
+        ("gone".to_string(), None),
+    ];
+    db.write_batch(&good).unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, Options::default()).unwrap();
+    assert_eq!(db.scan().unwrap(), vec![("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())]);
+}
+
+#[test]
+fn lookups_across_many_blocks() {
+    let dir = temp_dir("lookups_across_many_blocks");
+    let db = open(&dir, Options { memtable_bytes: 1024 * 1024, ..Options::default() }).unwrap();
+    // long shared prefixes, a value bigger than a block, and deletes
+    for i in 0..5000 {
+        db.put(&format!("user/{:06}/name", i * 2), &format!("name {}", i)).unwrap();
+    }
+    db.put("user/000100/name", &"x".repeat(10_000)).unwrap();
+    db.delete("user/000200/name").unwrap();
+    db.close().unwrap();
+
+    let db = open(&dir, Options { memtable_bytes: 1, ..Options::default() }).unwrap();
+    // the next write flushes everything replayed from the log to one table
+    db.put("zzz", "last").unwrap();
+    assert_eq!(db.stats().unwrap().tables, 1);
+    for i in 0..5000 {
+        let expected = match i * 2 {
+            100 => Some("x".repeat(10_000)),
+            200 => None,
+            _ => Some(format!("name {}", i)),
+        };
+        assert_eq!(db.get(&format!("user/{:06}/name", i * 2)).unwrap(), expected);
+        // keys between two stored keys, before the first and after the last
+        assert_eq!(db.get(&format!("user/{:06}/name", i * 2 + 1)).unwrap(), None);
+    }
+    assert_eq!(db.get("a").unwrap(), None);
+    assert_eq!(db.get("user/").unwrap(), None);
+    assert_eq!(db.get("zzz").unwrap(), Some("last".to_string()));
+    assert_eq!(db.scan().unwrap().len(), 5000);
+}
+
+#[test]
+fn corrupt_blocks_are_reported() {
+    let dir = temp_dir("corrupt_blocks_are_reported");
+    let db = open(&dir, small()).unwrap();
+    for i in 0..20 {
+        db.put(&format!("k{:02}", i), "some value long enough to flush").unwrap();
+    }
+    db.close().unwrap();
+
+    let table = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "sst"))
+        .min()
+        .unwrap();
+    let mut bytes = std::fs::read(&table).unwrap();
+    bytes[20] ^= 0xff;
+    std::fs::write(&table, &bytes).unwrap();
+
+    let db = open(&dir, small()).unwrap();
+    assert!(matches!(db.get("k00"), Err(Error::Corrupt(_))));
+
+    // and a table whose footer is gone doesn't open at all
+    let len = bytes.len();
+    std::fs::write(&table, &bytes[..len - 8]).unwrap();
+    assert!(matches!(open(&dir, small()), Err(Error::Corrupt(_))));
+}
+
+#[test]
+fn filters_skip_tables_without_the_key() {
+    let dir = temp_dir("filters_skip_tables_without_the_key");
+    // every table stays in L0, so every lookup checks all of them
+    let options = Options { memtable_bytes: 1024, l0_compaction_trigger: usize::MAX, ..Options::default() };
+    let db = open(&dir, options).unwrap();
+    for i in 0..2000 {
+        db.put(&format!("k{}", i), "v").unwrap();
+    }
+    let tables = db.stats().unwrap().tables as u64;
+    assert!(tables > 5);
+
+    for i in 0..2000 {
+        assert_eq!(db.get(&format!("missing{}", i)).unwrap(), None);
+    }
+    let filters = db.stats().unwrap().filters;
+    let checked = 2000 * tables;
+    assert_eq!(filters.negatives + filters.false_positives, checked);
+    // 10 bits per key lets through about 1%
+    assert!(filters.false_positives < checked / 20, "{:?}", filters);
+
+    assert_eq!(db.get("k10").unwrap(), Some("v".to_string()));
+    assert!(db.stats().unwrap().filters.positives >= 1);
+}
+
+#[test]
+fn tables_without_filters_still_work() {
+    let dir = temp_dir("tables_without_filters_still_work");
+    let db = open(&dir, Options { memtable_bytes: 256, bloom_bits_per_key: 0, ..Options::default() }).unwrap();
+    for i in 0..100 {
+        db.put(&format!("k{}", i), "some value").unwrap();
+    }
+    assert_eq!(db.get("k5").unwrap(), Some("some value".to_string()));
+    assert_eq!(db.get("nope").unwrap(), None);
+    let filters = db.stats().unwrap().filters;
+    assert_eq!(filters.negatives + filters.positives + filters.false_positives, 0);
+}
+
+#[test]
+fn block_cache_serves_repeat_reads() {
+    let dir = temp_dir("block_cache_serves_repeat_reads");
+    let options = Options { memtable_bytes: 64 * 1024, block_cache_bytes: 16 * 1024, ..Options::default() };
+    let db = open(&dir, options).unwrap();
+    for i in 0..5000 {
+        db.put(&format!("k{:05}", i), "a value of some length").unwrap();
+    }
+
+    for _ in 0..10 {
+        assert_eq!(db.get("k00042").unwrap(), Some("a value of some length".to_string()));
+    }
+    let cache = db.stats().unwrap().cache;
+    assert_eq!(cache.misses, 1);
+    assert_eq!(cache.hits, 9);
+
+    // reading everything once can't grow the cache past its capacity
+    for i in 0..5000 {
+        assert!(db.get(&format!("k{:05}", i)).unwrap().is_some());
+    }
+    let cache = db.stats().unwrap().cache;
+    assert!(cache.bytes <= 16 * 1024 && cache.bytes > 0, "{:?}", cache);
+}
+
+// Small enough that a few thousand writes fill several levels.
+fn leveled() -> Options {
+    Options {
+        memtable_bytes: 1024,
+        l0_compaction_trigger: 2,
+        level1_bytes: 4 * 1024,
+        table_bytes: 1024,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn compaction_moves_tables_down_the_levels() {
+    let dir = temp_dir("compaction_moves_tables_down_the_levels");
+    let db = open(&dir, leveled()).unwrap();
+    for i in 0..3000 {
+        db.put(&format!("k{:04}", i % 1000), &format!("v{}", i)).unwrap();
+        if i % 7 == 0 {
+            db.delete(&format!("k{:04}", (i + 500) % 1000)).unwrap();
+        }
+    }
+    db.compact().unwrap();
+
+    let stats = db.stats().unwrap();
+    assert!(stats.compactions > 0);
+    assert!(stats.levels[0].tables < 2, "{:?}", stats.levels);
+    assert!(stats.levels[2].tables > 0, "{:?}", stats.levels);
+    let mut limit = 4 * 1024;
+    for level in &stats.levels[1..stats.levels.len() - 1] {
+        assert!(level.bytes <= limit, "{:?}", stats.levels);
+        limit *= 10;
+    }
+
+    let check = |db: &disk_sstables::Db| {
+        let mut expected = std::collections::BTreeMap::new();
+        for i in 0..3000 {
+            expected.insert(format!("k{:04}", i % 1000), format!("v{}", i));
+            if i % 7 == 0 {
+                expected.remove(&format!("k{:04}", (i + 500) % 1000));
+            }
+        }
+        for i in 0..1000 {
+            let key = format!("k{:04}", i);
+            assert_eq!(db.get(&key).unwrap(), expected.get(&key).cloned(), "{}", key);
+        }
+        assert_eq!(db.scan().unwrap(), expected.into_iter().collect::<Vec<(String, String)>>());
+    };
+    check(&db);
+    db.close().unwrap();
+
+    // the manifest puts every table back in its level
+    let db = open(&dir, leveled()).unwrap();
+    assert_eq!(db.stats().unwrap().levels[..3], stats.levels[..3]);
+    check(&db);
+}
+
+#[test]
+fn compaction_drops_deletes_with_nothing_under_them() {
+    let dir = temp_dir("compaction_drops_deletes_with_nothing_under_them");
+    let options = Options { level1_bytes: 1024 * 1024, ..leveled() };
+    let db = open(&dir, options).unwrap();
+    // 16 bytes a record, so every 64 fill the memtable
+    for i in 0..192 {
+        db.put(&format!("k{:03}", i), "twelve bytes").unwrap();
+    }
+    for i in 0..192 {
+        db.delete(&format!("k{:03}", i)).unwrap();
+    }
+    for i in 0..16 {
+        db.put(&format!("z{:03}", i), "twelve bytes").unwrap();
+    }
+    db.compact().unwrap();
+
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.memtable_bytes, 0);
+    assert_eq!(stats.keys, 16);
+    // L1 is the last level holding anything, so the deletes and the values
+    // they hide are both gone, leaving a table of just the 16 records
+    assert_eq!(stats.levels[0].tables, 0);
+    assert_eq!(stats.levels[1].tables, 1);
+    assert!(stats.levels[1].bytes < 1024, "{:?}", stats.levels);
+}
+
+#[test]
+fn background_compaction_keeps_l0_small() {
+    let dir = temp_dir("background_compaction_keeps_l0_small");
+    let db = open(&dir, leveled()).unwrap();
+    for i in 0..2000 {
+        db.put(&format!("k{:04}", i), "some value").unwrap();
+    }
+    let start = std::time::Instant::now();
+    while db.stats().unwrap().levels[0].tables >= 2 {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "L0 was never compacted");
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(db.stats().unwrap().compactions > 0);
+    assert_eq!(db.get("k1234").unwrap(), Some("some value".to_string()));
+}
+
+#[test]
+fn tables_missing_from_the_manifest_are_removed() {
+    let dir = temp_dir("tables_missing_from_the_manifest_are_removed");
+    let db = open(&dir, small()).unwrap();
+    for i in 0..50 {
+        db.put(&format!("k{}", i), "some value").unwrap();
+    }
+    db.close().unwrap();
+
+    // what a compaction that crashed before recording its output leaves
+    let orphan = dir.path().join(format!("{:020}.sst", 999));
+    std::fs::write(&orphan, b"half a table").unwrap();
+    let db = open(&dir, small()).unwrap();
+    assert!(!orphan.exists());
+    assert_eq!(db.get("k7").unwrap(), Some("some value".to_string()));
+    db.close().unwrap();
+
+    // but a table the manifest lists has to be there
+    let table = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|e| e == "sst"))
+        .unwrap();
+    std::fs::remove_file(table).unwrap();
+    assert!(matches!(open(&dir, small()), Err(Error::Corrupt(_))));
+}
+
+// Values of 100 bytes or more go to the value log, in files of about 4 KiB.
+fn separated() -> Options {
+    Options {
+        memtable_bytes: 2048,
+        value_threshold: 100,
+        value_log_file_bytes: 4096,
+        ..Options::default()
+    }
+}
+
+fn big_value(i: usize, version: usize) -> String {
+    format!("{:04}-{}-", i, version).repeat(20)
+}
+
+#[test]
+fn large_values_are_kept_in_the_value_log() {
+    let dir = temp_dir("large_values_are_kept_in_the_value_log");
+    let db = open(&dir, separated()).unwrap();
+    for i in 0..200 {
+        db.put(&format!("big{:03}", i), &big_value(i, 0)).unwrap();
+        db.put(&format!("small{:03}", i), "tiny").unwrap();
+    }
+    db.delete("big007").unwrap();
+    db.compact().unwrap();
+
+    let check = |db: &disk_sstables::Db| {
+        assert_eq!(db.get("big042").unwrap(), Some(big_value(42, 0)));
+        assert_eq!(db.get("small042").unwrap(), Some("tiny".to_string()));
+        assert_eq!(db.get("big007").unwrap(), None);
+        let records = db.scan().unwrap();
+        assert_eq!(records.len(), 399);
+        assert_eq!(records[0], ("big000".to_string(), big_value(0, 0)));
+    };
+    check(&db);
+    let stats = db.stats().unwrap();
+    assert!(stats.value_log.files > 1, "{:?}", stats.value_log);
+    // the tables only hold pointers to the big values
+    let table_bytes = stats.levels.iter().map(|l| l.bytes).sum::<u64>();
+    assert!(table_bytes < stats.value_log.bytes / 2, "{} {:?}", table_bytes, stats.value_log);
+    db.close().unwrap();
+
+    check(&open(&dir, separated()).unwrap());
+}
+
+#[test]
+fn value_log_garbage_is_collected() {
+    let dir = temp_dir("value_log_garbage_is_collected");
+    let db = open(&dir, separated()).unwrap();
+    for version in 0..3 {
+        for i in 0..100 {
+            db.put(&format!("k{:03}", i), &big_value(i, version)).unwrap();
+        }
+    }
+    for i in 0..10 {
+        db.delete(&format!("k{:03}", i)).unwrap();
+    }
+    // pad the memtable out so the last writes are in tables too
+    for i in 0..100 {
+        db.put(&format!("pad{:03}", i), "tiny").unwrap();
+    }
+    db.close().unwrap();
+
+    // a fresh start leaves every file collectable, none being appended to
+    let db = open(&dir, separated()).unwrap();
+    let before = db.stats().unwrap().value_log;
+    let mut freed = 0;
+    for _ in 0..before.files {
+        freed += db.collect_value_log_garbage().unwrap();
+    }
+    assert!(freed > 0);
+    // moved values are written out to a new file at the next flush
+    for i in 0..100 {
+        db.put(&format!("pad{:03}", i), "tiny").unwrap();
+    }
+    let after = db.stats().unwrap().value_log;
+    assert!(after.bytes < before.bytes / 2, "{:?} {:?}", before, after);
+
+    let check = |db: &disk_sstables::Db| {
+        for i in 0..100 {
+            let expected = if i < 10 { None } else { Some(big_value(i, 2)) };
+            assert_eq!(db.get(&format!("k{:03}", i)).unwrap(), expected);
+        }
+        assert_eq!(db.scan().unwrap().len(), 190);
+    };
+    check(&db);
+    db.close().unwrap();
+    check(&open(&dir, separated()).unwrap());
+}